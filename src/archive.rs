@@ -0,0 +1,521 @@
+// src/archive.rs - Pack a directory tree into one stream and unpack it safely
+use std::collections::HashSet;
+use std::fs::{self, File, FileTimes};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::config::OnConflict;
+use crate::error::{Context, Error, Result};
+use crate::file_utils::write_atomically_with;
+
+// Archive layout: `ARCHIVE_VERSION (u8)`, then entries, then ENTRY_END.
+//
+// Every entry is `kind (u8) || path_len (u16 LE) || path || mode (u32 LE) ||
+// mtime_secs (i64 LE) || mtime_nanos (u32 LE)`, and file entries add
+// `size (u64 LE) || contents`. Paths are relative, with `/` between
+// components. Directories always come before anything inside them.
+const ARCHIVE_VERSION: u8 = 1;
+
+const ENTRY_END: u8 = 0;
+const ENTRY_DIR: u8 = 1;
+const ENTRY_FILE: u8 = 2;
+
+// Archived paths are short; anything longer is corrupt or hostile
+const MAX_PATH_LEN: usize = 4096;
+
+/// What was packed into or unpacked from an archive
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ArchiveStats {
+    pub files: u64,
+    pub dirs: u64,
+    pub bytes: u64,
+    /// Files left alone because something was already at their path
+    pub skipped: u64,
+}
+
+/// Permissions and modification time kept for every entry
+struct EntryMeta {
+    /// Unix permission bits; on Windows only the read-only flag is kept
+    mode: u32,
+    mtime: SystemTime,
+}
+
+impl EntryMeta {
+    fn read_from(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)
+            .with_context(|| format!("Failed to read metadata for: {}", path.display()))?;
+
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.permissions().mode() & 0o7777
+        };
+        #[cfg(not(unix))]
+        let mode = if metadata.permissions().readonly() { 0o444 } else { 0o644 };
+
+        Ok(Self {
+            mode,
+            mtime: metadata.modified().unwrap_or(UNIX_EPOCH),
+        })
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let (secs, nanos) = encode_time(self.mtime);
+        out.extend_from_slice(&self.mode.to_le_bytes());
+        out.extend_from_slice(&secs.to_le_bytes());
+        out.extend_from_slice(&nanos.to_le_bytes());
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<Self> {
+        let mode = u32::from_le_bytes(read_array(reader)?);
+        let secs = i64::from_le_bytes(read_array(reader)?);
+        let nanos = u32::from_le_bytes(read_array(reader)?);
+        let mtime = decode_time(secs, nanos)
+            .ok_or_else(|| Error::UnsupportedFormat("Invalid modification time in archive".into()))?;
+        Ok(Self {
+            mode: mode & 0o7777,
+            mtime,
+        })
+    }
+
+    /// Apply the modification time and permissions to an extracted entry.
+    /// Setuid and setgid bits are not restored.
+    fn apply(&self, path: &Path) -> Result<()> {
+        // Directories can't be opened for writing, but a read handle is
+        // enough to set times on most platforms; where it isn't, the time
+        // is simply not restored
+        if let Ok(file) = File::open(path) {
+            let _ = file.set_times(FileTimes::new().set_modified(self.mtime));
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(self.mode & 0o1777))
+                .with_context(|| format!("Failed to set permissions on: {}", path.display()))?;
+        }
+        #[cfg(not(unix))]
+        {
+            let mut permissions = fs::metadata(path)?.permissions();
+            permissions.set_readonly(self.mode & 0o200 == 0);
+            fs::set_permissions(path, permissions)
+                .with_context(|| format!("Failed to set permissions on: {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Pack `entries` (all under `base`, as returned by
+/// `find_entries_recursive`) into `writer`, along with the directories that
+/// hold them. Directories are kept even when empty; symlinks and special
+/// files are skipped with a warning.
+pub fn pack<W: Write>(base: &Path, entries: &[PathBuf], mut writer: W) -> Result<ArchiveStats> {
+    let mut stats = ArchiveStats::default();
+    let mut written_dirs = HashSet::new();
+
+    writer.write_all(&[ARCHIVE_VERSION])?;
+
+    for file in entries {
+        let relative = file.strip_prefix(base)
+            .map_err(|_| Error::InvalidInput(format!("{} is not inside {}", file.display(), base.display())))?;
+        let file_type = fs::symlink_metadata(file)
+            .with_context(|| format!("Failed to read metadata for: {}", file.display()))?
+            .file_type();
+        if !file_type.is_file() && !file_type.is_dir() {
+            log::warn!("Skipping {}: symlinks and special files aren't archived", file.display());
+            continue;
+        }
+
+        // Emit each directory on the way, outermost first, the first time
+        // something inside it (or the directory itself) comes up
+        let mut dirs: Vec<&Path> = relative.ancestors()
+            .skip(if file_type.is_dir() { 0 } else { 1 })
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect();
+        dirs.reverse();
+        for dir in dirs {
+            if !written_dirs.insert(dir.to_path_buf()) {
+                continue;
+            }
+            let meta = EntryMeta::read_from(&base.join(dir))?;
+            write_entry_header(&mut writer, ENTRY_DIR, dir, &meta)?;
+            stats.dirs += 1;
+        }
+        if file_type.is_dir() {
+            continue;
+        }
+
+        let mut input = File::open(file)
+            .with_context(|| format!("Failed to open input file: {}", file.display()))?;
+        let meta = EntryMeta::read_from(file)?;
+        let size = input.metadata()?.len();
+
+        write_entry_header(&mut writer, ENTRY_FILE, relative, &meta)?;
+        writer.write_all(&size.to_le_bytes())?;
+
+        // Hold the file to the size we announced, even if it grows or
+        // shrinks while being read
+        let copied = io::copy(&mut (&mut input).take(size), &mut writer)
+            .with_context(|| format!("Failed to read input file: {}", file.display()))?;
+        if copied != size {
+            return Err(Error::InvalidInput(format!("File changed while it was being archived: {}", file.display())));
+        }
+
+        stats.files += 1;
+        stats.bytes += size;
+    }
+
+    writer.write_all(&[ENTRY_END])?;
+    writer.flush()?;
+    Ok(stats)
+}
+
+/// Unpack an archive from `reader` into the directory `dest`.
+///
+/// Entries must stay inside `dest`: absolute paths, `..` components and
+/// paths through symlinks are rejected. A file whose path is taken is
+/// handled as `on_conflict` says. On error, entries extracted so far are
+/// left in place.
+pub fn unpack<R: Read>(reader: R, dest: &Path, on_conflict: OnConflict) -> Result<ArchiveStats> {
+    let mut reader = BufReader::new(reader);
+    let mut stats = ArchiveStats::default();
+
+    let [version] = read_array(&mut reader)
+        .with_context(|| "Archive is empty")?;
+    if version != ARCHIVE_VERSION {
+        return Err(Error::UnsupportedFormat(format!("Unsupported archive version {}", version)));
+    }
+
+    fs::create_dir_all(dest)
+        .with_context(|| format!("Failed to create output directory: {}", dest.display()))?;
+
+    // Directory permissions and times are applied last, so read-only
+    // directories can still be filled and their times aren't bumped
+    let mut dirs = Vec::new();
+
+    loop {
+        let [kind] = read_array(&mut reader)
+            .with_context(|| "Archive is truncated")?;
+        if kind == ENTRY_END {
+            break;
+        }
+
+        let relative = read_entry_path(&mut reader)?;
+        let meta = EntryMeta::decode(&mut reader)?;
+        let target = safe_join(dest, &relative)?;
+
+        match kind {
+            ENTRY_DIR => {
+                match fs::create_dir(&target) {
+                    Ok(()) => {},
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists && target.is_dir() => {},
+                    Err(e) => {
+                        return Err(e).with_context(|| format!("Failed to create directory: {}", target.display()));
+                    }
+                }
+                dirs.push((target, meta));
+                stats.dirs += 1;
+            },
+            ENTRY_FILE => {
+                let size = u64::from_le_bytes(read_array(&mut reader)?);
+
+                // The contents go to a temporary file that is moved into
+                // place, which replaces a symlink planted at the target
+                // rather than following it
+                let written = if on_conflict == OnConflict::Skip && target.symlink_metadata().is_ok() {
+                    copy_contents(&mut reader, &mut io::sink(), size, &target)?;
+                    None
+                } else {
+                    let result = write_atomically_with(&target, on_conflict, |writer| {
+                        copy_contents(&mut reader, writer, size, &target)
+                    });
+                    match result {
+                        Ok((written, ())) => Some(written),
+                        Err(Error::OutputExists(_)) if on_conflict == OnConflict::Skip => None,
+                        Err(e) => return Err(e),
+                    }
+                };
+
+                match written {
+                    Some(written) => {
+                        meta.apply(&written)?;
+                        stats.files += 1;
+                        stats.bytes += size;
+                    },
+                    None => stats.skipped += 1,
+                }
+            },
+            other => return Err(Error::UnsupportedFormat(format!("Unknown archive entry type: {}", other))),
+        }
+    }
+
+    for (dir, meta) in dirs.iter().rev() {
+        meta.apply(dir)?;
+    }
+    Ok(stats)
+}
+
+/// Copy the `size` bytes of a file entry's contents to `writer`
+fn copy_contents<R: Read, W: Write>(reader: &mut R, writer: &mut W, size: u64, target: &Path) -> Result<()> {
+    let copied = io::copy(&mut reader.take(size), writer)
+        .with_context(|| format!("Failed to write output file: {}", target.display()))?;
+    if copied != size {
+        return Err(Error::UnsupportedFormat("Archive is truncated".into()));
+    }
+    Ok(())
+}
+
+fn write_entry_header<W: Write>(writer: &mut W, kind: u8, relative: &Path, meta: &EntryMeta) -> Result<()> {
+    let path = encode_path(relative)?;
+
+    let mut out = Vec::with_capacity(3 + path.len() + 16);
+    out.push(kind);
+    out.extend_from_slice(&(path.len() as u16).to_le_bytes());
+    out.extend_from_slice(&path);
+    meta.encode(&mut out);
+    writer.write_all(&out)?;
+    Ok(())
+}
+
+fn read_entry_path<R: Read>(reader: &mut R) -> Result<PathBuf> {
+    let len = u16::from_le_bytes(read_array(reader)?) as usize;
+    if len == 0 || len > MAX_PATH_LEN {
+        return Err(Error::UnsupportedFormat(format!("Invalid path length in archive: {}", len)));
+    }
+    let mut path = vec![0u8; len];
+    reader.read_exact(&mut path)
+        .with_context(|| "Archive is truncated")?;
+    decode_path(&path)
+}
+
+/// `relative` as `/`-separated bytes
+pub(crate) fn encode_path(relative: &Path) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for component in relative.components() {
+        let Component::Normal(name) = component else {
+            return Err(Error::InvalidInput(format!("Unexpected path component in: {}", relative.display())));
+        };
+        if !out.is_empty() {
+            out.push(b'/');
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            out.extend_from_slice(name.as_bytes());
+        }
+        #[cfg(not(unix))]
+        {
+            let name = name.to_str()
+                .ok_or_else(|| Error::InvalidInput(format!("File name is not valid Unicode: {}", relative.display())))?;
+            out.extend_from_slice(name.as_bytes());
+        }
+    }
+    if out.len() > MAX_PATH_LEN {
+        return Err(Error::InvalidInput(format!("Path too long to archive: {}", relative.display())));
+    }
+    Ok(out)
+}
+
+/// Parse a stored path, accepting only plain relative names: no root, drive
+/// prefix, `.` or `..`
+pub(crate) fn decode_path(bytes: &[u8]) -> Result<PathBuf> {
+    let mut path = PathBuf::new();
+    for part in bytes.split(|&b| b == b'/') {
+        #[cfg(unix)]
+        let name = {
+            use std::os::unix::ffi::OsStrExt;
+            std::ffi::OsStr::from_bytes(part)
+        };
+        #[cfg(not(unix))]
+        let name = std::ffi::OsStr::new(std::str::from_utf8(part)
+            .map_err(|_| Error::UnsupportedFormat("Archive path is not valid Unicode".into()))?);
+
+        // A single name must parse as exactly one normal component, which
+        // also catches `\\` and `C:` on Windows
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(normal)), None) if normal == name && !part.contains(&0) => {
+                path.push(name);
+            },
+            _ => {
+                return Err(Error::UnsupportedFormat(format!(
+                    "Refusing unsafe path in archive: {}", String::from_utf8_lossy(bytes)
+                )));
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// `dest/relative`, making sure no existing part of it is a symlink that
+/// could lead outside `dest`
+fn safe_join(dest: &Path, relative: &Path) -> Result<PathBuf> {
+    let mut path = dest.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(Error::InvalidInput(format!("Refusing to extract through a symlink: {}", path.display())));
+            },
+            _ => {},
+        }
+    }
+    Ok(path)
+}
+
+/// Seconds and nanoseconds since the Unix epoch, as stored in archives and
+/// headers. Times before 1970 round down to whole seconds plus a positive
+/// nanosecond part, like `timespec`.
+pub(crate) fn encode_time(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => (after.as_secs() as i64, after.subsec_nanos()),
+        Err(before) => {
+            let before = before.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                n => (-(before.as_secs() as i64) - 1, 1_000_000_000 - n),
+            }
+        }
+    }
+}
+
+/// The inverse of `encode_time`. Out-of-range times fall back to the
+/// epoch; `None` means the value can't have come from `encode_time`.
+pub(crate) fn decode_time(secs: i64, nanos: u32) -> Option<SystemTime> {
+    if nanos >= 1_000_000_000 {
+        return None;
+    }
+    let time = if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+            .and_then(|t| t.checked_add(Duration::from_nanos(nanos as u64)))
+    };
+    Some(time.unwrap_or(UNIX_EPOCH))
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)
+        .with_context(|| "Archive is truncated")?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_utils::find_entries_recursive;
+
+    #[test]
+    fn empty_directories_are_kept_and_symlinks_skipped() {
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir_all(src.path().join("full/empty")).unwrap();
+        fs::write(src.path().join("full/file.txt"), b"contents").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("full/file.txt", src.path().join("link")).unwrap();
+
+        let mut packed = Vec::new();
+        let stats = pack(src.path(), &find_entries_recursive(src.path()), &mut packed).unwrap();
+        assert_eq!((stats.files, stats.dirs), (1, 2));
+
+        let dest = tempfile::tempdir().unwrap();
+        unpack(&packed[..], dest.path(), OnConflict::Fail).unwrap();
+        assert!(dest.path().join("full/empty").is_dir());
+        assert_eq!(fs::read(dest.path().join("full/file.txt")).unwrap(), b"contents");
+        assert!(fs::symlink_metadata(dest.path().join("link")).is_err());
+    }
+
+    /// An archive holding one file stored under `path`, exactly as given
+    fn crafted(path: &[u8]) -> Vec<u8> {
+        let mut out = vec![ARCHIVE_VERSION, ENTRY_FILE];
+        out.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        out.extend_from_slice(path);
+        EntryMeta { mode: 0o644, mtime: UNIX_EPOCH }.encode(&mut out);
+        out.extend_from_slice(&4u64.to_le_bytes());
+        out.extend_from_slice(b"evil");
+        out.push(ENTRY_END);
+        out
+    }
+
+    #[test]
+    fn stored_paths_must_be_plain_relative_names() {
+        assert_eq!(decode_path(b"a/b.txt").unwrap(), Path::new("a").join("b.txt"));
+        for bad in [&b".."[..], b"../x", b"a/../../x", b"/etc/passwd", b".", b"a/./b", b"a//b", b"a/", b"", b"a\0b"] {
+            assert!(matches!(decode_path(bad), Err(Error::UnsupportedFormat(_))), "accepted {:?}", String::from_utf8_lossy(bad));
+        }
+
+        let relative = Path::new("dir").join("file.txt");
+        assert_eq!(encode_path(&relative).unwrap(), b"dir/file.txt");
+        assert_eq!(decode_path(&encode_path(&relative).unwrap()).unwrap(), relative);
+        assert!(encode_path(Path::new("../x")).is_err());
+        assert!(encode_path(&std::env::current_dir().unwrap()).is_err());
+    }
+
+    #[test]
+    fn existing_files_follow_the_conflict_policy() {
+        let root = tempfile::tempdir().unwrap();
+        let dest = root.path().join("dest");
+        let existing = dest.join("inside");
+        let reset = || {
+            let _ = fs::remove_dir_all(&dest);
+            fs::create_dir(&dest).unwrap();
+            fs::write(&existing, b"old").unwrap();
+        };
+
+        reset();
+        let result = unpack(&crafted(b"inside")[..], &dest, OnConflict::Fail);
+        assert!(matches!(result, Err(Error::OutputExists(path)) if path == existing));
+        assert_eq!(fs::read(&existing).unwrap(), b"old");
+
+        reset();
+        let stats = unpack(&crafted(b"inside")[..], &dest, OnConflict::Skip).unwrap();
+        assert_eq!((stats.files, stats.skipped), (0, 1));
+        assert_eq!(fs::read(&existing).unwrap(), b"old");
+
+        reset();
+        let stats = unpack(&crafted(b"inside")[..], &dest, OnConflict::Overwrite).unwrap();
+        assert_eq!((stats.files, stats.skipped), (1, 0));
+        assert_eq!(fs::read(&existing).unwrap(), b"evil");
+
+        reset();
+        unpack(&crafted(b"inside")[..], &dest, OnConflict::Rename).unwrap();
+        assert_eq!(fs::read(&existing).unwrap(), b"old");
+        assert_eq!(fs::read(dest.join("inside-1")).unwrap(), b"evil");
+
+        // Not even overwriting goes through a planted symlink
+        #[cfg(unix)]
+        {
+            reset();
+            let outside = root.path().join("outside");
+            fs::write(&outside, b"outside").unwrap();
+            fs::remove_file(&existing).unwrap();
+            std::os::unix::fs::symlink(&outside, &existing).unwrap();
+            let result = unpack(&crafted(b"inside")[..], &dest, OnConflict::Overwrite);
+            assert!(matches!(result, Err(Error::InvalidInput(_))));
+            assert_eq!(fs::read(&outside).unwrap(), b"outside");
+        }
+    }
+
+    #[test]
+    fn unpacking_never_writes_outside_the_destination() {
+        let root = tempfile::tempdir().unwrap();
+        let dest = root.path().join("dest");
+
+        assert!(matches!(unpack(&crafted(b"../escaped")[..], &dest, OnConflict::Fail), Err(Error::UnsupportedFormat(_))));
+        assert!(!root.path().join("escaped").exists());
+
+        unpack(&crafted(b"inside")[..], &dest, OnConflict::Fail).unwrap();
+        assert_eq!(fs::read(dest.join("inside")).unwrap(), b"evil");
+
+        // A symlink already in the destination mustn't be followed
+        #[cfg(unix)]
+        {
+            let outside = root.path().join("outside");
+            fs::create_dir(&outside).unwrap();
+            std::os::unix::fs::symlink(&outside, dest.join("link")).unwrap();
+            assert!(safe_join(&dest, Path::new("link/escaped")).is_err());
+            assert!(matches!(unpack(&crafted(b"link/escaped")[..], &dest, OnConflict::Fail), Err(Error::InvalidInput(_))));
+            assert!(!outside.join("escaped").exists());
+        }
+    }
+}
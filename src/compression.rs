@@ -0,0 +1,72 @@
+// src/compression.rs - Optional deflate compression of payloads before encryption
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use crate::error::Result;
+use flate2::write::DeflateEncoder;
+
+use crate::container::Compression;
+
+/// Bytes of plaintext looked at to decide whether compression pays off
+pub const SAMPLE_LEN: usize = 64 * 1024;
+
+// Compress only if the sample shrinks to this share of its size or less.
+// Already compressed or encrypted data (media, archives, .enc files) doesn't,
+// and compressing it would only cost time.
+const MAX_RATIO_PERCENT: usize = 90;
+
+/// Pick `Deflate` if the start of the data compresses well, `None` otherwise
+pub fn choose(sample: &[u8]) -> Compression {
+    if sample.is_empty() {
+        return Compression::None;
+    }
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+    let compressed = encoder.write_all(sample)
+        .and_then(|_| encoder.finish())
+        .map(|out| out.len())
+        .unwrap_or(usize::MAX);
+
+    if compressed.saturating_mul(100) <= sample.len() * MAX_RATIO_PERCENT {
+        Compression::Deflate
+    } else {
+        Compression::None
+    }
+}
+
+/// Up to `SAMPLE_LEN` bytes from the start of `files`, read in order, to
+/// judge a whole archive by
+pub fn sample_files(files: &[PathBuf]) -> Vec<u8> {
+    let mut sample = Vec::new();
+    for file in files {
+        let remaining = SAMPLE_LEN - sample.len();
+        if remaining == 0 {
+            break;
+        }
+        if let Ok(file) = File::open(file) {
+            let _ = file.take(remaining as u64).read_to_end(&mut sample);
+        }
+    }
+    sample
+}
+
+/// Run `write` against `inner`, through a deflate encoder if `compression`
+/// asks for one, and hand `inner` back with the result
+pub fn compress_into<W, T, F>(inner: W, compression: Compression, write: F) -> Result<(W, T)>
+where
+    W: Write,
+    F: FnOnce(&mut dyn Write) -> Result<T>,
+{
+    match compression {
+        Compression::None => {
+            let mut inner = inner;
+            let result = write(&mut inner)?;
+            Ok((inner, result))
+        },
+        Compression::Deflate => {
+            let mut encoder = DeflateEncoder::new(inner, flate2::Compression::default());
+            let result = write(&mut encoder)?;
+            Ok((encoder.finish()?, result))
+        }
+    }
+}
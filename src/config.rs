@@ -1,127 +1,535 @@
-use std::path::PathBuf;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
+use crate::crypto::{CryptoMode, Kdf};
+use crate::error::{Context, Error, Result};
+use crate::secure_wipe::{Wipe, WipeMethod};
 
-/// Configuration for processing files
+/// Configuration for processing files.
+///
+/// The lasting settings can be kept in a config file (see `Config::load`);
+/// keys it leaves out keep their defaults. The per-run ones (`output_path`,
+/// `recursive`, `force` and `secure_delete`) are never read from it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     /// Output directory path (if None, use the same directory as input)
+    #[serde(skip)]
     pub output_path: Option<PathBuf>,
     
     /// Whether to process directories recursively
+    #[serde(skip)]
     pub recursive: bool,
     
-    /// Whether to overwrite existing files without prompting
+    /// Whether to overwrite existing files without prompting; overrides
+    /// `on_conflict`
+    #[serde(skip)]
     pub force: bool,
     
+    /// Cipher for newly encrypted files
+    pub mode: CryptoMode,
+    
+    /// Key derivation, with its cost parameters, for new passphrases
+    pub kdf: Kdf,
+    
+    /// What to do when an output file already exists
+    pub on_conflict: OnConflict,
+    
+    /// How encrypted outputs are named (`{name}.enc` if unset)
+    pub name_template: Option<NameTemplate>,
+    
     /// Whether to securely delete original files after processing
+    #[serde(skip)]
     pub secure_delete: bool,
     
-    /// Number of passes for secure deletion
+    /// How files are overwritten before secure deletion
+    pub wipe_method: WipeMethod,
+    
+    /// Number of passes for secure deletion with `WipeMethod::Random`
     pub shred_passes: u8,
     
     /// Whether to clean up empty folders after secure deletion
     pub clean_empty_folders: bool,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            output_path: None,
+            recursive: false,
+            force: false,
+            mode: CryptoMode::default(),
+            kdf: Kdf::default(),
+            on_conflict: OnConflict::default(),
+            name_template: None,
+            secure_delete: false,
+            wipe_method: WipeMethod::default(),
+            shred_passes: 3,
+            clean_empty_folders: false,
+        }
+    }
+}
+
+/// What to do when an output file already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Stop with `Error::OutputExists`
+    #[default]
+    Fail,
+    /// Leave the existing file alone; the library reports
+    /// `Error::OutputExists` and batch runs count the file as skipped
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Write next to it with a numeric suffix: `report-1.pdf`, `report-2.pdf`, ...
+    Rename,
+}
+
+impl std::str::FromStr for OnConflict {
+    type Err = Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "fail" => Ok(OnConflict::Fail),
+            "skip" => Ok(OnConflict::Skip),
+            "overwrite" => Ok(OnConflict::Overwrite),
+            "rename" => Ok(OnConflict::Rename),
+            other => Err(Error::InvalidInput(format!(
+                "Unknown conflict policy '{}'; use fail, skip, overwrite or rename", other
+            ))),
+        }
+    }
+}
+
+impl OnConflict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OnConflict::Fail => "fail",
+            OnConflict::Skip => "skip",
+            OnConflict::Overwrite => "overwrite",
+            OnConflict::Rename => "rename",
+        }
+    }
+    
+    /// Fail with `Error::OutputExists` before doing any work when `path`
+    /// already exists and the policy is to fail or skip. This only saves
+    /// work: the binding check is made as the output is moved into place,
+    /// by `file_utils::write_atomically_with`.
+    pub fn check(self, path: &Path) -> Result<()> {
+        // A dangling symlink is in the way too
+        match self {
+            OnConflict::Fail | OnConflict::Skip if path.symlink_metadata().is_ok() => {
+                Err(Error::OutputExists(path.to_path_buf()))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// `dir/name-n.ext`, or `dir/name-n` for names without an extension
+pub(crate) fn numbered_path(path: &Path, n: u32) -> PathBuf {
+    let mut numbered = path.file_stem().unwrap_or_default().to_os_string();
+    numbered.push(format!("-{}", n));
+    if let Some(ext) = path.extension() {
+        numbered.push(".");
+        numbered.push(ext);
+    }
+    path.with_file_name(numbered)
+}
+
+/// Where the config file is looked for: `~/.config/file-utils-i` on Linux,
+/// `~/Library/Application Support/file-utils-i` on macOS and
+/// `%APPDATA%\file-utils-i\config` on Windows
+pub fn config_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "file-utils-i").map(|dirs| dirs.config_dir().to_path_buf())
+}
+
 impl Config {
-    /// Get effective output path for a given input path
-    pub fn get_output_path(&self, input_path: &PathBuf, is_encrypting: bool) -> PathBuf {
+    /// The config file in use: `config.toml` in `config_dir()`, or
+    /// `config.json` when there is no TOML file
+    pub fn find_file() -> Option<PathBuf> {
+        let dir = config_dir()?;
+        ["config.toml", "config.json"].into_iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+    }
+    
+    /// Settings from the config file, or the defaults when there is none
+    pub fn load() -> Result<Self> {
+        match Self::find_file() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+    
+    /// Settings from a config file, read as JSON for `.json` files and as
+    /// TOML otherwise. Unknown keys are rejected so typos don't go unnoticed.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let invalid = |reason: String| Error::InvalidInput(format!("Invalid config file {}: {}", path.display(), reason));
+        
+        let config: Self = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?
+        } else {
+            toml::from_str(&text).map_err(|e| invalid(e.to_string()))?
+        };
+        // Files written with a KDF cost the reader refuses couldn't be opened
+        config.kdf.check().map_err(|e| invalid(e.to_string()))?;
+        if config.shred_passes == 0 {
+            return Err(invalid("shred_passes must be at least 1".into()));
+        }
+        Ok(config)
+    }
+    
+    /// How to overwrite files before secure deletion
+    pub fn wipe(&self) -> Wipe {
+        Wipe { method: self.wipe_method, passes: self.shred_passes }
+    }
+    
+    /// The conflict policy to apply, with `force` meaning overwrite
+    pub fn conflict_policy(&self) -> OnConflict {
+        if self.force {
+            OnConflict::Overwrite
+        } else {
+            self.on_conflict
+        }
+    }
+    
+    /// Get effective output path for a given input path.
+    ///
+    /// An `output_path` that is an existing directory receives the output
+    /// under its derived name; any other `output_path` is used as is.
+    pub fn get_output_path(&self, input_path: &Path, operation: Operation) -> PathBuf {
+        let file_name = input_path.file_name().unwrap_or_default();
+        
         match &self.output_path {
-            Some(output_dir) => {
-                if output_dir.is_dir() {
-                    // If output is a directory, keep the filename but in the output directory
-                    let filename = input_path.file_name()
-                        .unwrap_or_default();
-                    
-                    let mut new_path = output_dir.clone();
-                    
-                    // Add appropriate extension
-                    if is_encrypting {
-                        // For encryption, append .enc
-                        let mut new_filename = filename.to_os_string();
-                        new_filename.push(".enc");
-                        new_path.push(new_filename);
-                    } else {
-                        // For decryption, remove .enc extension if present
-                        let filename_str = filename.to_string_lossy();
-                        if filename_str.ends_with(".enc") {
-                            let original_name = &filename_str[..filename_str.len() - 4];
-                            new_path.push(original_name);
-                        } else {
-                            // If no .enc extension, keep as is
-                            new_path.push(filename);
-                        }
-                    }
-                    
-                    new_path
-                } else {
-                    // If output path is specified as a file, use it directly
-                    output_dir.clone()
-                }
+            Some(output_dir) if output_dir.is_dir() => {
+                output_dir.join(self.output_file_name(file_name, operation))
             },
-            None => {
-                // No output path, use input directory
-                let mut new_path = input_path.clone();
-                
-                if is_encrypting {
-                    // For encryption, append .enc
-                    new_path.set_extension("enc");
-                } else {
-                    // For decryption, remove .enc extension
-                    if let Some(ext) = new_path.extension() {
-                        if ext == "enc" {
-                            // Remove the .enc extension
-                            new_path.set_extension("");
-                        }
-                    }
-                }
-                
-                new_path
-            }
+            Some(output_file) => output_file.clone(),
+            None => input_path.with_file_name(self.output_file_name(file_name, operation)),
         }
     }
     
-    /// Get the relative path for a file within a directory structure
+    /// Get the output path for a file within a directory structure,
+    /// mirroring its place under `base_path` in `output_path` (or keeping
+    /// it next to the input without one)
     pub fn get_relative_output_path(
         &self, 
-        file_path: &PathBuf, 
-        base_path: &PathBuf, 
-        is_encrypting: bool
+        file_path: &Path, 
+        base_path: &Path, 
+        operation: Operation
     ) -> PathBuf {
         // Get relative path from base directory
         let relative_path = file_path.strip_prefix(base_path)
-            .unwrap_or(file_path.as_path());
+            .unwrap_or(file_path);
         
-        match &self.output_path {
-            Some(output_dir) => {
-                let mut new_path = output_dir.clone();
-                new_path.push(relative_path);
-                
-                // Add appropriate extension
-                if is_encrypting {
-                    new_path.set_extension("enc");
-                } else if let Some(ext) = new_path.extension() {
-                    if ext == "enc" {
-                        new_path.set_extension("");
-                    }
-                }
-                
-                new_path
+        let new_path = match &self.output_path {
+            Some(output_dir) => output_dir.join(relative_path),
+            None => file_path.to_path_buf(),
+        };
+        
+        let file_name = new_path.file_name().unwrap_or_default();
+        new_path.with_file_name(self.output_file_name(file_name, operation))
+    }
+    
+    /// The output name for a file called `file_name`: the name template
+    /// (by default `{name}.enc`) when encrypting, and the name minus exactly
+    /// one `.enc` when decrypting. Names without `.enc` get `.decrypted`
+    /// added instead, so the output never lands on the input.
+    pub fn output_file_name(&self, file_name: &OsStr, operation: Operation) -> OsString {
+        match operation {
+            Operation::Encrypt(mode) => {
+                self.name_template.as_ref()
+                    .unwrap_or(&NameTemplate::default())
+                    .render(file_name, mode)
             },
-            None => {
-                // No output path, use input directory structure
-                let mut new_path = file_path.clone();
-                
-                if is_encrypting {
-                    new_path.set_extension("enc");
-                } else if let Some(ext) = new_path.extension() {
-                    if ext == "enc" {
-                        new_path.set_extension("");
+            Operation::Decrypt => {
+                let path = Path::new(file_name);
+                match (path.extension(), path.file_stem()) {
+                    (Some(ext), Some(stem)) if ext == "enc" => stem.to_os_string(),
+                    _ => {
+                        let mut name = file_name.to_os_string();
+                        name.push(".decrypted");
+                        name
                     }
                 }
-                
-                new_path
             }
         }
     }
 }
+
+/// What a run does to each file, which decides how its output is named
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    /// Encrypt with this cipher, which `{mode}` in a name template stands for
+    Encrypt(CryptoMode),
+    Decrypt,
+}
+
+/// How encrypted files are named, such as `{stem}.{ext}.{mode}.enc`.
+///
+/// `{name}` is the whole file name, `{stem}` and `{ext}` are the parts
+/// before and after its last dot, and `{mode}` is the cipher. For names
+/// without an extension, `.{ext}` disappears along with its dot. Templates
+/// must end in `.enc` and can't contain path separators.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NameTemplate {
+    template: String,
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Name,
+    Stem,
+    Ext,
+    Mode,
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        "{name}.enc".parse().expect("default name template is valid")
+    }
+}
+
+impl std::str::FromStr for NameTemplate {
+    type Err = Error;
+    
+    fn from_str(template: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidInput(format!("Invalid name template '{}': {}", template, reason));
+        
+        if !template.ends_with(".enc") {
+            return Err(invalid("it must end in .enc"));
+        }
+        if template.contains(['/', '\\']) {
+            return Err(invalid("it can't contain path separators"));
+        }
+        
+        let mut pieces = Vec::new();
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                pieces.push(Piece::Literal(rest[..open].to_string()));
+            }
+            let close = rest[open..].find('}')
+                .ok_or_else(|| invalid("unclosed {"))?;
+            pieces.push(match &rest[open + 1..open + close] {
+                "name" => Piece::Name,
+                "stem" => Piece::Stem,
+                "ext" => Piece::Ext,
+                "mode" => Piece::Mode,
+                other => return Err(invalid(&format!("unknown placeholder {{{}}}; use {{name}}, {{stem}}, {{ext}} or {{mode}}", other))),
+            });
+            rest = &rest[open + close + 1..];
+        }
+        if rest.contains('}') {
+            return Err(invalid("unmatched }"));
+        }
+        pieces.push(Piece::Literal(rest.to_string()));
+        
+        // Without the file name, every file would get the same output name
+        if !pieces.iter().any(|piece| matches!(piece, Piece::Name | Piece::Stem)) {
+            return Err(invalid("it needs {name} or {stem}"));
+        }
+        
+        Ok(Self { template: template.to_string(), pieces })
+    }
+}
+
+impl TryFrom<String> for NameTemplate {
+    type Error = Error;
+    
+    fn try_from(template: String) -> Result<Self> {
+        template.parse()
+    }
+}
+
+impl From<NameTemplate> for String {
+    fn from(template: NameTemplate) -> Self {
+        template.template
+    }
+}
+
+impl NameTemplate {
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+    
+    /// The encrypted name for a file called `file_name`
+    pub fn render(&self, file_name: &OsStr, mode: CryptoMode) -> OsString {
+        let path = Path::new(file_name);
+        let stem = path.file_stem().unwrap_or(file_name);
+        let ext = path.extension().unwrap_or_default();
+        
+        let mut out = OsString::new();
+        for (i, piece) in self.pieces.iter().enumerate() {
+            match piece {
+                Piece::Literal(text) => match self.pieces.get(i + 1) {
+                    Some(Piece::Ext) if ext.is_empty() => out.push(text.strip_suffix('.').unwrap_or(text)),
+                    _ => out.push(text),
+                },
+                Piece::Name => out.push(file_name),
+                Piece::Stem => out.push(stem),
+                Piece::Ext => out.push(ext),
+                Piece::Mode => out.push(mode.as_str()),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypted_name(config: &Config, name: &str) -> OsString {
+        config.output_file_name(OsStr::new(name), Operation::Encrypt(CryptoMode::Aes256Gcm))
+    }
+
+    fn decrypted_name(name: &str) -> OsString {
+        Config::default().output_file_name(OsStr::new(name), Operation::Decrypt)
+    }
+
+    fn templated(template: &str) -> Config {
+        Config { name_template: Some(template.parse().unwrap()), ..Config::default() }
+    }
+
+    #[test]
+    fn encrypt_appends_enc_and_decrypt_strips_it() {
+        let config = Config::default();
+        for name in ["report.tar.gz", ".bashrc", "Makefile", "notes.enc.txt", "archive.enc"] {
+            let encrypted = encrypted_name(&config, name);
+            assert_eq!(encrypted, OsString::from(format!("{}.enc", name)));
+            assert_eq!(decrypted_name(encrypted.to_str().unwrap()), OsStr::new(name));
+        }
+    }
+
+    #[test]
+    fn decrypt_without_enc_suffix_adds_decrypted() {
+        assert_eq!(decrypted_name("report.tar.gz"), OsStr::new("report.tar.gz.decrypted"));
+        assert_eq!(decrypted_name(".enc"), OsStr::new(".enc.decrypted"));
+        assert_eq!(decrypted_name("Makefile"), OsStr::new("Makefile.decrypted"));
+    }
+
+    #[test]
+    fn template_placeholders() {
+        let config = templated("{stem}.{ext}.{mode}.enc");
+        assert_eq!(encrypted_name(&config, "report.tar.gz"), OsStr::new("report.tar.gz.aes-gcm.enc"));
+        assert_eq!(encrypted_name(&config, "Makefile"), OsStr::new("Makefile.aes-gcm.enc"));
+        assert_eq!(encrypted_name(&config, ".bashrc"), OsStr::new(".bashrc.aes-gcm.enc"));
+
+        let config = templated("{name}-{mode}.enc");
+        assert_eq!(encrypted_name(&config, "photo.jpg"), OsStr::new("photo.jpg-aes-gcm.enc"));
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        for template in ["{name}", "{name}.bin", "dir/{name}.enc", "{stem}\\x.enc", "{mode}.enc",
+                         "{nam}.enc", "{name.enc", "{name}}.enc"] {
+            assert!(template.parse::<NameTemplate>().is_err(), "{} was accepted", template);
+        }
+        assert_eq!(NameTemplate::default().as_str(), "{name}.enc");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_survive() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        let encrypted = templated("{stem}.{ext}.{mode}.enc")
+            .output_file_name(name, Operation::Encrypt(CryptoMode::Aes256Gcm));
+        assert_eq!(encrypted.as_bytes(), b"caf\xe9.txt.aes-gcm.enc");
+
+        let encrypted = Config::default().output_file_name(name, Operation::Encrypt(CryptoMode::Aes256Gcm));
+        let decrypted = Config::default().output_file_name(&encrypted, Operation::Decrypt);
+        assert_eq!(decrypted.into_vec(), b"caf\xe9.txt");
+
+        let numbered = numbered_path(Path::new(OsStr::from_bytes(b"out/caf\xe9.txt.enc")), 1);
+        assert_eq!(numbered.as_os_str().as_bytes(), b"out/caf\xe9.txt-1.enc");
+    }
+
+    #[test]
+    fn numbered_paths() {
+        assert_eq!(numbered_path(Path::new("out/report.tar.gz.enc"), 2), PathBuf::from("out/report.tar.gz-2.enc"));
+        assert_eq!(numbered_path(Path::new(".bashrc"), 1), PathBuf::from(".bashrc-1"));
+        assert_eq!(numbered_path(Path::new("Makefile"), 1), PathBuf::from("Makefile-1"));
+    }
+
+    #[test]
+    fn output_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        // An existing directory receives the output under its derived name
+        let config = Config { output_path: Some(dir.to_path_buf()), ..Config::default() };
+        assert_eq!(config.get_output_path(Path::new("in/data.csv"), Operation::Encrypt(CryptoMode::Aes256Gcm)),
+                   dir.join("data.csv.enc"));
+        assert_eq!(config.get_output_path(Path::new("in/data.csv.enc"), Operation::Decrypt),
+                   dir.join("data.csv"));
+
+        // Anything else is the output file itself
+        let config = Config { output_path: Some(PathBuf::from("out.bin")), ..Config::default() };
+        assert_eq!(config.get_output_path(Path::new("data.csv"), Operation::Decrypt), PathBuf::from("out.bin"));
+
+        // Without one, the output sits next to the input
+        assert_eq!(Config::default().get_output_path(Path::new("in/data.csv"), Operation::Encrypt(CryptoMode::Aes256Gcm)),
+                   PathBuf::from("in/data.csv.enc"));
+    }
+
+    fn load_text(file_name: &str, text: &str) -> Result<Config> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(file_name);
+        std::fs::write(&path, text).unwrap();
+        Config::load_from(&path)
+    }
+
+    #[test]
+    fn config_files_override_the_defaults() {
+        let config = load_text("config.toml", r#"
+            mode = "xchacha"
+            wipe_method = "dod"
+            name_template = "{stem}.{ext}.{mode}.enc"
+
+            [kdf]
+            algorithm = "pbkdf2-sha256"
+            iterations = 900000
+        "#).unwrap();
+        assert!(matches!(config.mode, CryptoMode::XChaCha20Poly1305));
+        assert_eq!(config.kdf, Kdf::Pbkdf2Sha256 { iterations: 900_000 });
+        assert_eq!(config.wipe(), Wipe { method: WipeMethod::Dod, passes: 3 });
+        assert_eq!(config.name_template.unwrap().as_str(), "{stem}.{ext}.{mode}.enc");
+        assert_eq!(config.on_conflict, OnConflict::Fail);
+
+        let config = load_text("config.json", r#"{"shred_passes": 7, "clean_empty_folders": true}"#).unwrap();
+        assert_eq!(config.wipe(), Wipe { method: WipeMethod::Random, passes: 7 });
+        assert!(config.clean_empty_folders);
+        assert_eq!(config.kdf, Kdf::default());
+    }
+
+    #[test]
+    fn invalid_config_files_are_rejected() {
+        for text in ["mdoe = \"aes\"", "output_path = \"out\"", "shred_passes = 0", "mode = \"rot13\"",
+                     "name_template = \"{name}\"", "[kdf]\nalgorithm = \"argon2id\"\nmemory_kib = 1\niterations = 1\nparallelism = 1"] {
+            assert!(matches!(load_text("config.toml", text), Err(Error::InvalidInput(_))), "{} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn relative_output_paths_mirror_the_tree() {
+        let config = Config { output_path: Some(PathBuf::from("out")), ..Config::default() };
+        assert_eq!(config.get_relative_output_path(Path::new("src/a/b.txt"), Path::new("src"),
+                                                   Operation::Encrypt(CryptoMode::XChaCha20Poly1305)),
+                   PathBuf::from("out/a/b.txt.enc"));
+        assert_eq!(Config::default().get_relative_output_path(Path::new("src/a/b.txt.enc"), Path::new("src"),
+                                                              Operation::Decrypt),
+                   PathBuf::from("src/a/b.txt"));
+    }
+}
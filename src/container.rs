@@ -0,0 +1,774 @@
+// src/container.rs - Self-describing header for .enc files
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use crate::error::{Context, Error, Result};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::Serialize;
+
+use crate::crypto::{CryptoMode, Kdf, KDF_BLOCK_LEN, SALT_LEN};
+
+/// Magic bytes at the start of every container file
+pub const MAGIC: &[u8; 6] = b"FUIENC";
+
+/// Current container format version
+///
+/// Version 1 encrypted the whole payload in one AEAD call. Version 2 splits
+/// it into fixed-size chunks (see `crypto::EncryptingWriter`).
+pub const FORMAT_VERSION: u8 = 2;
+
+/// Plaintext bytes per chunk for newly written files
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+
+// Bounds on chunk sizes we accept when reading
+const MIN_CHUNK_SIZE: u32 = 1024;
+const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+// Headers are small; anything bigger than this is corrupt or hostile
+const MAX_BODY_LEN: u32 = 64 * 1024;
+
+// Header record tags. Every record is `tag (u8) || len (u16 LE) || value`.
+const TAG_CIPHER: u8 = 1;
+const TAG_KDF: u8 = 2;
+const TAG_NONCE: u8 = 3;
+const TAG_CHUNK_SIZE: u8 = 4;
+const TAG_RECIPIENT: u8 = 5;
+const TAG_PAYLOAD: u8 = 6;
+const TAG_NAME: u8 = 7;
+const TAG_COMPRESSION: u8 = 8;
+const TAG_METADATA: u8 = 9;
+
+// Cipher ids stored in the TAG_CIPHER record
+const CIPHER_AES_256_GCM: u8 = 1;
+const CIPHER_XCHACHA20_POLY1305: u8 = 2;
+
+// Payload kinds stored in the TAG_PAYLOAD record. Plain files omit the
+// record, so they read the same as files written before archives existed.
+const PAYLOAD_ARCHIVE: u8 = 1;
+
+// Algorithms stored in the TAG_COMPRESSION record, which is omitted for
+// uncompressed payloads
+const COMPRESSION_DEFLATE: u8 = 1;
+
+// Stanza types stored in the first byte of a TAG_RECIPIENT record
+const STANZA_X25519: u8 = 1;
+const STANZA_PASSPHRASE: u8 = 2;
+
+/// Most recipient stanzas a header may hold
+pub const MAX_STANZAS: usize = 64;
+
+/// Most passphrase stanzas a header may hold. Trying a passphrase runs the
+/// KDF once per stanza, so this bounds the work a crafted header can cause.
+pub const MAX_PASSPHRASE_STANZAS: usize = 8;
+
+/// Length of a file key wrapped with XChaCha20-Poly1305 (key + tag)
+pub const WRAPPED_KEY_LEN: usize = 32 + 16;
+
+/// A copy of the file key, wrapped for one recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stanza {
+    /// Wrapped with a key agreed between an ephemeral X25519 key and the
+    /// recipient's public key (see `keys::X25519Recipient`)
+    X25519 {
+        ephemeral: [u8; 32],
+        wrapped: [u8; WRAPPED_KEY_LEN],
+    },
+    /// Wrapped with a key derived from a passphrase
+    Passphrase {
+        kdf: Kdf,
+        salt: [u8; SALT_LEN],
+        wrapped: [u8; WRAPPED_KEY_LEN],
+    },
+}
+
+impl Stanza {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Stanza::X25519 { .. } => "x25519",
+            Stanza::Passphrase { .. } => "passphrase",
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Stanza::X25519 { ephemeral, wrapped } => {
+                let mut out = Vec::with_capacity(1 + 32 + WRAPPED_KEY_LEN);
+                out.push(STANZA_X25519);
+                out.extend_from_slice(ephemeral);
+                out.extend_from_slice(wrapped);
+                out
+            },
+            Stanza::Passphrase { kdf, salt, wrapped } => {
+                let mut out = Vec::with_capacity(1 + KDF_BLOCK_LEN + WRAPPED_KEY_LEN);
+                out.push(STANZA_PASSPHRASE);
+                out.extend_from_slice(&kdf.encode(salt));
+                out.extend_from_slice(wrapped);
+                out
+            }
+        }
+    }
+
+    fn decode(value: &[u8]) -> Result<Self> {
+        match value.first() {
+            Some(&STANZA_X25519) if value.len() == 1 + 32 + WRAPPED_KEY_LEN => {
+                Ok(Stanza::X25519 {
+                    ephemeral: value[1..33].try_into().unwrap(),
+                    wrapped: value[33..].try_into().unwrap(),
+                })
+            },
+            Some(&STANZA_X25519) => Err(Error::UnsupportedFormat("Invalid X25519 recipient record".into())),
+            Some(&STANZA_PASSPHRASE) if value.len() == 1 + KDF_BLOCK_LEN + WRAPPED_KEY_LEN => {
+                let (kdf, salt) = Kdf::decode(&value[1..1 + KDF_BLOCK_LEN])?;
+                Ok(Stanza::Passphrase {
+                    kdf,
+                    salt,
+                    wrapped: value[1 + KDF_BLOCK_LEN..].try_into().unwrap(),
+                })
+            },
+            Some(&STANZA_PASSPHRASE) => Err(Error::UnsupportedFormat("Invalid passphrase recipient record".into())),
+            Some(other) => Err(Error::UnsupportedFormat(format!("Unknown recipient type: {}", other))),
+            None => Err(Error::UnsupportedFormat("Empty recipient record".into())),
+        }
+    }
+}
+
+/// What the decrypted payload holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Payload {
+    /// The contents of a single file
+    File,
+    /// A directory tree packed by `archive::pack`
+    Archive,
+}
+
+impl Payload {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Payload::File => "file",
+            Payload::Archive => "archive",
+        }
+    }
+}
+
+/// How the plaintext was compressed before encryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    /// Raw deflate (RFC 1951)
+    Deflate,
+}
+
+impl Compression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Deflate => "deflate",
+        }
+    }
+}
+
+/// Where the payload key comes from
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    /// Derived directly from a passphrase (files written before recipient
+    /// stanzas; read-only)
+    Passphrase { kdf: Kdf, salt: [u8; SALT_LEN] },
+    /// A random file key, wrapped once per passphrase or public key
+    Recipients(Vec<Stanza>),
+}
+
+/// Parsed container header
+///
+/// On disk: `MAGIC || version (u8) || body_len (u32 LE) || body`, where the
+/// body is a list of tagged records. The canonical encoding of the header,
+/// minus recipient stanzas, is passed to the cipher as associated data, so
+/// any edit to it makes decryption fail. Stanzas are protected by their own
+/// AEAD tag instead, which keeps them replaceable without touching the
+/// payload.
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub version: u8,
+    pub mode: CryptoMode,
+    pub key_source: KeySource,
+    /// Full nonce (v1) or base nonce whose last 5 bytes are replaced by the
+    /// chunk counter and final-chunk flag (v2)
+    pub nonce: Vec<u8>,
+    /// Plaintext bytes per chunk; `None` for single-shot v1 files
+    pub chunk_size: Option<u32>,
+    pub payload: Payload,
+    /// The original file name, encrypted under the file key (see
+    /// `names::seal_name`), when the file was stored under an opaque name
+    pub sealed_name: Option<Vec<u8>>,
+    pub compression: Compression,
+    /// Permissions, owner, timestamps and extended attributes of the
+    /// original file, encrypted under the file key (see
+    /// `metadata::seal_metadata`)
+    pub sealed_metadata: Option<Vec<u8>>,
+}
+
+impl Header {
+    /// Create a header for a new file with a fresh random nonce
+    pub fn new(mode: CryptoMode, key_source: KeySource) -> Result<Self> {
+        let nonce_len = nonce_len(mode)?;
+
+        let mut nonce = vec![0u8; nonce_len];
+        OsRng.fill_bytes(&mut nonce);
+
+        Ok(Self {
+            version: FORMAT_VERSION,
+            mode,
+            key_source,
+            nonce,
+            chunk_size: Some(DEFAULT_CHUNK_SIZE),
+            payload: Payload::File,
+            sealed_name: None,
+            compression: Compression::None,
+            sealed_metadata: None,
+        })
+    }
+
+    /// Serialize the header, including magic bytes and version. Fails if a
+    /// record or the whole header is too large to be read back.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.encode(true)
+    }
+
+    /// Associated data that binds the ciphertext to this header
+    pub fn aad(&self) -> Result<Vec<u8>> {
+        self.encode(false)
+    }
+
+    fn encode(&self, with_stanzas: bool) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        push_record(&mut body, TAG_CIPHER, &[cipher_id(self.mode)?])?;
+        match &self.key_source {
+            KeySource::Passphrase { kdf, salt } => {
+                push_record(&mut body, TAG_KDF, &kdf.encode(salt))?;
+            },
+            KeySource::Recipients(stanzas) if with_stanzas => {
+                for stanza in stanzas {
+                    push_record(&mut body, TAG_RECIPIENT, &stanza.encode())?;
+                }
+            },
+            KeySource::Recipients(_) => {},
+        }
+        push_record(&mut body, TAG_NONCE, &self.nonce)?;
+        if let Some(chunk_size) = self.chunk_size {
+            push_record(&mut body, TAG_CHUNK_SIZE, &chunk_size.to_le_bytes())?;
+        }
+        if self.payload == Payload::Archive {
+            push_record(&mut body, TAG_PAYLOAD, &[PAYLOAD_ARCHIVE])?;
+        }
+        if let Some(sealed_name) = &self.sealed_name {
+            push_record(&mut body, TAG_NAME, sealed_name)?;
+        }
+        if self.compression == Compression::Deflate {
+            push_record(&mut body, TAG_COMPRESSION, &[COMPRESSION_DEFLATE])?;
+        }
+        if let Some(sealed_metadata) = &self.sealed_metadata {
+            push_record(&mut body, TAG_METADATA, sealed_metadata)?;
+        }
+
+        if body.len() > MAX_BODY_LEN as usize {
+            return Err(Error::InvalidInput(format!("Container header too large ({} bytes)", body.len())));
+        }
+
+        let mut out = Vec::with_capacity(MAGIC.len() + 5 + body.len());
+        out.extend_from_slice(MAGIC);
+        out.push(self.version);
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(&body);
+        Ok(out)
+    }
+
+    /// Read and validate a header from the start of a stream
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0u8; 6];
+        reader.read_exact(&mut magic)
+            .with_context(|| "File too short for a container header")?;
+        if &magic != MAGIC {
+            return Err(Error::UnsupportedFormat("Not an encrypted container (bad magic bytes)".into()));
+        }
+
+        let mut fixed = [0u8; 5];
+        reader.read_exact(&mut fixed)
+            .with_context(|| "Truncated container header")?;
+        let version = fixed[0];
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(Error::UnsupportedFormat(format!(
+                "Unsupported container version {} (this build reads version {})",
+                version, FORMAT_VERSION
+            )));
+        }
+
+        let body_len = u32::from_le_bytes(fixed[1..5].try_into().unwrap());
+        if body_len > MAX_BODY_LEN {
+            return Err(Error::UnsupportedFormat(format!("Container header too large ({} bytes)", body_len)));
+        }
+
+        let mut body = vec![0u8; body_len as usize];
+        reader.read_exact(&mut body)
+            .with_context(|| "Truncated container header")?;
+
+        Self::parse_body(version, &body)
+    }
+
+    /// Parse a header from the start of a buffer, returning it and its length
+    pub fn parse(data: &[u8]) -> Result<(Self, usize)> {
+        let mut cursor = Cursor::new(data);
+        let header = Self::read_from(&mut cursor)?;
+        Ok((header, cursor.position() as usize))
+    }
+
+    fn parse_body(version: u8, body: &[u8]) -> Result<Self> {
+        let mut mode = None;
+        let mut kdf = None;
+        let mut stanzas = Vec::new();
+        let mut nonce = None;
+        let mut chunk_size = None;
+        let mut payload = Payload::File;
+        let mut sealed_name = None;
+        let mut compression = Compression::None;
+        let mut sealed_metadata = None;
+
+        let mut pos = 0;
+        while pos < body.len() {
+            if body.len() - pos < 3 {
+                return Err(Error::UnsupportedFormat("Malformed container header record".into()));
+            }
+            let tag = body[pos];
+            let len = u16::from_le_bytes([body[pos + 1], body[pos + 2]]) as usize;
+            pos += 3;
+            if body.len() - pos < len {
+                return Err(Error::UnsupportedFormat("Malformed container header record".into()));
+            }
+            let value = &body[pos..pos + len];
+            pos += len;
+
+            match tag {
+                TAG_CIPHER => {
+                    if len != 1 {
+                        return Err(Error::UnsupportedFormat("Invalid cipher record".into()));
+                    }
+                    mode = Some(mode_from_cipher_id(value[0])?);
+                },
+                TAG_KDF => {
+                    if len != KDF_BLOCK_LEN {
+                        return Err(Error::UnsupportedFormat("Invalid KDF record".into()));
+                    }
+                    kdf = Some(Kdf::decode(value)?);
+                },
+                TAG_RECIPIENT => {
+                    if stanzas.len() == MAX_STANZAS {
+                        return Err(Error::UnsupportedFormat(format!(
+                            "Container header has more than {} recipients", MAX_STANZAS
+                        )));
+                    }
+                    stanzas.push(Stanza::decode(value)?);
+                },
+                TAG_NONCE => nonce = Some(value.to_vec()),
+                TAG_CHUNK_SIZE => {
+                    let bytes: [u8; 4] = value.try_into()
+                        .map_err(|_| Error::UnsupportedFormat("Invalid chunk size record".into()))?;
+                    chunk_size = Some(u32::from_le_bytes(bytes));
+                },
+                TAG_PAYLOAD => {
+                    payload = match value {
+                        [PAYLOAD_ARCHIVE] => Payload::Archive,
+                        _ => return Err(Error::UnsupportedFormat("Unknown payload record in container header".into())),
+                    };
+                },
+                TAG_NAME => sealed_name = Some(value.to_vec()),
+                TAG_COMPRESSION => {
+                    compression = match value {
+                        [COMPRESSION_DEFLATE] => Compression::Deflate,
+                        _ => return Err(Error::UnsupportedFormat("Unknown compression in container header".into())),
+                    };
+                },
+                TAG_METADATA => sealed_metadata = Some(value.to_vec()),
+                other => {
+                    return Err(Error::UnsupportedFormat(format!("Unknown container header record: {}", other)));
+                }
+            }
+        }
+
+        let mode = mode.ok_or_else(|| Error::UnsupportedFormat("Container header has no cipher".into()))?;
+        if passphrase_stanzas(&stanzas) > MAX_PASSPHRASE_STANZAS {
+            return Err(Error::UnsupportedFormat(format!(
+                "Container header has more than {} passphrase recipients", MAX_PASSPHRASE_STANZAS
+            )));
+        }
+        let key_source = match (kdf, stanzas.is_empty()) {
+            (Some((kdf, salt)), true) => KeySource::Passphrase { kdf, salt },
+            (None, false) if version >= 2 => KeySource::Recipients(stanzas),
+            (Some(_), false) => {
+                return Err(Error::UnsupportedFormat("Container header has both a KDF and recipients".into()));
+            },
+            _ => return Err(Error::UnsupportedFormat("Container header has no key derivation or recipients".into())),
+        };
+        let nonce = nonce.ok_or_else(|| Error::UnsupportedFormat("Container header has no nonce".into()))?;
+
+        if nonce.len() != nonce_len(mode)? {
+            return Err(Error::UnsupportedFormat(format!("Invalid nonce length for {}", mode.as_str())));
+        }
+
+        match (version, chunk_size) {
+            (1, None) => {},
+            (1, Some(_)) => return Err(Error::UnsupportedFormat("Version 1 container with a chunk size".into())),
+            (_, None) => return Err(Error::UnsupportedFormat("Container header has no chunk size".into())),
+            (_, Some(size)) if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&size) => {
+                return Err(Error::UnsupportedFormat(format!("Unsupported chunk size: {} bytes", size)));
+            },
+            (_, Some(_)) => {},
+        }
+        if version < 2
+            && (payload != Payload::File || sealed_name.is_some() || compression != Compression::None || sealed_metadata.is_some())
+        {
+            return Err(Error::UnsupportedFormat("Version 1 container with a payload, name, compression or metadata record".into()));
+        }
+
+        Ok(Self {
+            version,
+            mode,
+            key_source,
+            nonce,
+            chunk_size,
+            payload,
+            sealed_name,
+            compression,
+            sealed_metadata,
+        })
+    }
+}
+
+/// Key-free description of an encrypted file, as reported by `inspect`
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerInfo {
+    /// `container`, `legacy-aead`, `legacy-quantum`, `legacy-aes-ctr` or `unknown`
+    pub format: &'static str,
+    pub version: Option<u8>,
+    pub cipher: Option<&'static str>,
+    pub kdf: Option<Kdf>,
+    /// Each wrapped copy of the file key, in header order
+    pub recipients: Vec<StanzaInfo>,
+    pub chunk_size: Option<u32>,
+    /// `file` or `archive`; `None` for legacy files
+    pub payload: Option<Payload>,
+    /// Whether the original file name is stored (encrypted) in the header
+    pub name_hidden: bool,
+    pub compression: Option<Compression>,
+    /// Whether permissions, owner and timestamps are stored (encrypted) in
+    /// the header
+    pub metadata_stored: bool,
+    /// Plaintext size, derived from the ciphertext layout (unknown for
+    /// compressed files)
+    pub original_size: Option<u64>,
+    pub file_size: u64,
+    pub note: Option<String>,
+}
+
+/// Key-free description of one recipient stanza
+#[derive(Debug, Clone, Serialize)]
+pub struct StanzaInfo {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub kdf: Option<Kdf>,
+}
+
+impl From<&Stanza> for StanzaInfo {
+    fn from(stanza: &Stanza) -> Self {
+        let kdf = match stanza {
+            Stanza::Passphrase { kdf, .. } => Some(*kdf),
+            Stanza::X25519 { .. } => None,
+        };
+        Self { kind: stanza.kind(), kdf }
+    }
+}
+
+/// Describe an encrypted file without needing its key.
+///
+/// Containers are parsed exactly. Headerless legacy files are recognised
+/// on a best-effort basis from their layout.
+pub fn inspect_file(path: &Path) -> Result<ContainerInfo> {
+    let mut file = File::open(path)
+        .with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let file_size = file.metadata()?.len();
+
+    let mut prefix = Vec::with_capacity(KDF_BLOCK_LEN);
+    file.by_ref().take(KDF_BLOCK_LEN as u64).read_to_end(&mut prefix)?;
+
+    let mut info = ContainerInfo {
+        format: "unknown",
+        version: None,
+        cipher: None,
+        kdf: None,
+        recipients: Vec::new(),
+        chunk_size: None,
+        payload: None,
+        name_hidden: false,
+        compression: None,
+        metadata_stored: false,
+        original_size: None,
+        file_size,
+        note: None,
+    };
+
+    if has_magic(&prefix) {
+        let mut reader = Cursor::new(prefix).chain(file);
+        let header = Header::read_from(&mut reader)?;
+        let body_len = file_size.saturating_sub(header.to_bytes()?.len() as u64);
+
+        info.format = "container";
+        info.version = Some(header.version);
+        info.cipher = Some(header.mode.as_str());
+        match &header.key_source {
+            KeySource::Passphrase { kdf, .. } => info.kdf = Some(*kdf),
+            KeySource::Recipients(stanzas) => {
+                info.recipients = stanzas.iter().map(StanzaInfo::from).collect();
+            }
+        }
+        info.chunk_size = header.chunk_size;
+        info.payload = Some(header.payload);
+        info.name_hidden = header.sealed_name.is_some();
+        info.compression = Some(header.compression);
+        info.metadata_stored = header.sealed_metadata.is_some();
+        let payload_size = plaintext_size(body_len, header.chunk_size);
+        if payload_size.is_none() {
+            info.note = Some("payload is truncated".to_string());
+        }
+        if header.compression == Compression::None {
+            info.original_size = payload_size;
+        }
+        return Ok(info);
+    }
+
+    // Quantum files start with the plaintext length, which always matches
+    // the rest of the file
+    if prefix.len() >= 8 {
+        let stored_len = u64::from_le_bytes(prefix[..8].try_into().unwrap());
+        if stored_len == file_size - 8 {
+            info.format = "legacy-quantum";
+            info.cipher = Some(CryptoMode::Quantum.as_str());
+            info.original_size = Some(stored_len);
+            info.note = Some("pre-container RKState layout; decrypt with -m quantum".to_string());
+            return Ok(info);
+        }
+    }
+
+    // Early aes-gcm/xchacha files began with a bare KDF block
+    if prefix.len() == KDF_BLOCK_LEN {
+        if let Ok((kdf, _)) = Kdf::decode(&prefix) {
+            info.format = "legacy-aead";
+            info.kdf = Some(kdf);
+            info.note = Some("pre-container KDF block layout; decrypt with -m aes-gcm or -m xchacha".to_string());
+            return Ok(info);
+        }
+    }
+
+    // Anything else with room for an IV is most likely an AES-CTR file,
+    // but random bytes look exactly the same
+    if file_size >= 16 {
+        info.format = "legacy-aes-ctr";
+        info.cipher = Some(CryptoMode::Aes256Ctr.as_str());
+        info.original_size = Some(file_size - 16);
+        info.note = Some("no header; assumed AES-256-CTR (IV || ciphertext), which is unauthenticated".to_string());
+    } else {
+        info.note = Some("too short to be an encrypted file".to_string());
+    }
+
+    Ok(info)
+}
+
+/// Plaintext bytes in a payload of `body_len` bytes, or `None` if truncated
+fn plaintext_size(body_len: u64, chunk_size: Option<u32>) -> Option<u64> {
+    const TAG_LEN: u64 = 16;
+    match chunk_size {
+        None => body_len.checked_sub(TAG_LEN),
+        Some(chunk_size) => {
+            // Every chunk carries a tag, and even an empty file has one chunk
+            let sealed = chunk_size as u64 + TAG_LEN;
+            let chunks = body_len.div_ceil(sealed).max(1);
+            body_len.checked_sub(chunks * TAG_LEN)
+        }
+    }
+}
+
+/// Check whether a buffer starts with the container magic bytes
+pub fn has_magic(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Nonce length used by each containerised cipher
+pub fn nonce_len(mode: CryptoMode) -> Result<usize> {
+    match mode {
+        CryptoMode::Aes256Gcm => Ok(12),
+        CryptoMode::XChaCha20Poly1305 => Ok(24),
+        CryptoMode::Aes256Ctr | CryptoMode::Quantum => Err(Error::InvalidInput(format!(
+            "{} mode has no container format", mode.as_str()
+        ))),
+    }
+}
+
+/// How many of `stanzas` are opened with a passphrase
+pub fn passphrase_stanzas(stanzas: &[Stanza]) -> usize {
+    stanzas.iter().filter(|stanza| matches!(stanza, Stanza::Passphrase { .. })).count()
+}
+
+fn cipher_id(mode: CryptoMode) -> Result<u8> {
+    match mode {
+        CryptoMode::Aes256Gcm => Ok(CIPHER_AES_256_GCM),
+        CryptoMode::XChaCha20Poly1305 => Ok(CIPHER_XCHACHA20_POLY1305),
+        // Header::new refuses these, but `mode` is a public field
+        CryptoMode::Aes256Ctr | CryptoMode::Quantum => Err(Error::InvalidInput(format!(
+            "{} mode has no container format", mode.as_str()
+        ))),
+    }
+}
+
+fn mode_from_cipher_id(id: u8) -> Result<CryptoMode> {
+    match id {
+        CIPHER_AES_256_GCM => Ok(CryptoMode::Aes256Gcm),
+        CIPHER_XCHACHA20_POLY1305 => Ok(CryptoMode::XChaCha20Poly1305),
+        other => Err(Error::UnsupportedFormat(format!("Unknown cipher id in container header: {}", other))),
+    }
+}
+
+fn push_record(body: &mut Vec<u8>, tag: u8, value: &[u8]) -> Result<()> {
+    let len = u16::try_from(value.len())
+        .map_err(|_| Error::InvalidInput(format!("Header record {} too large ({} bytes)", tag, value.len())))?;
+    body.push(tag);
+    body.extend_from_slice(&len.to_le_bytes());
+    body.extend_from_slice(value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A header using every optional record
+    fn full_header() -> Header {
+        let stanza = Stanza::X25519 { ephemeral: [9u8; 32], wrapped: [1u8; WRAPPED_KEY_LEN] };
+        let mut header = Header::new(CryptoMode::XChaCha20Poly1305, KeySource::Recipients(vec![stanza])).unwrap();
+        header.payload = Payload::Archive;
+        header.sealed_name = Some(vec![2u8; 40]);
+        header.compression = Compression::Deflate;
+        header.sealed_metadata = Some(vec![3u8; 60]);
+        header
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        let bytes = full_header().to_bytes().unwrap();
+        let mut with_payload = bytes.clone();
+        with_payload.extend_from_slice(b"payload");
+
+        let (header, len) = Header::parse(&with_payload).unwrap();
+        assert_eq!(len, bytes.len());
+        assert_eq!(header.to_bytes().unwrap(), bytes);
+        assert!(matches!(header.mode, CryptoMode::XChaCha20Poly1305));
+        assert_eq!(header.payload, Payload::Archive);
+        assert_eq!(header.compression, Compression::Deflate);
+        assert_eq!(header.chunk_size, Some(DEFAULT_CHUNK_SIZE));
+    }
+
+    #[test]
+    fn aad_leaves_out_the_recipients() {
+        let mut header = full_header();
+        let aad = header.aad().unwrap();
+        header.key_source = KeySource::Recipients(Vec::new());
+        assert_eq!(header.aad().unwrap(), aad);
+
+        header.sealed_name = None;
+        assert_ne!(header.aad().unwrap(), aad);
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let bytes = full_header().to_bytes().unwrap();
+        let corrupt = |offset: usize, value: u8| {
+            let mut bytes = bytes.clone();
+            bytes[offset] = value;
+            Header::parse(&bytes)
+        };
+
+        // Magic, version, body length, then the first record's tag and length
+        assert!(matches!(corrupt(0, b'X'), Err(Error::UnsupportedFormat(_))));
+        assert!(matches!(corrupt(6, 0), Err(Error::UnsupportedFormat(_))));
+        assert!(matches!(corrupt(6, FORMAT_VERSION + 1), Err(Error::UnsupportedFormat(_))));
+        assert!(matches!(corrupt(10, 0xff), Err(Error::UnsupportedFormat(_))));
+        assert!(matches!(corrupt(11, 200), Err(Error::UnsupportedFormat(_))));
+        assert!(matches!(corrupt(13, 0xff), Err(Error::UnsupportedFormat(_))));
+        assert!(matches!(corrupt(12, 2), Err(Error::UnsupportedFormat(_))));
+
+        for len in 0..bytes.len() {
+            assert!(Header::parse(&bytes[..len]).is_err(), "accepted a header cut to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn inspect_describes_containers_without_a_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.enc");
+        let mut bytes = full_header().to_bytes().unwrap();
+        bytes.extend_from_slice(&[0u8; 100]);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let info = inspect_file(&path).unwrap();
+        assert_eq!(info.format, "container");
+        assert_eq!(info.version, Some(FORMAT_VERSION));
+        assert_eq!(info.cipher, Some("xchacha"));
+        assert_eq!(info.recipients.len(), 1);
+        assert_eq!(info.payload, Some(Payload::Archive));
+        assert!(info.name_hidden && info.metadata_stored);
+        assert_eq!(info.file_size, bytes.len() as u64);
+
+        std::fs::write(&path, b"not a container").unwrap();
+        assert_ne!(inspect_file(&path).unwrap().format, "container");
+    }
+
+    #[test]
+    fn oversized_records_are_refused() {
+        let mut header = Header::new(CryptoMode::Aes256Gcm, KeySource::Recipients(Vec::new())).unwrap();
+        header.sealed_name = Some(vec![0u8; u16::MAX as usize + 1]);
+        assert!(matches!(header.to_bytes(), Err(Error::InvalidInput(_))));
+
+        // Records that each fit can still add up to more than a reader takes
+        header.sealed_name = Some(vec![0u8; u16::MAX as usize]);
+        header.sealed_metadata = Some(vec![0u8; u16::MAX as usize]);
+        assert!(matches!(header.to_bytes(), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn stanza_counts_are_capped() {
+        let passphrase = Stanza::Passphrase {
+            kdf: Kdf::Pbkdf2Sha256 { iterations: 1 },
+            salt: [0u8; SALT_LEN],
+            wrapped: [0u8; WRAPPED_KEY_LEN],
+        };
+        let x25519 = Stanza::X25519 { ephemeral: [9u8; 32], wrapped: [0u8; WRAPPED_KEY_LEN] };
+        let parse = |stanzas: Vec<Stanza>| {
+            let header = Header::new(CryptoMode::Aes256Gcm, KeySource::Recipients(stanzas)).unwrap();
+            Header::read_from(&mut &header.to_bytes().unwrap()[..])
+        };
+
+        assert!(parse(vec![passphrase.clone(); MAX_PASSPHRASE_STANZAS]).is_ok());
+        assert!(matches!(parse(vec![passphrase.clone(); MAX_PASSPHRASE_STANZAS + 1]), Err(Error::UnsupportedFormat(_))));
+        assert!(parse(vec![x25519.clone(); MAX_STANZAS]).is_ok());
+        assert!(matches!(parse(vec![x25519; MAX_STANZAS + 1]), Err(Error::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn legacy_modes_have_no_header() {
+        for mode in [CryptoMode::Aes256Ctr, CryptoMode::Quantum] {
+            assert!(matches!(Header::new(mode, KeySource::Recipients(Vec::new())), Err(Error::InvalidInput(_))));
+
+            let mut header = Header::new(CryptoMode::Aes256Gcm, KeySource::Recipients(Vec::new())).unwrap();
+            header.mode = mode;
+            assert!(matches!(header.to_bytes(), Err(Error::InvalidInput(_))));
+        }
+    }
+}
@@ -1,18 +1,43 @@
 use aes::Aes256;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
 use ctr::cipher::{KeyIvInit, StreamCipher};
+use flate2::read::DeflateDecoder;
+use hkdf::Hkdf;
 use rand::rngs::OsRng;
 use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
-use anyhow::{Context, Result};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use crate::error::{Context, Error, Result};
 use std::env;
+use zeroize::Zeroizing;
+
+use crate::archive::{self, ArchiveStats};
+use crate::compression;
+use crate::config::OnConflict;
+use crate::container::{self, Header, KeySource, Stanza};
+use crate::file_utils::{temp_path_for, write_atomically_with};
+use crate::keys::{Identity, Recipient};
+use crate::metadata::{self, FileMetadata, RestoreMetadata};
+use crate::names;
+use crate::parallel;
 
 type Aes256Ctr = ctr::Ctr128BE<Aes256>;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum CryptoMode {
+    #[serde(rename = "aes", alias = "aes-ctr")]
     Aes256Ctr,
+    #[serde(rename = "aes-gcm", alias = "gcm")]
+    Aes256Gcm,
+    #[serde(rename = "xchacha", alias = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+    #[serde(rename = "quantum")]
     Quantum,
 }
 
@@ -22,23 +47,32 @@ impl Default for CryptoMode {
         match env::var("CRYPTO_MODE").as_deref() {
             Ok("quantum") => CryptoMode::Quantum,
             Ok("aes") => CryptoMode::Aes256Ctr,
-            _ => CryptoMode::Aes256Ctr, // Default to AES
+            Ok("aes-gcm") => CryptoMode::Aes256Gcm,
+            Ok("xchacha") => CryptoMode::XChaCha20Poly1305,
+            _ => CryptoMode::Aes256Gcm, // Default to authenticated AES
         }
     }
 }
 
 impl CryptoMode {
-    pub fn from_str(s: &str) -> Self {
+    /// Look a mode up by name
+    pub fn from_name(s: &str) -> Result<Self> {
         match s.to_lowercase().as_str() {
-            "quantum" => CryptoMode::Quantum,
-            "aes" | "aes256" | "aes-ctr" => CryptoMode::Aes256Ctr,
-            _ => CryptoMode::default(),
+            "quantum" => Ok(CryptoMode::Quantum),
+            "aes" | "aes256" | "aes-ctr" => Ok(CryptoMode::Aes256Ctr),
+            "aes-gcm" | "gcm" | "aes256-gcm" => Ok(CryptoMode::Aes256Gcm),
+            "xchacha" | "xchacha20" | "xchacha20-poly1305" => Ok(CryptoMode::XChaCha20Poly1305),
+            other => Err(Error::InvalidInput(format!(
+                "Unknown mode: {} (expected aes-gcm, xchacha, aes or quantum)", other
+            ))),
         }
     }
     
     pub fn as_str(&self) -> &'static str {
         match self {
             CryptoMode::Aes256Ctr => "aes",
+            CryptoMode::Aes256Gcm => "aes-gcm",
+            CryptoMode::XChaCha20Poly1305 => "xchacha",
             CryptoMode::Quantum => "quantum",
         }
     }
@@ -56,9 +90,275 @@ pub fn decrypt_aes_ctr256(data: &[u8], key: &[u8; 32], iv: &[u8; 16]) -> Vec<u8>
     encrypt_aes_ctr256(data, key, iv)
 }
 
+pub fn encrypt_aes_gcm256(data: &[u8], key: &[u8; 32], nonce: &[u8; 12]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(key.into());
+    cipher.encrypt(Nonce::from_slice(nonce), data)
+        .map_err(|_| Error::InvalidInput("AES-GCM encryption failed".into()))
+}
+
+pub fn decrypt_aes_gcm256(data: &[u8], key: &[u8; 32], nonce: &[u8; 12]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(key.into());
+    cipher.decrypt(Nonce::from_slice(nonce), data)
+        .map_err(|_| authentication_failed(false))
+}
+
+pub fn encrypt_xchacha20(data: &[u8], key: &[u8; 32], nonce: &[u8; 24]) -> Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    cipher.encrypt(XNonce::from_slice(nonce), data)
+        .map_err(|_| Error::InvalidInput("XChaCha20-Poly1305 encryption failed".into()))
+}
+
+pub fn decrypt_xchacha20(data: &[u8], key: &[u8; 32], nonce: &[u8; 24]) -> Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    cipher.decrypt(XNonce::from_slice(nonce), data)
+        .map_err(|_| authentication_failed(false))
+}
+
+/// Encrypt with one of the AEAD modes, authenticating `aad` alongside the data
+pub(crate) fn aead_seal(mode: CryptoMode, key: &[u8; 32], nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let payload = Payload { msg, aad };
+    match mode {
+        CryptoMode::Aes256Gcm => Aes256Gcm::new(key.into())
+            .encrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| Error::InvalidInput("AES-GCM encryption failed".into())),
+        CryptoMode::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into())
+            .encrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| Error::InvalidInput("XChaCha20-Poly1305 encryption failed".into())),
+        CryptoMode::Aes256Ctr | CryptoMode::Quantum => {
+            Err(Error::InvalidInput(format!("{} is not an authenticated mode", mode.as_str())))
+        }
+    }
+}
+
+/// The error for a tag that doesn't verify. A tag check can't tell a wrong
+/// key from modified data, so it depends on what came before: until
+/// something has been authenticated with `key` (a recipient stanza or an
+/// earlier chunk) and no plaintext has been released, the key is taken to
+/// be wrong; after that, the data was modified.
+fn authentication_failed(key_proven: bool) -> Error {
+    if key_proven {
+        Error::Tampered("Authentication failed: the data was modified".into())
+    } else {
+        Error::WrongKey
+    }
+}
+
+/// Decrypt with one of the AEAD modes, failing if the data or `aad` were
+/// modified. `key_proven` picks the error, see `authentication_failed`.
+pub(crate) fn aead_open(mode: CryptoMode, key: &[u8; 32], nonce: &[u8], ciphertext: &[u8], aad: &[u8], key_proven: bool) -> Result<Vec<u8>> {
+    let payload = Payload { msg: ciphertext, aad };
+    let result = match mode {
+        CryptoMode::Aes256Gcm => Aes256Gcm::new(key.into())
+            .decrypt(Nonce::from_slice(nonce), payload),
+        CryptoMode::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into())
+            .decrypt(XNonce::from_slice(nonce), payload),
+        CryptoMode::Aes256Ctr | CryptoMode::Quantum => {
+            return Err(Error::InvalidInput(format!("{} is not an authenticated mode", mode.as_str())));
+        }
+    };
+    result.map_err(|_| authentication_failed(key_proven))
+}
+
+/// HKDF-SHA256 of `ikm` into a 32-byte key for `info`
+pub(crate) fn hkdf_sha256(salt: Option<&[u8]>, ikm: &[u8], info: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(salt, ikm)
+        .expand(info, &mut key[..])
+        .map_err(|_| Error::InvalidInput("HKDF expansion failed".into()))?;
+    Ok(key)
+}
+
+/// Encrypt a header record (the file name, the metadata) under a key
+/// derived from `file_key` for `info`.
+///
+/// Each file key seals at most one record per `info`, so every subkey is
+/// used once and a fixed all-zero nonce is safe.
+pub(crate) fn seal_with_subkey(mode: CryptoMode, file_key: &[u8; 32], info: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let key = hkdf_sha256(None, file_key, info)?;
+    let nonce = vec![0u8; container::nonce_len(mode)?];
+    aead_seal(mode, &key, &nonce, plaintext, &[])
+}
+
+/// Decrypt a record sealed by `seal_with_subkey`
+pub(crate) fn open_with_subkey(mode: CryptoMode, file_key: &[u8; 32], info: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+    let key = hkdf_sha256(None, file_key, info)?;
+    let nonce = vec![0u8; container::nonce_len(mode)?];
+    aead_open(mode, &key, &nonce, sealed, &[], true)
+}
+
+/// Stretch or truncate a user supplied key to 32 bytes for the legacy AES-CTR mode
+fn pad_key(key: &[u8]) -> [u8; 32] {
+    let mut k = [0u8; 32];
+    let key_len = key.len().min(32);
+    k[..key_len].copy_from_slice(&key[..key_len]);
+    
+    // If key is shorter than 32 bytes, pad with deterministic data
+    if key_len > 0 && key_len < 32 {
+        for i in key_len..32 {
+            k[i] = key[i % key_len].wrapping_add(i as u8);
+        }
+    }
+    
+    k
+}
+
+// Password-based key derivation for the authenticated modes
+pub(crate) const SALT_LEN: usize = 16;
+// kdf id (1) + three u32 parameters (12) + salt (16)
+pub(crate) const KDF_BLOCK_LEN: usize = 1 + 12 + SALT_LEN;
+
+const KDF_ID_PBKDF2_SHA256: u8 = 1;
+const KDF_ID_ARGON2ID: u8 = 2;
+
+// Upper bounds on parameters read back from a file, so a crafted file can't
+// make us allocate gigabytes or spin for hours before the tag check fails
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+const MAX_ARGON2_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+const MAX_ARGON2_ITERATIONS: u32 = 64;
+const MAX_ARGON2_PARALLELISM: u32 = 16;
+
+/// Password-based key derivation function and its cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "kebab-case")]
+pub enum Kdf {
+    Pbkdf2Sha256 { iterations: u32 },
+    Argon2id { memory_kib: u32, iterations: u32, parallelism: u32 },
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Kdf::argon2id()
+    }
+}
+
+impl std::str::FromStr for Kdf {
+    type Err = Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "argon2" | "argon2id" => Ok(Kdf::argon2id()),
+            "pbkdf2" | "pbkdf2-sha256" => Ok(Kdf::pbkdf2()),
+            other => Err(Error::InvalidInput(format!("Unknown KDF: {} (expected argon2id or pbkdf2)", other))),
+        }
+    }
+}
+
+impl Kdf {
+    /// PBKDF2-HMAC-SHA256 with the OWASP recommended iteration count
+    pub fn pbkdf2() -> Self {
+        Kdf::Pbkdf2Sha256 { iterations: 600_000 }
+    }
+    
+    /// Argon2id with the OWASP recommended memory and time cost
+    pub fn argon2id() -> Self {
+        Kdf::Argon2id { memory_kib: 19 * 1024, iterations: 2, parallelism: 1 }
+    }
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kdf::Pbkdf2Sha256 { .. } => "pbkdf2",
+            Kdf::Argon2id { .. } => "argon2id",
+        }
+    }
+    
+    /// Human-readable name and cost parameters
+    pub fn describe(&self) -> String {
+        match *self {
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                format!("pbkdf2-sha256 ({} iterations)", iterations)
+            },
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                format!("argon2id (m={} KiB, t={}, p={})", memory_kib, iterations, parallelism)
+            }
+        }
+    }
+    
+    /// Derive a 256-bit cipher key from a passphrase and salt
+    pub fn derive_key(&self, passphrase: &[u8], salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+        let mut key = Zeroizing::new([0u8; 32]);
+        
+        match *self {
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                pbkdf2::pbkdf2_hmac::<Sha256>(passphrase, salt, iterations, &mut key[..]);
+            },
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                let params = argon2::Params::new(memory_kib, iterations, parallelism, Some(32))
+                    .map_err(|e| Error::UnsupportedFormat(format!("Invalid Argon2 parameters: {}", e)))?;
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(passphrase, salt, &mut key[..])
+                    .map_err(|e| Error::InvalidInput(format!("Argon2 key derivation failed: {}", e)))?;
+            }
+        }
+        
+        Ok(key)
+    }
+    
+    /// Serialize the KDF id, parameters and salt into a fixed-size block
+    pub(crate) fn encode(&self, salt: &[u8; SALT_LEN]) -> [u8; KDF_BLOCK_LEN] {
+        let (id, params) = match *self {
+            Kdf::Pbkdf2Sha256 { iterations } => (KDF_ID_PBKDF2_SHA256, [iterations, 0, 0]),
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                (KDF_ID_ARGON2ID, [memory_kib, iterations, parallelism])
+            }
+        };
+        
+        let mut block = [0u8; KDF_BLOCK_LEN];
+        block[0] = id;
+        for (i, param) in params.iter().enumerate() {
+            block[1 + i * 4..5 + i * 4].copy_from_slice(&param.to_le_bytes());
+        }
+        block[13..].copy_from_slice(salt);
+        block
+    }
+    
+    /// Reject parameters that are invalid, or so costly that a file using
+    /// them looks like an attack on whoever decrypts it
+    pub fn check(&self) -> Result<()> {
+        match *self {
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                if iterations == 0 || iterations > MAX_PBKDF2_ITERATIONS {
+                    return Err(Error::UnsupportedFormat(format!("Unsupported PBKDF2 iteration count: {}", iterations)));
+                }
+            },
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                if memory_kib > MAX_ARGON2_MEMORY_KIB
+                    || iterations > MAX_ARGON2_ITERATIONS
+                    || parallelism > MAX_ARGON2_PARALLELISM
+                    || argon2::Params::new(memory_kib, iterations, parallelism, Some(32)).is_err()
+                {
+                    return Err(Error::UnsupportedFormat(format!(
+                        "Unsupported Argon2 parameters: m={} KiB, t={}, p={}",
+                        memory_kib, iterations, parallelism
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// Parse a block written by `encode`, rejecting unknown or absurd parameters
+    pub(crate) fn decode(block: &[u8]) -> Result<(Self, [u8; SALT_LEN])> {
+        if block.len() != KDF_BLOCK_LEN {
+            return Err(Error::UnsupportedFormat("Invalid KDF block length".into()));
+        }
+        
+        let param = |i: usize| u32::from_le_bytes(block[1 + i * 4..5 + i * 4].try_into().unwrap());
+        let kdf = match block[0] {
+            KDF_ID_PBKDF2_SHA256 => Kdf::Pbkdf2Sha256 { iterations: param(0) },
+            KDF_ID_ARGON2ID => Kdf::Argon2id { memory_kib: param(0), iterations: param(1), parallelism: param(2) },
+            id => return Err(Error::UnsupportedFormat(format!("Unknown KDF id: {}", id))),
+        };
+        kdf.check()?;
+        
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&block[13..]);
+        
+        Ok((kdf, salt))
+    }
+}
+
 // Enhanced Quantum Resilient Kryptographic State Machine
 // Based on theoretical quantum-resistant entropy cascading
-const ENTROPY_THRESHOLD: f64 = 0.3;
 const INITIAL_ETA: f64 = 1.0;
 const FRAGILITY_ALPHA: f64 = 0.7;
 const BUFFER_T: f64 = 5.0;
@@ -77,21 +377,6 @@ struct RKState {
 }
 
 impl RKState {
-    pub fn new(message: Vec<u8>) -> Self {
-        let mut entropy_pool = vec![0u8; ENTROPY_SEED_SIZE];
-        OsRng.fill_bytes(&mut entropy_pool);
-        
-        Self {
-            message,
-            eta: INITIAL_ETA,
-            q: 0.0,
-            t: BUFFER_T,
-            alpha: FRAGILITY_ALPHA,
-            entropy_pool,
-            round: 0,
-        }
-    }
-    
     pub fn new_with_key(message: Vec<u8>, key: &[u8]) -> Self {
         let mut entropy_pool = vec![0u8; ENTROPY_SEED_SIZE];
         
@@ -161,50 +446,109 @@ impl RKState {
         result
     }
 
-    pub fn decrypt(mut self) -> Vec<u8> {
-        // Extract original length
+    /// Invert `encrypt`.
+    ///
+    /// The keystream doesn't depend on the message, so it is replayed in
+    /// encryption order and each round undone in reverse. A round multiplies
+    /// every byte by `factor + 1`, which can only be undone when that
+    /// multiplier is odd; bytes hit by an even one are lost for good.
+    pub fn decrypt(mut self) -> Result<Vec<u8>> {
         if self.message.len() < 8 {
-            return self.message; // Corrupted data
+            return Err(Error::UnsupportedFormat("Quantum data too short (missing length prefix)".into()));
         }
         
-        let original_len_bytes: [u8; 8] = self.message[0..8].try_into().unwrap_or([0; 8]);
-        let original_len = u64::from_le_bytes(original_len_bytes) as usize;
-        
-        self.message = self.message[8..].to_vec();
+        let original_len = u64::from_le_bytes(self.message[0..8].try_into().unwrap()) as usize;
+        let mut message = self.message.split_off(8);
+        if original_len != message.len() {
+            return Err(Error::UnsupportedFormat("Quantum data length prefix doesn't match its size".into()));
+        }
         
-        // Reverse the encryption rounds
-        for round in (0..QUANTUM_ROUNDS).rev() {
+        // Replay the keystream exactly as encrypt() consumed it
+        let mut rounds = Vec::with_capacity(QUANTUM_ROUNDS);
+        for round in 0..QUANTUM_ROUNDS {
             self.round = round;
             
-            // Process message in chunks to avoid borrowing conflicts
-            let message_len = self.message.len();
-            for i in 0..message_len {
+            let mut steps = Vec::with_capacity(message.len());
+            for i in 0..message.len() {
                 self.update_entropy(i);
                 
-                // Reverse quantum state transformation
                 let quantum_noise = (self.t.sin() * 255.0) as u8;
                 let cascade_factor = (self.q.fract() * 255.0) as u8;
-                
-                self.message[i] = self.message[i]
+                steps.push((quantum_noise, cascade_factor.wrapping_add(1)));
+            }
+            rounds.push(steps);
+            
+            self.q *= 0.618;
+            self.t *= 1.414;
+        }
+        
+        let mut lost = vec![false; message.len()];
+        for steps in rounds.iter().rev() {
+            for (i, &(quantum_noise, multiplier)) in steps.iter().enumerate() {
+                let Some(inverse) = mul_inverse_u8(multiplier) else {
+                    lost[i] = true;
+                    continue;
+                };
+                message[i] = message[i]
                     .wrapping_sub(42)
-                    .wrapping_div(cascade_factor.wrapping_add(1))
+                    .wrapping_mul(inverse)
                     .wrapping_sub(quantum_noise);
             }
-            
-            // Reset state for next round (reverse)
-            self.t /= 1.414;
-            self.q /= 0.618;
         }
         
-        // Truncate to original length
-        self.message.truncate(original_len);
-        self.message
+        let lost = lost.iter().filter(|&&lost| lost).count();
+        if lost > 0 {
+            return Err(Error::UnsupportedFormat(format!(
+                "Quantum data can't be recovered: {} of {} bytes were multiplied by an even factor",
+                lost, message.len()
+            )));
+        }
+        
+        Ok(message)
+    }
+}
+
+/// Keys shorter than the entropy pool were topped up with OS randomness
+/// that was never stored, so they can neither produce nor open a usable file
+fn check_quantum_key(key: &[u8]) -> Result<()> {
+    if key.len() < ENTROPY_SEED_SIZE {
+        return Err(Error::InvalidInput(format!(
+            "Quantum mode needs a key of at least {} bytes; shorter keys were padded with random bytes that can't be recovered",
+            ENTROPY_SEED_SIZE
+        )));
     }
+    Ok(())
+}
+
+/// Multiplicative inverse modulo 256, which exists only for odd values
+fn mul_inverse_u8(x: u8) -> Option<u8> {
+    if x & 1 == 0 {
+        return None;
+    }
+    
+    // Newton's iteration; every odd x is its own inverse modulo 8, and
+    // each step doubles the number of correct bits
+    let mut inverse = x;
+    for _ in 0..2 {
+        inverse = inverse.wrapping_mul(2u8.wrapping_sub(x.wrapping_mul(inverse)));
+    }
+    Some(inverse)
 }
 
 pub fn encrypt_with_mode(data: &[u8], key: &[u8], mode: CryptoMode) -> Result<Vec<u8>> {
+    encrypt_with_kdf(data, key, mode, Kdf::default())
+}
+
+/// Encrypt a buffer, deriving the cipher key from `key` with the given KDF.
+///
+/// The AEAD modes produce a container: a versioned header naming the
+/// cipher, KDF parameters, salt and nonce, followed by the ciphertext. The
+/// legacy `aes` and `quantum` modes keep their original headerless layout
+/// and ignore `kdf`.
+pub fn encrypt_with_kdf(data: &[u8], key: &[u8], mode: CryptoMode, kdf: Kdf) -> Result<Vec<u8>> {
     match mode {
         CryptoMode::Quantum => {
+            check_quantum_key(key)?;
             let rk = RKState::new_with_key(data.to_vec(), key);
             Ok(rk.encrypt())
         },
@@ -212,16 +556,7 @@ pub fn encrypt_with_mode(data: &[u8], key: &[u8], mode: CryptoMode) -> Result<Ve
             let mut iv = [0u8; 16];
             OsRng.fill_bytes(&mut iv);
             
-            let mut k = [0u8; 32];
-            let key_len = key.len().min(32);
-            k[..key_len].copy_from_slice(&key[..key_len]);
-            
-            // If key is shorter than 32 bytes, pad with deterministic data
-            if key_len < 32 {
-                for i in key_len..32 {
-                    k[i] = key[i % key_len].wrapping_add(i as u8);
-                }
-            }
+            let k = pad_key(key);
             
             let encrypted = encrypt_aes_ctr256(data, &k, &iv);
             
@@ -230,86 +565,1032 @@ pub fn encrypt_with_mode(data: &[u8], key: &[u8], mode: CryptoMode) -> Result<Ve
             result.extend_from_slice(&iv);
             result.extend_from_slice(&encrypted);
             
+            Ok(result)
+        },
+        CryptoMode::Aes256Gcm | CryptoMode::XChaCha20Poly1305 => {
+            let mut result = Vec::with_capacity(data.len() + data.len() / 1024 + 128);
+            encrypt_stream(data, &mut result, key, mode, kdf)?;
             Ok(result)
         }
     }
 }
 
+/// Decrypt a buffer produced by `encrypt_with_mode`.
+///
+/// Containers are recognised by their magic bytes and decrypted with the
+/// cipher named in the header; `mode` only applies to legacy headerless data.
 pub fn decrypt_with_mode(data: &[u8], key: &[u8], mode: CryptoMode) -> Result<Vec<u8>> {
+    if container::has_magic(data) {
+        return decrypt_container(data, key);
+    }
+    
     match mode {
         CryptoMode::Quantum => {
+            check_quantum_key(key)?;
             let rk = RKState::new_with_key(data.to_vec(), key);
-            Ok(rk.decrypt())
+            rk.decrypt()
         },
         CryptoMode::Aes256Ctr => {
             if data.len() < 16 {
-                return Err(anyhow::anyhow!("Encrypted data too short (missing IV)"));
+                return Err(Error::UnsupportedFormat("Encrypted data too short (missing IV)".into()));
             }
             
             let (iv_bytes, ciphertext) = data.split_at(16);
             let iv: [u8; 16] = iv_bytes.try_into()
-                .map_err(|_| anyhow::anyhow!("Invalid IV length"))?;
+                .map_err(|_| Error::UnsupportedFormat("Invalid IV length".into()))?;
+            
+            let k = pad_key(key);
+            
+            Ok(decrypt_aes_ctr256(ciphertext, &k, &iv))
+        },
+        CryptoMode::Aes256Gcm => {
+            if data.len() < KDF_BLOCK_LEN + 12 + 16 {
+                return Err(Error::UnsupportedFormat("Encrypted data too short (missing KDF block, nonce or tag)".into()));
+            }
+            
+            let (kdf_block, rest) = data.split_at(KDF_BLOCK_LEN);
+            let (kdf, salt) = Kdf::decode(kdf_block)?;
+            
+            let (nonce_bytes, ciphertext) = rest.split_at(12);
+            let nonce: [u8; 12] = nonce_bytes.try_into()
+                .map_err(|_| Error::UnsupportedFormat("Invalid nonce length".into()))?;
+            
+            let k = kdf.derive_key(key, &salt)?;
+            
+            decrypt_aes_gcm256(ciphertext, &k, &nonce)
+        },
+        CryptoMode::XChaCha20Poly1305 => {
+            if data.len() < KDF_BLOCK_LEN + 24 + 16 {
+                return Err(Error::UnsupportedFormat("Encrypted data too short (missing KDF block, nonce or tag)".into()));
+            }
+            
+            let (kdf_block, rest) = data.split_at(KDF_BLOCK_LEN);
+            let (kdf, salt) = Kdf::decode(kdf_block)?;
             
-            let mut k = [0u8; 32];
-            let key_len = key.len().min(32);
-            k[..key_len].copy_from_slice(&key[..key_len]);
+            let (nonce_bytes, ciphertext) = rest.split_at(24);
+            let nonce: [u8; 24] = nonce_bytes.try_into()
+                .map_err(|_| Error::UnsupportedFormat("Invalid nonce length".into()))?;
             
-            // If key is shorter than 32 bytes, pad with deterministic data
-            if key_len < 32 {
-                for i in key_len..32 {
-                    k[i] = key[i % key_len].wrapping_add(i as u8);
+            let k = kdf.derive_key(key, &salt)?;
+            
+            decrypt_xchacha20(ciphertext, &k, &nonce)
+        }
+    }
+}
+
+fn decrypt_container(data: &[u8], key: &[u8]) -> Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(data.len());
+    decrypt_stream(data, &mut result, key)?;
+    Ok(result)
+}
+
+// Streaming container format (version 2)
+//
+// The payload is split into chunks of `chunk_size` plaintext bytes, each
+// sealed on its own following the STREAM construction: the nonce for chunk
+// `i` is the header nonce with its last five bytes replaced by `i` (u32 BE)
+// and a flag byte that is 1 only for the final chunk. Reordered chunks fail
+// because the counter no longer matches, and a stream cut at a chunk
+// boundary fails because no chunk carries the final flag.
+const TAG_LEN: usize = 16;
+
+fn chunk_nonce(base: &[u8], counter: u32, last: bool) -> Vec<u8> {
+    let mut nonce = base.to_vec();
+    let n = nonce.len();
+    nonce[n - 5..n - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[n - 1] = last as u8;
+    nonce
+}
+
+// Chunks handed to each worker per batch when sealing or opening in
+// parallel, so thread start-up cost is spread over a useful amount of work
+const CHUNKS_PER_JOB: usize = 4;
+
+/// `Write` adapter that encrypts everything written to it into a container
+///
+/// The header is written up front; data is buffered until a full batch of
+/// chunks is available, and the chunks of a batch are sealed on up to `jobs`
+/// threads. `finish` must be called to seal the final chunk - a writer
+/// dropped without it leaves a stream that fails to decrypt as truncated.
+///
+/// ```no_run
+/// # use std::fs::File;
+/// # use std::net::TcpStream;
+/// # use file_utils_i::crypto::{CryptoMode, EncryptingWriter, Kdf};
+/// # use file_utils_i::keys::Recipient;
+/// # fn main() -> file_utils_i::Result<()> {
+/// let socket = TcpStream::connect("backup.example:9000")?;
+/// let recipients = [Recipient::passphrase(b"secret", Kdf::default())];
+/// let mut enc = EncryptingWriter::new(socket, &recipients, CryptoMode::Aes256Gcm)?;
+/// std::io::copy(&mut File::open("report.pdf")?, &mut enc)?;
+/// let socket = enc.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    header: Header,
+    aad: Vec<u8>,
+    key: Zeroizing<[u8; 32]>,
+    chunk_size: usize,
+    jobs: usize,
+    buf: Zeroizing<Vec<u8>>,
+    counter: u32,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    /// Start a container for `recipients` in one of the AEAD modes, writing
+    /// its header to `inner` straight away
+    pub fn new(inner: W, recipients: &[Recipient], mode: CryptoMode) -> Result<Self> {
+        let (header, k) = seal_header(mode, recipients)?;
+        Self::from_header(inner, header, k)
+    }
+    
+    pub(crate) fn from_header(mut inner: W, header: Header, key: Zeroizing<[u8; 32]>) -> Result<Self> {
+        let chunk_size = header.chunk_size
+            .ok_or_else(|| Error::InvalidInput("Streaming requires a chunked container header".into()))? as usize;
+        
+        let aad = header.aad()?;
+        inner.write_all(&header.to_bytes()?)
+            .with_context(|| "Failed to write container header")?;
+        
+        Ok(Self {
+            inner,
+            header,
+            aad,
+            key,
+            chunk_size,
+            jobs: 1,
+            buf: Zeroizing::new(Vec::with_capacity(chunk_size)),
+            counter: 0,
+        })
+    }
+    
+    /// Seal chunks on up to `jobs` threads. The output is identical for any
+    /// value.
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+    
+    fn batch_len(&self) -> usize {
+        match self.jobs {
+            1 => self.chunk_size,
+            jobs => self.chunk_size * jobs * CHUNKS_PER_JOB,
+        }
+    }
+    
+    /// Seal everything buffered; only the final chunk of the stream gets the
+    /// last-chunk flag
+    fn seal_buffered(&mut self, last: bool) -> Result<()> {
+        let mut chunks: Vec<(u32, &[u8], bool)> = Vec::new();
+        for (i, chunk) in self.buf.chunks(self.chunk_size).enumerate() {
+            let counter = self.counter.checked_add(i as u32)
+                .ok_or_else(|| Error::InvalidInput("Stream too long: chunk counter overflow".into()))?;
+            chunks.push((counter, chunk, false));
+        }
+        match chunks.last_mut() {
+            Some(final_chunk) => final_chunk.2 = last,
+            // An empty stream still gets one (empty) final chunk
+            None if last => chunks.push((self.counter, &[], true)),
+            None => return Ok(()),
+        }
+        
+        let (mode, key, aad, base) = (self.header.mode, &self.key, &self.aad, &self.header.nonce);
+        let sealed = parallel::map_ordered(&chunks, self.jobs, |&(counter, chunk, last)| {
+            aead_seal(mode, key, &chunk_nonce(base, counter, last), chunk, aad)
+        });
+        for sealed in sealed {
+            self.inner.write_all(&sealed?)?;
+        }
+        
+        self.counter = self.counter.checked_add(chunks.len() as u32)
+            .ok_or_else(|| Error::InvalidInput("Stream too long: chunk counter overflow".into()))?;
+        self.buf.clear();
+        Ok(())
+    }
+    
+    /// Seal the final chunk and return the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        self.seal_buffered(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let batch_len = self.batch_len();
+        let mut written = 0;
+        while written < data.len() {
+            // Only seal a full batch once more data arrives, so the last
+            // chunk is always the one sealed by `finish`
+            if self.buf.len() == batch_len {
+                self.seal_buffered(false).map_err(std::io::Error::other)?;
+            }
+            let take = (batch_len - self.buf.len()).min(data.len() - written);
+            self.buf.extend_from_slice(&data[written..written + take]);
+            written += take;
+        }
+        Ok(written)
+    }
+    
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Authenticates and decrypts a chunked container body
+///
+/// Each chunk is verified before any of its plaintext is returned; with
+/// `jobs` above one, a batch of chunks is read ahead and opened in
+/// parallel. Reading fails if chunks were reordered, modified or cut off.
+struct ChunkReader<R: Read> {
+    inner: R,
+    header: Header,
+    aad: Vec<u8>,
+    key: Zeroizing<[u8; 32]>,
+    chunk_size: usize,
+    jobs: usize,
+    // Pending ciphertext; may hold one byte of lookahead past the current batch
+    ct: Vec<u8>,
+    plain: Zeroizing<Vec<u8>>,
+    pos: usize,
+    counter: u32,
+    finished: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Wrap a reader positioned just after `header`
+    fn new(inner: R, header: Header, key: Zeroizing<[u8; 32]>) -> Result<Self> {
+        let chunk_size = header.chunk_size
+            .ok_or_else(|| Error::InvalidInput("Streaming requires a chunked container header".into()))? as usize;
+        let aad = header.aad()?;
+        
+        Ok(Self {
+            inner,
+            header,
+            aad,
+            key,
+            chunk_size,
+            jobs: 1,
+            ct: Vec::with_capacity(chunk_size + TAG_LEN + 1),
+            plain: Zeroizing::new(Vec::new()),
+            pos: 0,
+            counter: 0,
+            finished: false,
+        })
+    }
+    
+    fn open_next_batch(&mut self) -> Result<()> {
+        let sealed_len = self.chunk_size + TAG_LEN;
+        let batch_len = match self.jobs {
+            1 => sealed_len,
+            jobs => sealed_len * jobs * CHUNKS_PER_JOB,
+        };
+        
+        // Read one byte past a full batch to learn whether it ends the stream
+        while self.ct.len() <= batch_len {
+            let start = self.ct.len();
+            self.ct.resize(batch_len + 1, 0);
+            let n = match self.inner.read(&mut self.ct[start..]) {
+                Ok(n) => n,
+                // A signal isn't the end of the stream; read again
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                    self.ct.truncate(start);
+                    continue;
                 }
+                Err(e) => {
+                    self.ct.truncate(start);
+                    return Err(e.into());
+                }
+            };
+            self.ct.truncate(start + n);
+            if n == 0 {
+                break;
+            }
+        }
+        
+        let last = self.ct.len() <= batch_len;
+        let take = self.ct.len().min(batch_len);
+        let chunks: Vec<&[u8]> = self.ct[..take].chunks(sealed_len).collect();
+        if chunks.last().is_none_or(|chunk| chunk.len() < TAG_LEN) {
+            return Err(Error::Tampered("Encrypted stream is truncated".into()));
+        }
+        
+        let first = self.counter;
+        let count = chunks.len();
+        let final_index = count - 1;
+        let indexed: Vec<(usize, &[u8])> = chunks.into_iter().enumerate().collect();
+        let (mode, key, aad, base) = (self.header.mode, &self.key, &self.aad, &self.header.nonce);
+        // Without a recipient list the payload key comes straight from the
+        // passphrase, so it is only proven once the first chunk opens
+        let from_passphrase = matches!(self.header.key_source, KeySource::Passphrase { .. });
+        let opened = parallel::map_ordered(&indexed, self.jobs, |&(i, chunk)| {
+            let counter = first.checked_add(i as u32)
+                .ok_or_else(|| Error::Tampered("Stream too long: chunk counter overflow".into()))?;
+            let nonce = chunk_nonce(base, counter, last && i == final_index);
+            aead_open(mode, key, &nonce, chunk, aad, !(from_passphrase && counter == 0))
+                .map(Zeroizing::new)
+                .with_context(|| format!("Chunk {} failed authentication", counter))
+        });
+        
+        let mut plain = Zeroizing::new(Vec::with_capacity(take));
+        for chunk in opened {
+            plain.extend_from_slice(&chunk?);
+        }
+        self.plain = plain;
+        self.pos = 0;
+        self.ct.drain(..take);
+        
+        self.counter = self.counter.checked_add(count as u32)
+            .ok_or_else(|| Error::Tampered("Stream too long: chunk counter overflow".into()))?;
+        self.finished = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ChunkReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.plain.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.open_next_batch().map_err(std::io::Error::other)?;
+        }
+        
+        let n = out.len().min(self.plain.len() - self.pos);
+        out[..n].copy_from_slice(&self.plain[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// `Read` adapter that yields the plaintext of a container
+///
+/// The header is read when the adapter is created and opened with whichever
+/// identity fits; after that, plaintext is only returned once the chunk
+/// holding it has been authenticated, and compressed payloads come out
+/// decompressed. Data read before an error must still be discarded, since
+/// a later chunk may turn out to be modified or missing.
+///
+/// ```no_run
+/// # use std::fs::File;
+/// # use std::net::TcpStream;
+/// # use file_utils_i::crypto::DecryptingReader;
+/// # use file_utils_i::keys::Identity;
+/// # fn main() -> file_utils_i::Result<()> {
+/// let socket = TcpStream::connect("backup.example:9000")?;
+/// let mut dec = DecryptingReader::new(socket, &[Identity::passphrase(b"secret")])?;
+/// std::io::copy(&mut dec, &mut File::create("report.pdf")?)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct DecryptingReader<R: Read> {
+    plaintext: Plaintext<R>,
+    mode: CryptoMode,
+    payload: container::Payload,
+}
+
+enum Plaintext<R: Read> {
+    Chunked(ChunkReader<R>),
+    Deflated(DeflateDecoder<ChunkReader<R>>),
+    // Version 1 containers are a single AEAD message, opened up front
+    Whole(std::io::Cursor<Zeroizing<Vec<u8>>>),
+}
+
+impl<R: Read> DecryptingReader<R> {
+    /// Read a container header from `inner` and unlock it with any of
+    /// `identities`
+    pub fn new(mut inner: R, identities: &[Identity]) -> Result<Self> {
+        let header = Header::read_from(&mut inner)?;
+        let k = unlock_header(&header, identities)?;
+        Self::from_header(inner, header, k)
+    }
+    
+    /// Wrap a reader positioned just after an already unlocked `header`
+    pub(crate) fn from_header(mut inner: R, header: Header, key: Zeroizing<[u8; 32]>) -> Result<Self> {
+        let (mode, payload, compression) = (header.mode, header.payload, header.compression);
+        let plaintext = if header.chunk_size.is_none() {
+            let mut ciphertext = Vec::new();
+            inner.read_to_end(&mut ciphertext)?;
+            // Version 1 headers always derive the key from a passphrase
+            let key_proven = !matches!(header.key_source, KeySource::Passphrase { .. });
+            let plain = aead_open(header.mode, &key, &header.nonce, &ciphertext, &header.aad()?, key_proven)?;
+            Plaintext::Whole(std::io::Cursor::new(Zeroizing::new(plain)))
+        } else {
+            let chunks = ChunkReader::new(inner, header, key)?;
+            match compression {
+                container::Compression::None => Plaintext::Chunked(chunks),
+                container::Compression::Deflate => Plaintext::Deflated(DeflateDecoder::new(chunks)),
+            }
+        };
+        
+        Ok(Self { plaintext, mode, payload })
+    }
+    
+    /// Open chunks on up to `jobs` threads
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        match &mut self.plaintext {
+            Plaintext::Chunked(chunks) => chunks.jobs = jobs.max(1),
+            Plaintext::Deflated(decoder) => decoder.get_mut().jobs = jobs.max(1),
+            Plaintext::Whole(_) => {},
+        }
+        self
+    }
+    
+    /// The cipher named in the header
+    pub fn mode(&self) -> CryptoMode {
+        self.mode
+    }
+    
+    /// Whether the plaintext is a single file or a packed directory
+    pub fn payload(&self) -> container::Payload {
+        self.payload
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        match &mut self.plaintext {
+            Plaintext::Chunked(chunks) => chunks.read(out),
+            Plaintext::Deflated(decoder) => decoder.read(out),
+            Plaintext::Whole(plain) => plain.read(out),
+        }
+    }
+}
+
+/// Build a header for `recipients` and return it with the payload key.
+///
+/// The payload is always encrypted under a random file key, with one
+/// wrapped copy per passphrase or public key, so recipients can be added
+/// or removed later without touching the payload.
+fn seal_header(mode: CryptoMode, recipients: &[Recipient]) -> Result<(Header, Zeroizing<[u8; 32]>)> {
+    if recipients.is_empty() {
+        return Err(Error::InvalidInput("No passphrase or recipients given".into()));
+    }
+    check_recipient_limits(&[], recipients)?;
+    
+    let mut file_key = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(&mut file_key[..]);
+    
+    let stanzas = recipients.iter()
+        .map(|recipient| recipient.wrap(&file_key))
+        .collect::<Result<Vec<_>>>()?;
+    
+    Ok((Header::new(mode, KeySource::Recipients(stanzas))?, file_key))
+}
+
+/// Refuse to add `recipients` to `stanzas` if the header would hold more
+/// than readers accept
+fn check_recipient_limits(stanzas: &[Stanza], recipients: &[Recipient]) -> Result<()> {
+    let passphrases = recipients.iter()
+        .filter(|recipient| matches!(recipient, Recipient::Passphrase { .. }))
+        .count();
+    if stanzas.len() + recipients.len() > container::MAX_STANZAS {
+        return Err(Error::InvalidInput(format!("A file can have at most {} recipients", container::MAX_STANZAS)));
+    }
+    if container::passphrase_stanzas(stanzas) + passphrases > container::MAX_PASSPHRASE_STANZAS {
+        return Err(Error::InvalidInput(format!(
+            "A file can have at most {} passphrase recipients", container::MAX_PASSPHRASE_STANZAS
+        )));
+    }
+    Ok(())
+}
+
+/// Recover the payload key for `header` using any of `identities`
+fn unlock_header(header: &Header, identities: &[Identity]) -> Result<Zeroizing<[u8; 32]>> {
+    match &header.key_source {
+        KeySource::Passphrase { kdf, salt } => {
+            let passphrase = identities.iter()
+                .find_map(|identity| match identity {
+                    Identity::Passphrase(passphrase) => Some(passphrase),
+                    Identity::X25519(_) => None,
+                })
+                .ok_or_else(|| Error::InvalidInput("File is protected by a passphrase, but none was given".into()))?;
+            kdf.derive_key(passphrase, salt)
+        },
+        KeySource::Recipients(stanzas) => {
+            let usable = identities.iter().any(|identity| stanzas.iter().any(|stanza| matches!(
+                (identity, stanza),
+                (Identity::Passphrase(_), Stanza::Passphrase { .. })
+                    | (Identity::X25519(_), Stanza::X25519 { .. })
+            )));
+            if !usable {
+                let mut kinds: Vec<&str> = stanzas.iter().map(Stanza::kind).collect();
+                kinds.sort_unstable();
+                kinds.dedup();
+                return Err(Error::InvalidInput(format!(
+                    "File can only be opened with: {}", kinds.join(", ")
+                )));
             }
             
-            Ok(decrypt_aes_ctr256(ciphertext, &k, &iv))
+            // A KDF that can't run is an error, not a wrong key; stop there
+            // instead of running the next stanza's KDF as well
+            for identity in identities {
+                for stanza in stanzas {
+                    if let Some(key) = identity.unwrap(stanza)? {
+                        return Ok(key);
+                    }
+                }
+            }
+            Err(Error::WrongKey)
         }
     }
 }
 
-/// High-level file encryption function with mode support
-pub fn encrypt_file<P: AsRef<Path>>(
-    input_path: P,
-    output_path: P,
+/// Wrap the file key of a container for additional recipients.
+///
+/// `identities` must be able to open the file. Only the header is
+/// rewritten; the payload is copied unchanged. Returns the new number of
+/// recipients.
+pub fn add_recipients<P: AsRef<Path>>(
+    path: P,
+    identities: &[Identity],
+    recipients: &[Recipient],
+) -> Result<usize> {
+    let path = path.as_ref();
+    let mut header = read_container_header(path)?;
+    let file_key = unlock_header(&header, identities)?;
+    
+    let KeySource::Recipients(stanzas) = &mut header.key_source else {
+        return Err(Error::InvalidInput(format!(
+            "{} predates multi-recipient headers; decrypt and re-encrypt it first", path.display()
+        )));
+    };
+    check_recipient_limits(stanzas, recipients)?;
+    for recipient in recipients {
+        stanzas.push(recipient.wrap(&file_key)?);
+    }
+    let count = stanzas.len();
+    
+    rewrite_header(path, &header)?;
+    Ok(count)
+}
+
+/// Drop recipient stanzas by index (as listed by `inspect`), without
+/// needing any key. Returns the number of recipients left.
+pub fn remove_recipients<P: AsRef<Path>>(path: P, indices: &[usize]) -> Result<usize> {
+    let path = path.as_ref();
+    let mut header = read_container_header(path)?;
+    
+    let KeySource::Recipients(stanzas) = &mut header.key_source else {
+        return Err(Error::InvalidInput(format!("{} has no recipient list", path.display())));
+    };
+    if let Some(bad) = indices.iter().find(|&&i| i >= stanzas.len()) {
+        return Err(Error::InvalidInput(format!(
+            "No recipient #{} ({} has {})", bad, path.display(), stanzas.len()
+        )));
+    }
+    
+    let mut index = 0;
+    stanzas.retain(|_| {
+        let keep = !indices.contains(&index);
+        index += 1;
+        keep
+    });
+    if stanzas.is_empty() {
+        return Err(Error::InvalidInput("Refusing to remove every recipient; the file could never be opened again".into()));
+    }
+    let count = stanzas.len();
+    
+    rewrite_header(path, &header)?;
+    Ok(count)
+}
+
+/// Change a passphrase on a container without re-encrypting it.
+///
+/// Every passphrase stanza that `old_passphrase` opens is replaced with one
+/// for `new_passphrase` under a fresh salt; other recipients and the
+/// payload are left as they are. Returns the number of stanzas replaced.
+pub fn rekey_file<P: AsRef<Path>>(
+    path: P,
+    old_passphrase: &[u8],
+    new_passphrase: &[u8],
+    kdf: Kdf,
+) -> Result<usize> {
+    let path = path.as_ref();
+    let mut header = read_container_header(path)?;
+    
+    let KeySource::Recipients(stanzas) = &mut header.key_source else {
+        return Err(Error::InvalidInput(format!(
+            "{} predates multi-recipient headers; decrypt and re-encrypt it first", path.display()
+        )));
+    };
+    
+    let old = Identity::passphrase(old_passphrase);
+    let new = Recipient::passphrase(new_passphrase, kdf);
+    let mut replaced = 0;
+    for stanza in stanzas.iter_mut() {
+        if let Some(file_key) = old.unwrap(stanza)? {
+            *stanza = new.wrap(&file_key)?;
+            replaced += 1;
+        }
+    }
+    if replaced == 0 {
+        return Err(Error::WrongKey);
+    }
+    
+    rewrite_header(path, &header)?;
+    Ok(replaced)
+}
+
+/// Recover a legacy Quantum file and re-encrypt it as an authenticated
+/// container at the same path.
+///
+/// Quantum files carry no authentication tag, so a wrong key can't be told
+/// apart from the right one. The original is therefore kept next to the
+/// new file as `<path>.legacy` and its location returned. `mode` must be
+/// one of the authenticated modes; the new file keeps the original's
+/// permissions.
+pub fn migrate_file<P: AsRef<Path>>(
+    path: P,
     key: &[u8],
+    recipients: &[Recipient],
     mode: CryptoMode,
-) -> Result<()> {
-    let input_path = input_path.as_ref();
-    let output_path = output_path.as_ref();
+) -> Result<PathBuf> {
+    let path = path.as_ref();
+    if matches!(mode, CryptoMode::Aes256Ctr | CryptoMode::Quantum) {
+        return Err(Error::InvalidInput(format!(
+            "Can't migrate to {} mode; use aes-gcm or xchacha", mode.as_str()
+        )));
+    }
     
-    // Read input file
-    let mut input_file = File::open(input_path)
-        .with_context(|| format!("Failed to open input file: {}", input_path.display()))?;
+    let mut backup_name = path.as_os_str().to_os_string();
+    backup_name.push(".legacy");
+    let backup_path = PathBuf::from(backup_name);
+    if backup_path.exists() {
+        return Err(Error::InvalidInput(format!("Backup already exists: {}", backup_path.display())));
+    }
     
-    let mut data = Vec::new();
-    input_file.read_to_end(&mut data)
-        .with_context(|| format!("Failed to read input file: {}", input_path.display()))?;
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read encrypted file: {}", path.display()))?;
+    if container::has_magic(&data) {
+        return Err(Error::InvalidInput(format!("{} is already an authenticated container", path.display())));
+    }
+    let plain = Zeroizing::new(decrypt_with_mode(&data, key, CryptoMode::Quantum)?);
     
-    // Encrypt data
-    let encrypted_data = encrypt_with_mode(&data, key, mode)
-        .with_context(|| "Failed to encrypt data")?;
+    let tmp_path = temp_path_for(path)?;
+    let result = (|| -> Result<()> {
+        let tmp_file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+            .with_context(|| format!("Failed to create temporary file: {}", tmp_path.display()))?;
+        let permissions = std::fs::metadata(path)?.permissions();
+        
+        let mut writer = BufWriter::new(&tmp_file);
+        encrypt_stream_for(&plain[..], &mut writer, recipients, mode, 1, &EncryptOptions::default())?;
+        writer.flush()?;
+        drop(writer);
+        
+        tmp_file.set_permissions(permissions)?;
+        tmp_file.sync_all()?;
+        
+        std::fs::rename(path, &backup_path)
+            .with_context(|| format!("Failed to move original to: {}", backup_path.display()))?;
+        std::fs::rename(&tmp_path, path)
+            .inspect_err(|_| {
+                // Put the original back rather than leave nothing at `path`
+                let _ = std::fs::rename(&backup_path, path);
+            })
+            .with_context(|| format!("Failed to replace: {}", path.display()))
+    })();
     
-    // Ensure output directory exists
-    if let Some(parent) = output_path.parent() {
-        std::fs::create_dir_all(parent)
-            .with_context(|| format!("Failed to create output directory: {}", parent.display()))?;
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result.map(|_| backup_path)
+}
+
+fn read_container_header(path: &Path) -> Result<Header> {
+    let mut file = BufReader::new(File::open(path)
+        .with_context(|| format!("Failed to open encrypted file: {}", path.display()))?);
+    Header::read_from(&mut file)
+        .with_context(|| format!("Failed to read container header: {}", path.display()))
+}
+
+/// Replace the header of a container, keeping its payload byte for byte.
+///
+/// The new file is written next to the old one and renamed over it, so a
+/// crash leaves either the old or the new file, never a mix.
+fn rewrite_header(path: &Path, header: &Header) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path)
+        .with_context(|| format!("Failed to open encrypted file: {}", path.display()))?);
+    let old = Header::read_from(&mut reader)?;
+    if old.aad()? != header.aad()? {
+        return Err(Error::InvalidInput("Header rewrite would invalidate the payload".into()));
     }
     
-    // Write encrypted data
-    let mut output_file = File::create(output_path)
-        .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
+    let tmp_path = temp_path_for(path)?;
     
-    output_file.write_all(&encrypted_data)
-        .with_context(|| format!("Failed to write encrypted data to: {}", output_path.display()))?;
+    let result = (|| -> Result<()> {
+        let tmp_file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+            .with_context(|| format!("Failed to create temporary file: {}", tmp_path.display()))?;
+        let permissions = std::fs::metadata(path)?.permissions();
+        
+        let mut writer = BufWriter::new(&tmp_file);
+        writer.write_all(&header.to_bytes()?)?;
+        std::io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        
+        tmp_file.set_permissions(permissions)?;
+        tmp_file.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to replace: {}", path.display()))
+    })();
     
-    output_file.sync_all()
-        .with_context(|| "Failed to sync encrypted file to disk")?;
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Encrypt everything from `reader` into a container written to `writer`.
+///
+/// Runs in constant memory regardless of input size. Returns the number of
+/// plaintext bytes processed.
+pub fn encrypt_stream<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    key: &[u8],
+    mode: CryptoMode,
+    kdf: Kdf,
+) -> Result<u64> {
+    encrypt_stream_for(reader, writer, &[Recipient::passphrase(key, kdf)], mode, 1, &EncryptOptions::default())
+}
+
+/// Optional extras recorded in the header of a new container
+#[derive(Debug, Clone, Default)]
+pub struct EncryptOptions {
+    /// Real file name to seal into the header, for files written under an
+    /// opaque name
+    pub stored_name: Option<OsString>,
+    /// Deflate the plaintext first, unless a sample shows it doesn't
+    /// compress
+    pub compress: bool,
+    /// Once a file is written, read it back and authenticate every chunk
+    /// before returning, so the source can safely be deleted
+    pub verify: bool,
+    /// What to do when the output file already exists
+    pub on_conflict: OnConflict,
+}
+
+/// Encrypt everything from `reader` for the given recipients, sealing
+/// chunks on up to `jobs` threads
+pub fn encrypt_stream_for<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    recipients: &[Recipient],
+    mode: CryptoMode,
+    jobs: usize,
+    options: &EncryptOptions,
+) -> Result<u64> {
+    let (header, k) = seal_header(mode, recipients)?;
+    encrypt_payload(reader, writer, header, &k, None, jobs, options)
+}
+
+/// Encrypt everything from `reader` under a freshly sealed header, storing
+/// the source file's `metadata` if there is one
+fn encrypt_payload<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    mut header: Header,
+    k: &Zeroizing<[u8; 32]>,
+    metadata: Option<&FileMetadata>,
+    jobs: usize,
+    options: &EncryptOptions,
+) -> Result<u64> {
+    if let Some(name) = &options.stored_name {
+        header.sealed_name = Some(names::seal_name(&header, k, name)?);
+    }
+    if let Some(metadata) = metadata {
+        header.sealed_metadata = Some(metadata::seal_metadata(&header, k, metadata)?);
+    }
+    
+    // The header has to say whether the payload is compressed, so decide
+    // from the start of the data and put it back in front afterwards
+    let mut sample = Vec::new();
+    if options.compress {
+        reader.by_ref().take(compression::SAMPLE_LEN as u64).read_to_end(&mut sample)?;
+        header.compression = compression::choose(&sample);
+    }
+    let mut reader = std::io::Cursor::new(sample).chain(reader);
     
-    println!("Encrypted {} -> {} using {} mode", 
-             input_path.display(), 
-             output_path.display(), 
-             mode.as_str());
+    let compression = header.compression;
+    let enc = EncryptingWriter::from_header(writer, header, k.clone())?.with_jobs(jobs);
+    let (enc, bytes) = compression::compress_into(enc, compression, |out| {
+        Ok(std::io::copy(&mut reader, out)?)
+    })?;
+    enc.finish()?;
     
-    Ok(())
+    Ok(bytes)
+}
+
+/// Read back a container just written with the file key `k`, authenticating
+/// every chunk, and return the number of plaintext bytes in it
+fn verify_written(path: &Path, k: &Zeroizing<[u8; 32]>, jobs: usize) -> Result<u64> {
+    let mut reader = BufReader::new(File::open(path)
+        .with_context(|| format!("Failed to open encrypted file: {}", path.display()))?);
+    let header = Header::read_from(&mut reader)?;
+    let mut dec = DecryptingReader::from_header(reader, header, k.clone())?.with_jobs(jobs);
+    Ok(std::io::copy(&mut dec, &mut std::io::sink())?)
+}
+
+/// Decrypt a container from `reader` into `writer`, picking the cipher and
+/// KDF from its header. Returns the number of plaintext bytes written.
+///
+/// Plaintext is written as each chunk is verified, so on error `writer` may
+/// already hold a prefix of the data and must be discarded.
+pub fn decrypt_stream<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    key: &[u8],
+) -> Result<u64> {
+    decrypt_stream_with(reader, writer, &[Identity::passphrase(key)], 1)
+}
+
+/// Decrypt a container using whichever of `identities` fits its header,
+/// opening chunks on up to `jobs` threads
+pub fn decrypt_stream_with<R: Read, W: Write>(
+    reader: R,
+    mut writer: W,
+    identities: &[Identity],
+    jobs: usize,
+) -> Result<u64> {
+    let mut dec = DecryptingReader::new(reader, identities)?.with_jobs(jobs);
+    let bytes = std::io::copy(&mut dec, &mut writer)?;
+    writer.flush()?;
+    Ok(bytes)
+}
+
+/// Decrypt from `reader` into `writer` whether it holds a container or a
+/// legacy headerless file, like `decrypt_file_with` does for paths. Returns
+/// the mode the data was encrypted with.
+///
+/// Legacy files are read into memory in full; containers are streamed.
+pub fn decrypt_stream_any<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    identities: &[Identity],
+    mode: CryptoMode,
+    jobs: usize,
+) -> Result<CryptoMode> {
+    let mut magic = Vec::with_capacity(container::MAGIC.len());
+    reader.by_ref().take(container::MAGIC.len() as u64).read_to_end(&mut magic)?;
+    let mut reader = std::io::Cursor::new(magic).chain(reader);
+    
+    if container::has_magic(reader.get_ref().0.get_ref()) {
+        let header = Header::read_from(&mut reader)?;
+        if header.payload == container::Payload::Archive {
+            return Err(Error::InvalidInput("This data is an archive; decrypt it from a file to extract it".into()));
+        }
+        let mode = header.mode;
+        let k = unlock_header(&header, identities)?;
+        decrypt_payload(reader, writer, header, k, jobs)?;
+        return Ok(mode);
+    }
+    
+    let passphrase = identities.iter()
+        .find_map(|identity| match identity {
+            Identity::Passphrase(passphrase) => Some(passphrase),
+            Identity::X25519(_) => None,
+        })
+        .ok_or_else(|| Error::InvalidInput("Legacy headerless files need a passphrase".into()))?;
+    let mut encrypted_data = Vec::new();
+    reader.read_to_end(&mut encrypted_data)?;
+    let decrypted = decrypt_with_mode(&encrypted_data, passphrase, mode)?;
+    writer.write_all(&decrypted)?;
+    writer.flush()?;
+    
+    Ok(mode)
+}
+
+/// Decrypt the payload that follows an already unlocked header
+fn decrypt_payload<R: Read, W: Write>(
+    reader: R,
+    mut writer: W,
+    header: Header,
+    k: Zeroizing<[u8; 32]>,
+    jobs: usize,
+) -> Result<u64> {
+    let mut dec = DecryptingReader::from_header(reader, header, k)?.with_jobs(jobs);
+    let bytes = std::io::copy(&mut dec, &mut writer)?;
+    writer.flush()?;
+    
+    Ok(bytes)
+}
+
+/// High-level file encryption function with mode support
+pub fn encrypt_file<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    key: &[u8],
+    mode: CryptoMode,
+    kdf: Kdf,
+) -> Result<()> {
+    encrypt_file_for(input_path, output_path, &[Recipient::passphrase(key, kdf)], mode, 1, &EncryptOptions::default())
+        .map(|_| ())
+}
+
+/// Encrypt a file for a set of passphrases and/or public-key recipients,
+/// sealing chunks on up to `jobs` threads. Returns the path written to,
+/// which differs from `output_path` when `options.on_conflict` renames.
+pub fn encrypt_file_for<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    recipients: &[Recipient],
+    mode: CryptoMode,
+    jobs: usize,
+    options: &EncryptOptions,
+) -> Result<PathBuf> {
+    let input_path = input_path.as_ref();
+    
+    if matches!(mode, CryptoMode::Aes256Ctr | CryptoMode::Quantum) {
+        if options.stored_name.is_some() {
+            return Err(Error::InvalidInput(format!("{} mode has no header to store the file name in", mode.as_str())));
+        }
+        if options.compress {
+            return Err(Error::InvalidInput(format!("{} mode doesn't support compression", mode.as_str())));
+        }
+    }
+    
+    let input_file = File::open(input_path)
+        .with_context(|| format!("Failed to open input file: {}", input_path.display()))?;
+    let output_path = output_path.as_ref();
+    options.on_conflict.check(output_path)?;
+    
+    match (mode, recipients) {
+        (CryptoMode::Aes256Gcm | CryptoMode::XChaCha20Poly1305, _) => {
+            // Stream through the chunked container in constant memory,
+            // keeping the permissions, owner and timestamps in the header
+            let (header, k) = seal_header(mode, recipients)?;
+            let metadata = FileMetadata::read_from(input_path)?;
+            let (output_path, bytes) = write_atomically_with(output_path, options.on_conflict, |writer| {
+                encrypt_payload(BufReader::new(input_file), writer, header, &k, Some(&metadata), jobs, options)
+                    .with_context(|| "Failed to encrypt data")
+            })?;
+            
+            if options.verify && verify_written(&output_path, &k, jobs).ok() != Some(bytes) {
+                return Err(Error::Tampered(format!(
+                    "Verification failed: {} doesn't read back as written", output_path.display()
+                )));
+            }
+            Ok(output_path)
+        },
+        (CryptoMode::Aes256Ctr | CryptoMode::Quantum, [Recipient::Passphrase { passphrase, kdf }]) => {
+            // Legacy layouts are whole-buffer only
+            let mut data = Vec::new();
+            BufReader::new(input_file).read_to_end(&mut data)
+                .with_context(|| format!("Failed to read input file: {}", input_path.display()))?;
+            let encrypted = encrypt_with_kdf(&data, passphrase, mode, *kdf)
+                .with_context(|| "Failed to encrypt data")?;
+            let (output_path, ()) = write_atomically_with(output_path, options.on_conflict, |writer| {
+                writer.write_all(&encrypted)
+                    .with_context(|| format!("Failed to write encrypted data to: {}", output_path.display()))
+            })?;
+            
+            // Without a tag to check, decrypt what was written and compare
+            // it with the source. Some quantum keys can't be inverted, and
+            // an output that doesn't decrypt is removed rather than left
+            // looking like a backup.
+            if options.verify {
+                let decrypted = std::fs::read(&output_path)
+                    .map_err(Error::from)
+                    .and_then(|written| decrypt_with_mode(&written, passphrase, mode))
+                    .map(Zeroizing::new);
+                if !decrypted.is_ok_and(|decrypted| *decrypted == data) {
+                    let _ = std::fs::remove_file(&output_path);
+                    return Err(Error::Tampered(format!(
+                        "Verification failed: {} doesn't decrypt back to the source", output_path.display()
+                    )));
+                }
+            }
+            Ok(output_path)
+        },
+        (CryptoMode::Aes256Ctr | CryptoMode::Quantum, _) => Err(Error::InvalidInput(format!(
+            "{} mode only supports a single passphrase", mode.as_str()
+        ))),
+    }
+}
+
+/// How `encrypt_file_for` protects a file, for status messages
+pub fn describe_encryption(mode: CryptoMode, recipients: &[Recipient]) -> String {
+    match (mode, recipients) {
+        (CryptoMode::Aes256Ctr | CryptoMode::Quantum, _) => {
+            format!("{} mode", mode.as_str())
+        },
+        (_, [Recipient::Passphrase { kdf, .. }]) => {
+            format!("{} mode ({} key derivation)", mode.as_str(), kdf.as_str())
+        },
+        _ => {
+            format!("{} mode for {} recipient(s)", mode.as_str(), recipients.len())
+        }
+    }
 }
 
 /// High-level file decryption function with mode support
@@ -319,43 +1600,241 @@ pub fn decrypt_file<P: AsRef<Path>>(
     key: &[u8],
     mode: CryptoMode,
 ) -> Result<()> {
+    decrypt_file_with(input_path, output_path, &[Identity::passphrase(key)], mode, 1, &DecryptOptions::default())
+        .map(|_| ())
+}
+
+/// What `decrypt_file_with` takes back from the header, and where it may
+/// write
+#[derive(Debug, Clone, Default)]
+pub struct DecryptOptions {
+    /// Write the plaintext under the file name stored in the header, if
+    /// there is one, instead of the file name of the output path
+    pub restore_name: bool,
+    /// Which kinds of stored file metadata to put back on the output
+    pub metadata: RestoreMetadata,
+    /// What to do when the output file (after restoring its name) already
+    /// exists
+    pub on_conflict: OnConflict,
+}
+
+/// Decrypt a file with a passphrase and/or identity files, opening chunks
+/// on up to `jobs` threads. Returns the mode the file was encrypted with
+/// and the path the plaintext was written to.
+///
+/// `mode` is only used for legacy headerless files, which always need a
+/// passphrase.
+pub fn decrypt_file_with<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    identities: &[Identity],
+    mut mode: CryptoMode,
+    jobs: usize,
+    options: &DecryptOptions,
+) -> Result<(CryptoMode, PathBuf)> {
     let input_path = input_path.as_ref();
-    let output_path = output_path.as_ref();
+    let mut output_path = output_path.as_ref().to_path_buf();
     
-    // Read encrypted file
     let mut input_file = File::open(input_path)
         .with_context(|| format!("Failed to open encrypted file: {}", input_path.display()))?;
     
+    // Containers announce themselves with magic bytes; anything else is a
+    // legacy headerless file that needs the caller's mode
+    let mut magic = [0u8; 6];
+    let is_container = input_file.read_exact(&mut magic).is_ok() && container::has_magic(&magic);
+    input_file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(input_file);
+    
+    // Containers are unlocked before the output is created, since the real
+    // file name may be stored in the header
+    let unlocked = if is_container {
+        let unlocked = Header::read_from(&mut reader)
+            .and_then(|header| {
+                if header.payload == container::Payload::Archive {
+                    return Err(Error::InvalidInput("This file is an archive; decrypt it on its own to extract it".into()));
+                }
+                let k = unlock_header(&header, identities)?;
+                if options.restore_name {
+                    if let Some(name) = names::open_name(&header, &k)? {
+                        output_path.set_file_name(name);
+                    }
+                }
+                let stored = metadata::open_metadata(&header, &k)?;
+                Ok((header, k, stored))
+            })
+            .with_context(|| "Failed to decrypt data")?;
+        // The header, when present, decides the cipher
+        mode = unlocked.0.mode;
+        Some(unlocked)
+    } else {
+        None
+    };
+    options.on_conflict.check(&output_path)?;
+    
+    // Plaintext only appears under its real name once every chunk has been
+    // authenticated; a failure leaves nothing (unauthenticated) behind
+    let (output_path, ()) = if let Some((header, k, stored)) = unlocked {
+        write_atomically_with(&output_path, options.on_conflict, |writer| {
+            decrypt_payload(reader, &mut *writer, header, k, jobs)
+                .with_context(|| "Failed to decrypt data")?;
+            match stored {
+                Some(stored) => stored.apply_to(writer.get_ref(), &output_path, &options.metadata),
+                None => Ok(()),
+            }
+        })?
+    } else {
+        let passphrase = identities.iter()
+            .find_map(|identity| match identity {
+                Identity::Passphrase(passphrase) => Some(passphrase),
+                Identity::X25519(_) => None,
+            })
+            .ok_or_else(|| Error::InvalidInput("Legacy headerless files need a passphrase".into()))?;
+        let mut encrypted_data = Vec::new();
+        reader.read_to_end(&mut encrypted_data)
+            .with_context(|| format!("Failed to read encrypted file: {}", input_path.display()))?;
+        let decrypted = Zeroizing::new(decrypt_with_mode(&encrypted_data, passphrase, mode)
+            .with_context(|| "Failed to decrypt data")?);
+        write_atomically_with(&output_path, options.on_conflict, |writer| {
+            writer.write_all(&decrypted)
+                .with_context(|| format!("Failed to write decrypted data to: {}", output_path.display()))
+        })?
+    };
+    
+    Ok((mode, output_path))
+}
+
+/// Run the full authenticated decryption of a file, archives included,
+/// without writing any plaintext. Returns the mode the file was encrypted
+/// with and the number of plaintext bytes it holds.
+///
+/// Every chunk, and the stored file name and metadata if present, must
+/// pass authentication. Legacy headerless files can only be checked in an
+/// authenticated `mode` (`aes-gcm` or `xchacha`); AES-CTR and quantum files
+/// carry no tag, so they are rejected.
+pub fn verify_file_with<P: AsRef<Path>>(
+    input_path: P,
+    identities: &[Identity],
+    mode: CryptoMode,
+    jobs: usize,
+) -> Result<(CryptoMode, u64)> {
+    let input_path = input_path.as_ref();
+    
+    let mut input_file = File::open(input_path)
+        .with_context(|| format!("Failed to open encrypted file: {}", input_path.display()))?;
+    let mut magic = [0u8; 6];
+    let is_container = input_file.read_exact(&mut magic).is_ok() && container::has_magic(&magic);
+    input_file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(input_file);
+    
+    if is_container {
+        let header = Header::read_from(&mut reader)
+            .with_context(|| format!("Failed to read container header: {}", input_path.display()))?;
+        let mode = header.mode;
+        let k = unlock_header(&header, identities)?;
+        names::open_name(&header, &k)?;
+        metadata::open_metadata(&header, &k)?;
+        
+        let mut dec = DecryptingReader::from_header(reader, header, k)?.with_jobs(jobs);
+        let bytes = std::io::copy(&mut dec, &mut std::io::sink())?;
+        return Ok((mode, bytes));
+    }
+    
+    if matches!(mode, CryptoMode::Aes256Ctr | CryptoMode::Quantum) {
+        return Err(Error::UnsupportedFormat(format!(
+            "Headerless {} files have no authentication tag, so they can't be verified", mode.as_str()
+        )));
+    }
+    let passphrase = identities.iter()
+        .find_map(|identity| match identity {
+            Identity::Passphrase(passphrase) => Some(passphrase),
+            Identity::X25519(_) => None,
+        })
+        .ok_or_else(|| Error::InvalidInput("Legacy headerless files need a passphrase".into()))?;
     let mut encrypted_data = Vec::new();
-    input_file.read_to_end(&mut encrypted_data)
+    reader.read_to_end(&mut encrypted_data)
         .with_context(|| format!("Failed to read encrypted file: {}", input_path.display()))?;
+    let decrypted = Zeroizing::new(decrypt_with_mode(&encrypted_data, passphrase, mode)?);
     
-    // Decrypt data
-    let decrypted_data = decrypt_with_mode(&encrypted_data, key, mode)
-        .with_context(|| "Failed to decrypt data")?;
+    Ok((mode, decrypted.len() as u64))
+}
+
+/// Pack `entries` from the directory `base` (as listed by
+/// `find_entries_recursive`) into a single archive container, so file
+/// names, sizes and layout are encrypted along with the contents.
+///
+/// With `options.compress`, whether to compress is judged from the start
+/// of the first files. `options.stored_name` is not used. Returns the path
+/// written to, as `encrypt_file_for` does, and what was packed.
+pub fn encrypt_archive_for<P: AsRef<Path>>(
+    base: P,
+    entries: &[PathBuf],
+    output_path: P,
+    recipients: &[Recipient],
+    mode: CryptoMode,
+    jobs: usize,
+    options: &EncryptOptions,
+) -> Result<(PathBuf, ArchiveStats)> {
+    let base = base.as_ref();
+    let output_path = output_path.as_ref();
+    options.on_conflict.check(output_path)?;
     
-    // Ensure output directory exists
-    if let Some(parent) = output_path.parent() {
-        std::fs::create_dir_all(parent)
-            .with_context(|| format!("Failed to create output directory: {}", parent.display()))?;
+    let (mut header, k) = seal_header(mode, recipients)?;
+    header.payload = container::Payload::Archive;
+    if options.compress {
+        header.compression = compression::choose(&compression::sample_files(entries));
     }
+    let compression = header.compression;
+    
+    let (output_path, stats) = write_atomically_with(output_path, options.on_conflict, |writer| {
+        let enc = EncryptingWriter::from_header(writer, header, k.clone())?.with_jobs(jobs);
+        let (enc, stats) = compression::compress_into(enc, compression, |out| {
+            archive::pack(base, entries, out)
+        })?;
+        enc.finish()?;
+        Ok(stats)
+    }).with_context(|| "Failed to encrypt archive")?;
     
-    // Write decrypted data
-    let mut output_file = File::create(output_path)
-        .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
+    if options.verify && verify_written(&output_path, &k, jobs).is_err() {
+        return Err(Error::Tampered(format!(
+            "Verification failed: {} doesn't read back as written", output_path.display()
+        )));
+    }
     
-    output_file.write_all(&decrypted_data)
-        .with_context(|| format!("Failed to write decrypted data to: {}", output_path.display()))?;
+    Ok((output_path, stats))
+}
+
+/// Extract an archive container into the directory `dest_dir`, following
+/// `on_conflict` for files that already exist there. Returns the mode the
+/// archive was encrypted with and what was extracted.
+///
+/// Entries are only written once the chunks holding them have been
+/// authenticated, but a tampered or truncated archive can leave the
+/// entries before the damage behind.
+pub fn decrypt_archive_with<P: AsRef<Path>>(
+    input_path: P,
+    dest_dir: P,
+    identities: &[Identity],
+    jobs: usize,
+    on_conflict: OnConflict,
+) -> Result<(CryptoMode, ArchiveStats)> {
+    let input_path = input_path.as_ref();
+    let dest_dir = dest_dir.as_ref();
     
-    output_file.sync_all()
-        .with_context(|| "Failed to sync decrypted file to disk")?;
+    let mut reader = BufReader::new(File::open(input_path)
+        .with_context(|| format!("Failed to open encrypted file: {}", input_path.display()))?);
+    let header = Header::read_from(&mut reader)
+        .with_context(|| format!("Failed to read container header: {}", input_path.display()))?;
+    if header.payload != container::Payload::Archive {
+        return Err(Error::InvalidInput(format!("Not an archive: {}", input_path.display())));
+    }
     
-    println!("Decrypted {} -> {} using {} mode", 
-             input_path.display(), 
-             output_path.display(), 
-             mode.as_str());
+    let mode = header.mode;
+    let k = unlock_header(&header, identities)?;
+    let dec = DecryptingReader::from_header(reader, header, k)?.with_jobs(jobs);
+    let stats = archive::unpack(dec, dest_dir, on_conflict)
+        .with_context(|| "Failed to extract archive")?;
     
-    Ok(())
+    Ok((mode, stats))
 }
 
 // Convenience functions for backward compatibility
@@ -365,8 +1844,8 @@ pub fn encrypt_file_legacy<P: AsRef<Path>>(
     key: &[u8],
     mode: &str,
 ) -> Result<()> {
-    let crypto_mode = CryptoMode::from_str(mode);
-    encrypt_file(input_path, output_path, key, crypto_mode)
+    let crypto_mode = CryptoMode::from_name(mode)?;
+    encrypt_file(input_path, output_path, key, crypto_mode, Kdf::default())
 }
 
 pub fn decrypt_file_legacy<P: AsRef<Path>>(
@@ -375,6 +1854,453 @@ pub fn decrypt_file_legacy<P: AsRef<Path>>(
     key: &[u8],
     mode: &str,
 ) -> Result<()> {
-    let crypto_mode = CryptoMode::from_str(mode);
+    let crypto_mode = CryptoMode::from_name(mode)?;
     decrypt_file(input_path, output_path, key, crypto_mode)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Compression;
+
+    // Cheap enough for tests; real files use `Kdf::default()`
+    const FAST_KDF: Kdf = Kdf::Pbkdf2Sha256 { iterations: 1 };
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    fn encrypt(data: &[u8], mode: CryptoMode) -> Vec<u8> {
+        let mut out = Vec::new();
+        let recipients = [Recipient::passphrase(b"pw", FAST_KDF)];
+        encrypt_stream_for(data, &mut out, &recipients, mode, 1, &EncryptOptions::default()).unwrap();
+        out
+    }
+
+    fn decrypt<R: Read>(reader: R, passphrase: &[u8], jobs: usize) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        decrypt_stream_with(reader, &mut out, &[Identity::passphrase(passphrase)], jobs)?;
+        Ok(out)
+    }
+
+    /// Hands out at most 1000 bytes per read, failing every other read with
+    /// `Interrupted` the way a signal does
+    struct Interrupting<R> {
+        inner: R,
+        interrupt: bool,
+    }
+
+    impl<R: Read> Read for Interrupting<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            let len = buf.len().min(1000);
+            self.inner.read(&mut buf[..len])
+        }
+    }
+
+    const CHUNK: usize = container::DEFAULT_CHUNK_SIZE as usize;
+
+    /// Split a container into its header and sealed chunks
+    fn split_chunks(container: &[u8]) -> (&[u8], Vec<&[u8]>) {
+        let (_, header_len) = Header::parse(container).unwrap();
+        let (header, payload) = container.split_at(header_len);
+        (header, payload.chunks(CHUNK + TAG_LEN).collect())
+    }
+
+    fn assert_tampered(container: &[u8]) {
+        for jobs in [1, 4] {
+            let result = decrypt(container, b"pw", jobs);
+            assert!(matches!(result, Err(Error::Tampered(_))), "jobs {}: {:?}", jobs, result.map(|d| d.len()));
+        }
+    }
+
+    #[test]
+    fn empty_and_single_chunk_streams() {
+        for (len, chunks) in [(0, 1), (1, 1), (CHUNK, 1), (CHUNK + 1, 2), (3 * CHUNK, 3)] {
+            let data = sample(len);
+            let container = encrypt(&data, CryptoMode::Aes256Gcm);
+            let (header, sealed) = split_chunks(&container);
+            assert_eq!(sealed.len(), chunks, "{} bytes", len);
+            assert_eq!(container.len(), header.len() + len + chunks * TAG_LEN);
+            for jobs in [1, 4] {
+                assert_eq!(decrypt(&container[..], b"pw", jobs).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn cuts_at_chunk_boundaries_are_detected() {
+        let container = encrypt(&sample(3 * CHUNK + 100), CryptoMode::XChaCha20Poly1305);
+        let (header, sealed) = split_chunks(&container);
+        for keep in 0..sealed.len() {
+            assert_tampered(&[header, &sealed[..keep].concat()].concat());
+        }
+        assert_tampered(&container[..container.len() - 1]);
+    }
+
+    #[test]
+    fn reordered_chunks_are_detected() {
+        let container = encrypt(&sample(3 * CHUNK + 100), CryptoMode::Aes256Gcm);
+        let (header, sealed) = split_chunks(&container);
+        assert_tampered(&[header, sealed[1], sealed[0], sealed[2], sealed[3]].concat());
+        // Even two full chunks swapped with each other
+        assert_tampered(&[header, sealed[0], sealed[2], sealed[1], sealed[3]].concat());
+    }
+
+    #[test]
+    fn appended_bytes_are_detected() {
+        for len in [0, CHUNK, CHUNK + 100] {
+            let container = encrypt(&sample(len), CryptoMode::Aes256Gcm);
+            let (_, sealed) = split_chunks(&container);
+            assert_tampered(&[&container[..], b"x"].concat());
+            assert_tampered(&[&container[..], sealed[0]].concat());
+        }
+    }
+
+    #[test]
+    fn wrong_keys_and_tampering_are_told_apart() {
+        let data = sample(CHUNK + 100);
+        let container = encrypt(&data, CryptoMode::Aes256Gcm);
+        assert!(matches!(decrypt(&container[..], b"not pw", 1), Err(Error::WrongKey)));
+
+        let mut flipped = container.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert_tampered(&flipped);
+
+        // The header is authenticated too, minus the recipients
+        let mut header = Header::parse(&container).unwrap().0;
+        header.chunk_size = Some(CHUNK as u32 * 2);
+        let (old_header, _) = split_chunks(&container);
+        let edited = [header.to_bytes().unwrap(), container[old_header.len()..].to_vec()].concat();
+        assert_tampered(&edited);
+    }
+
+    #[test]
+    fn only_the_first_chunk_can_mean_a_wrong_passphrase() {
+        // Headers without recipients derive the payload key from the
+        // passphrase, so nothing proves the key before the first chunk
+        let data = sample(5 * CHUNK + 100);
+        let salt = [9u8; SALT_LEN];
+        let header = Header::new(CryptoMode::XChaCha20Poly1305, KeySource::Passphrase { kdf: FAST_KDF, salt }).unwrap();
+        let key = FAST_KDF.derive_key(b"pw", &salt).unwrap();
+        let mut writer = EncryptingWriter::from_header(Vec::new(), header, key).unwrap();
+        writer.write_all(&data).unwrap();
+        let container = writer.finish().unwrap();
+        assert_eq!(decrypt(&container[..], b"pw", 4).unwrap(), data);
+
+        for jobs in [1, 4] {
+            assert!(matches!(decrypt(&container[..], b"not pw", jobs), Err(Error::WrongKey)));
+        }
+        let (header, _) = split_chunks(&container);
+        let mut first = container.clone();
+        first[header.len()] ^= 1;
+        for jobs in [1, 4] {
+            assert!(matches!(decrypt(&first[..], b"pw", jobs), Err(Error::WrongKey)));
+        }
+        // Chunk 3 is in the first batch with four jobs
+        let mut third = container.clone();
+        third[header.len() + 3 * (CHUNK + TAG_LEN)] ^= 1;
+        assert_tampered(&third);
+
+        // With recipients the key is proven by its stanza
+        let mut first = encrypt(&data, CryptoMode::XChaCha20Poly1305);
+        let (header, _) = split_chunks(&first);
+        let header_len = header.len();
+        first[header_len] ^= 1;
+        assert_tampered(&first);
+
+        // Single-shot decryption has nothing to go on but the one tag
+        let nonce = [0u8; 24];
+        let sealed = encrypt_xchacha20(b"data", &[1u8; 32], &nonce).unwrap();
+        assert!(matches!(decrypt_xchacha20(&sealed, &[2u8; 32], &nonce), Err(Error::WrongKey)));
+    }
+
+    #[test]
+    fn interrupted_reads_are_retried() {
+        let data = sample(300_000);
+        let container = encrypt(&data, CryptoMode::Aes256Gcm);
+        for jobs in [1, 4] {
+            let reader = Interrupting { inner: &container[..], interrupt: false };
+            assert_eq!(decrypt(reader, b"pw", jobs).unwrap(), data);
+        }
+    }
+
+    fn open_with(path: &Path, identities: &[Identity]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        decrypt_stream_with(File::open(path)?, &mut out, identities, 1)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn recipients_can_be_added_and_removed() {
+        use crate::keys::X25519Identity;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shared.enc");
+        let data = sample(CHUNK + 100);
+        std::fs::write(&path, encrypt(&data, CryptoMode::Aes256Gcm)).unwrap();
+        let payload = |path: &Path| {
+            let bytes = std::fs::read(path).unwrap();
+            let (_, header_len) = Header::parse(&bytes).unwrap();
+            bytes[header_len..].to_vec()
+        };
+        let original_payload = payload(&path);
+
+        let alice = X25519Identity::generate();
+        let added = [Recipient::X25519(alice.to_public()), Recipient::passphrase(b"second", FAST_KDF)];
+        let wrong = add_recipients(&path, &[Identity::passphrase(b"not pw")], &added);
+        assert!(matches!(wrong, Err(Error::WrongKey)));
+        assert_eq!(add_recipients(&path, &[Identity::passphrase(b"pw")], &added).unwrap(), 3);
+        assert_eq!(payload(&path), original_payload);
+
+        let alice = Identity::X25519(alice);
+        assert_eq!(open_with(&path, &[alice]).unwrap(), data);
+        assert_eq!(open_with(&path, &[Identity::passphrase(b"second")]).unwrap(), data);
+
+        assert!(matches!(remove_recipients(&path, &[3]), Err(Error::InvalidInput(_))));
+        assert!(matches!(remove_recipients(&path, &[0, 1, 2]), Err(Error::InvalidInput(_))));
+        assert_eq!(remove_recipients(&path, &[0, 1]).unwrap(), 1);
+        assert_eq!(payload(&path), original_payload);
+        assert!(matches!(open_with(&path, &[Identity::passphrase(b"pw")]), Err(Error::WrongKey)));
+        assert_eq!(open_with(&path, &[Identity::passphrase(b"second")]).unwrap(), data);
+
+        // A public key can't be tried against a passphrase-only file
+        let bob = Identity::X25519(X25519Identity::generate());
+        assert!(matches!(open_with(&path, &[bob]), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn compression_is_used_only_when_it_helps() {
+        let compress = EncryptOptions { compress: true, ..Default::default() };
+        let recipients = [Recipient::passphrase(b"pw", FAST_KDF)];
+        let mut random = vec![0u8; 3 * CHUNK];
+        OsRng.fill_bytes(&mut random);
+
+        for (data, expected) in [(vec![b'a'; 3 * CHUNK], Compression::Deflate), (random, Compression::None)] {
+            let mut container = Vec::new();
+            encrypt_stream_for(&data[..], &mut container, &recipients, CryptoMode::XChaCha20Poly1305, 2, &compress).unwrap();
+            assert_eq!(Header::parse(&container).unwrap().0.compression, expected);
+            if expected == Compression::Deflate {
+                assert!(container.len() < data.len() / 10);
+            }
+            assert_eq!(decrypt(&container[..], b"pw", 2).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn verify_reports_wrong_keys_tampering_and_unverifiable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checked.enc");
+        let data = sample(2 * CHUNK + 10);
+        let mut container = encrypt(&data, CryptoMode::XChaCha20Poly1305);
+        std::fs::write(&path, &container).unwrap();
+
+        let (mode, bytes) = verify_file_with(&path, &[Identity::passphrase(b"pw")], CryptoMode::Aes256Ctr, 2).unwrap();
+        assert!(matches!(mode, CryptoMode::XChaCha20Poly1305));
+        assert_eq!(bytes, data.len() as u64);
+        assert!(matches!(
+            verify_file_with(&path, &[Identity::passphrase(b"wrong")], CryptoMode::Aes256Ctr, 2),
+            Err(Error::WrongKey)
+        ));
+
+        *container.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, &container).unwrap();
+        assert!(matches!(
+            verify_file_with(&path, &[Identity::passphrase(b"pw")], CryptoMode::Aes256Ctr, 2),
+            Err(Error::Tampered(_))
+        ));
+
+        // Headerless CTR output has no tag to check
+        std::fs::write(&path, sample(100)).unwrap();
+        assert!(matches!(
+            verify_file_with(&path, &[Identity::passphrase(b"pw")], CryptoMode::Aes256Ctr, 1),
+            Err(Error::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn rekey_replaces_only_the_old_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rekeyed.enc");
+        let data = sample(1000);
+        let recipients = [Recipient::passphrase(b"pw", FAST_KDF), Recipient::passphrase(b"other", FAST_KDF)];
+        let mut container = Vec::new();
+        encrypt_stream_for(&data[..], &mut container, &recipients, CryptoMode::Aes256Gcm, 1, &EncryptOptions::default()).unwrap();
+        std::fs::write(&path, &container).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        }
+
+        assert!(matches!(rekey_file(&path, b"wrong", b"new", FAST_KDF), Err(Error::WrongKey)));
+        assert_eq!(std::fs::read(&path).unwrap(), container);
+
+        assert_eq!(rekey_file(&path, b"pw", b"new", FAST_KDF).unwrap(), 1);
+        assert!(matches!(open_with(&path, &[Identity::passphrase(b"pw")]), Err(Error::WrongKey)));
+        assert_eq!(open_with(&path, &[Identity::passphrase(b"new")]).unwrap(), data);
+        assert_eq!(open_with(&path, &[Identity::passphrase(b"other")]).unwrap(), data);
+
+        let rekeyed = std::fs::read(&path).unwrap();
+        let old_len = Header::parse(&container).unwrap().1;
+        let new_len = Header::parse(&rekeyed).unwrap().1;
+        assert_eq!(rekeyed[new_len..], container[old_len..]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn recipients_are_capped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capped.enc");
+        std::fs::write(&path, encrypt(b"data", CryptoMode::Aes256Gcm)).unwrap();
+        let owner = [Identity::passphrase(b"pw")];
+
+        let more: Vec<_> = (1..container::MAX_PASSPHRASE_STANZAS)
+            .map(|i| Recipient::passphrase(format!("pw{}", i).as_bytes(), FAST_KDF))
+            .collect();
+        assert_eq!(add_recipients(&path, &owner, &more).unwrap(), container::MAX_PASSPHRASE_STANZAS);
+        let one_more = [Recipient::passphrase(b"one too many", FAST_KDF)];
+        assert!(matches!(add_recipients(&path, &owner, &one_more), Err(Error::InvalidInput(_))));
+        assert_eq!(decrypt(File::open(&path).unwrap(), b"pw3", 1).unwrap(), b"data");
+    }
+
+    #[test]
+    fn a_failing_kdf_stops_the_search() {
+        let (mut header, _) = seal_header(CryptoMode::Aes256Gcm, &[Recipient::passphrase(b"pw", FAST_KDF)]).unwrap();
+        let KeySource::Recipients(stanzas) = &mut header.key_source else { unreachable!() };
+        // Argon2 refuses zero memory, so this stanza can't even be tried
+        let broken = Stanza::Passphrase {
+            kdf: Kdf::Argon2id { memory_kib: 0, iterations: 1, parallelism: 1 },
+            salt: [0u8; SALT_LEN],
+            wrapped: [0u8; container::WRAPPED_KEY_LEN],
+        };
+        stanzas.insert(0, broken);
+
+        let result = unlock_header(&header, &[Identity::passphrase(b"pw")]);
+        assert!(matches!(result, Err(Error::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn legacy_outputs_are_verified_by_decrypting_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let data = sample(64);
+        std::fs::write(&source, &data).unwrap();
+        let verify = EncryptOptions { verify: true, ..Default::default() };
+
+        // The second quantum key loses bytes on this data and can't be read back
+        for (mode, key, readable) in [
+            (CryptoMode::Aes256Ctr, [7u8; ENTROPY_SEED_SIZE], true),
+            (CryptoMode::Quantum, [120u8; ENTROPY_SEED_SIZE], true),
+            (CryptoMode::Quantum, [1u8; ENTROPY_SEED_SIZE], false),
+        ] {
+            let output = dir.path().join(format!("{}-{}.enc", mode.as_str(), key[0]));
+            let recipients = [Recipient::passphrase(&key, FAST_KDF)];
+            let result = encrypt_file_for(&source, &output, &recipients, mode, 1, &verify);
+            if readable {
+                assert_eq!(result.unwrap(), output);
+                assert_eq!(decrypt_with_mode(&std::fs::read(&output).unwrap(), &key, mode).unwrap(), data);
+            } else {
+                assert!(matches!(result, Err(Error::Tampered(_))));
+                assert!(!output.exists());
+            }
+        }
+    }
+
+    #[test]
+    fn migrate_needs_an_aead_mode_and_keeps_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("old.enc");
+        // Most quantum keystreams lose bytes for good; this key and length
+        // happen to round-trip
+        let key = [120u8; ENTROPY_SEED_SIZE];
+        let data = sample(64);
+        std::fs::write(&path, encrypt_with_mode(&data, &key, CryptoMode::Quantum).unwrap()).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        }
+
+        let recipients = [Recipient::passphrase(b"pw", FAST_KDF)];
+        for legacy in [CryptoMode::Aes256Ctr, CryptoMode::Quantum] {
+            let result = migrate_file(&path, &key, &recipients, legacy);
+            assert!(matches!(result, Err(Error::InvalidInput(_))));
+        }
+
+        let backup = migrate_file(&path, &key, &recipients, CryptoMode::XChaCha20Poly1305).unwrap();
+        assert!(backup.exists());
+        assert_eq!(decrypt(File::open(&path).unwrap(), b"pw", 1).unwrap(), data);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn kdf_blocks_round_trip() {
+        let salt = [5u8; SALT_LEN];
+        for kdf in [FAST_KDF, Kdf::pbkdf2(), Kdf::argon2id(), Kdf::Argon2id { memory_kib: 8, iterations: 1, parallelism: 1 }] {
+            let block = kdf.encode(&salt);
+            assert_eq!(Kdf::decode(&block).unwrap(), (kdf, salt));
+        }
+        assert!(matches!(Kdf::decode(&FAST_KDF.encode(&salt)[1..]), Err(Error::UnsupportedFormat(_))));
+
+        let mut unknown = FAST_KDF.encode(&salt);
+        unknown[0] = 9;
+        assert!(matches!(Kdf::decode(&unknown), Err(Error::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn absurd_kdf_parameters_are_rejected_when_parsed() {
+        let salt = [5u8; SALT_LEN];
+        let argon2 = |memory_kib, iterations, parallelism| Kdf::Argon2id { memory_kib, iterations, parallelism };
+        for kdf in [
+            Kdf::Pbkdf2Sha256 { iterations: 0 },
+            Kdf::Pbkdf2Sha256 { iterations: MAX_PBKDF2_ITERATIONS + 1 },
+            argon2(MAX_ARGON2_MEMORY_KIB + 1, 2, 1),
+            argon2(1, 2, 1),
+            argon2(19 * 1024, MAX_ARGON2_ITERATIONS + 1, 1),
+            argon2(19 * 1024, 0, 1),
+            argon2(19 * 1024, 2, MAX_ARGON2_PARALLELISM + 1),
+            argon2(19 * 1024, 2, 0),
+        ] {
+            assert!(matches!(kdf.check(), Err(Error::UnsupportedFormat(_))), "{:?}", kdf);
+            assert!(matches!(Kdf::decode(&kdf.encode(&salt)), Err(Error::UnsupportedFormat(_))), "{:?}", kdf);
+        }
+        for kdf in [
+            Kdf::Pbkdf2Sha256 { iterations: MAX_PBKDF2_ITERATIONS },
+            argon2(MAX_ARGON2_MEMORY_KIB, MAX_ARGON2_ITERATIONS, MAX_ARGON2_PARALLELISM),
+        ] {
+            kdf.check().unwrap();
+        }
+    }
+
+    #[test]
+    fn keys_are_derived_from_passphrase_salt_and_cost() {
+        // RFC 7914 section 11
+        let key = Kdf::Pbkdf2Sha256 { iterations: 1 }.derive_key(b"passwd", b"salt").unwrap();
+        assert_eq!(hex::encode(*key), "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc");
+
+        let kdf = Kdf::Argon2id { memory_kib: 8, iterations: 1, parallelism: 1 };
+        let salt = [5u8; SALT_LEN];
+        let key = kdf.derive_key(b"pw", &salt).unwrap();
+        assert_eq!(kdf.derive_key(b"pw", &salt).unwrap(), key);
+        assert_ne!(kdf.derive_key(b"other", &salt).unwrap(), key);
+        assert_ne!(kdf.derive_key(b"pw", &[6u8; SALT_LEN]).unwrap(), key);
+        assert_ne!(Kdf::Argon2id { memory_kib: 16, iterations: 1, parallelism: 1 }.derive_key(b"pw", &salt).unwrap(), key);
+        assert_ne!(FAST_KDF.derive_key(b"pw", &salt).unwrap(), key);
+
+        assert_eq!("argon2".parse::<Kdf>().unwrap(), Kdf::argon2id());
+        assert_eq!("PBKDF2-SHA256".parse::<Kdf>().unwrap(), Kdf::pbkdf2());
+        assert!(matches!("scrypt".parse::<Kdf>(), Err(Error::InvalidInput(_))));
+    }
+}
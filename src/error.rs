@@ -0,0 +1,97 @@
+// src/error.rs - Error type shared by the library modules
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Everything the library can fail with.
+///
+/// Callers can match on the kind of failure; the message carries the
+/// details (file names, which chunk failed, and so on).
+#[derive(Debug, Error)]
+pub enum Error {
+    /// None of the given passphrases or keys opens the data
+    #[error("Authentication failed: none of the given keys can open this file")]
+    WrongKey,
+
+    /// The data failed authentication after it was opened: modified,
+    /// reordered or cut short
+    #[error("{0}")]
+    Tampered(String),
+
+    /// Not something this library can read, or a format version, cipher or
+    /// option it doesn't know
+    #[error("{0}")]
+    UnsupportedFormat(String),
+
+    /// Another process holds the file, so it can't be overwritten or deleted
+    #[error("File is locked by another process: {}", .0.display())]
+    Locked(PathBuf),
+
+    /// The output file already exists and the conflict policy doesn't
+    /// allow replacing it
+    #[error("Output already exists: {}", .0.display())]
+    OutputExists(PathBuf),
+
+    /// Secure deletion failed for some files of a batch
+    #[error("Failed to delete {} files: {:?}", .0.len(), .0)]
+    DeleteFailed(Vec<PathBuf>),
+
+    /// Options that can't work together, a missing key, or a bad argument
+    #[error("{0}")]
+    InvalidInput(String),
+
+    /// A filesystem or stream operation failed. The message already
+    /// includes `error`, so it isn't also reported as the source.
+    #[error("{}", join(.context, &.error.to_string()))]
+    Io {
+        context: String,
+        error: io::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Put `context` in front of the message, keeping the kind of error
+    fn context(self, context: String) -> Self {
+        match self {
+            Error::Tampered(message) => Error::Tampered(join(&context, &message)),
+            Error::UnsupportedFormat(message) => Error::UnsupportedFormat(join(&context, &message)),
+            Error::InvalidInput(message) => Error::InvalidInput(join(&context, &message)),
+            Error::Io { context: inner, error } => Error::Io { context: join(&context, &inner), error },
+            other => other,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        // Errors raised inside our own `Read`/`Write` adapters travel
+        // through `io::Error`; unwrap them so their kind isn't lost
+        if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = e.into_inner().and_then(|inner| inner.downcast::<Error>().ok());
+            return *inner.expect("checked to wrap an Error");
+        }
+        Error::Io { context: String::new(), error: e }
+    }
+}
+
+/// Add context to the error of a `Result`, like `anyhow::Context`, while
+/// keeping it an `Error`
+pub(crate) trait Context<T> {
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, context: F) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, context: F) -> Result<T> {
+        self.map_err(|e| e.into().context(context().into()))
+    }
+}
+
+fn join(outer: &str, inner: &str) -> String {
+    match (outer.is_empty(), inner.is_empty()) {
+        (true, _) => inner.to_string(),
+        (_, true) => outer.to_string(),
+        _ => format!("{}: {}", outer, inner),
+    }
+}
@@ -1,7 +1,12 @@
 // src/secure_delete/file_operations.rs - File attribute and reboot operations
 use std::path::Path;
 use std::fs::OpenOptions;
-use anyhow::Result;
+
+#[cfg(windows)]
+use crate::error::Context;
+#[cfg(not(windows))]
+use crate::error::Error;
+use crate::error::Result;
 
 #[cfg(windows)]
 use std::ffi::OsStr;
@@ -58,8 +63,7 @@ pub fn remove_file_attributes(path: &Path) -> Result<()> {
     
     if result == 0 {
         let error = unsafe { GetLastError() };
-        eprintln!("Warning: Failed to remove file attributes for {}: Error {}", 
-                  path.display(), error);
+        log::warn!("Failed to remove file attributes for {}: Error {}", path.display(), error);
     }
     
     Ok(())
@@ -80,21 +84,16 @@ pub fn schedule_deletion_on_reboot(path: &Path) -> Result<()> {
     
     if result == 0 {
         let error = unsafe { GetLastError() };
-        return Err(anyhow::anyhow!(
-            "Failed to schedule deletion on reboot: Error {}", error
-        ));
+        return Err(std::io::Error::from_raw_os_error(error as i32))
+            .with_context(|| format!("Failed to schedule deletion on reboot: {}", path.display()));
     }
     
-    println!("Scheduled for deletion on next reboot: {}", path.display());
     Ok(())
 }
 
 /// Test if we can access the file for writing (simple lock test)
 pub fn test_file_access(path: &Path) -> bool {
-    match OpenOptions::new().write(true).open(path) {
-        Ok(_) => true,
-        Err(_) => false,
-    }
+    OpenOptions::new().write(true).open(path).is_ok()
 }
 
 // Non-Windows stubs
@@ -105,5 +104,5 @@ pub fn remove_file_attributes(_path: &Path) -> Result<()> {
 
 #[cfg(not(windows))]
 pub fn schedule_deletion_on_reboot(_path: &Path) -> Result<()> {
-    Err(anyhow::anyhow!("Reboot deletion not supported on this platform"))
-}
\ No newline at end of file
+    Err(Error::InvalidInput("Reboot deletion not supported on this platform".to_string()))
+}
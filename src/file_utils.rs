@@ -1,5 +1,13 @@
-use std::path::Path;
-use std::fs;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::iter;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use walkdir::WalkDir;
+
+use crate::config::{numbered_path, OnConflict};
+use crate::error::{Context, Error, Result};
 
 pub fn list_encrypted_files(directory: &Path) -> Vec<String> {
     let mut encrypted_files = Vec::new();
@@ -25,22 +33,41 @@ pub fn process_path(path: &Path) -> bool {
     path.exists()
 }
 
-/// Recursively find all files in a directory
+/// Recursively find all files in a directory, in a stable (sorted) order.
+/// Symlinks are not followed.
 pub fn find_files_recursive(directory: &Path) -> Vec<std::path::PathBuf> {
-    let mut files = Vec::new();
-    
-    if let Ok(entries) = fs::read_dir(directory) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                files.push(path);
-            } else if path.is_dir() {
-                files.extend(find_files_recursive(&path));
-            }
-        }
-    }
-    
-    files
+    WalkDir::new(directory)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// Everything under `directory` (but not `directory` itself), directories
+/// before their contents. Symlinks are listed, not followed.
+pub fn find_entries_recursive(directory: &Path) -> Vec<std::path::PathBuf> {
+    WalkDir::new(directory)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// Remove the empty directories under `directory`, deepest first, keeping
+/// `directory` itself. Returns how many were removed.
+pub fn remove_empty_dirs(directory: &Path) -> usize {
+    WalkDir::new(directory)
+        .min_depth(1)
+        .contents_first(true)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_dir())
+        .filter(|entry| fs::remove_dir(entry.path()).is_ok())
+        .count()
 }
 
 /// Check if a file has the .enc extension
@@ -70,4 +97,234 @@ pub fn ensure_directory_exists(path: &Path) -> std::io::Result<()> {
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Hidden, randomly named sibling of `path` to write a replacement into
+pub(crate) fn temp_path_for(path: &Path) -> Result<PathBuf> {
+    let file_name = path.file_name()
+        .ok_or_else(|| Error::InvalidInput(format!("Not a file: {}", path.display())))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".{:08x}.tmp", OsRng.next_u32()));
+    Ok(path.with_file_name(tmp_name))
+}
+
+/// Create `path` with the contents `write` produces, all or nothing.
+///
+/// The data goes to a hidden temporary file in the same directory, which
+/// is flushed, synced to disk and only then renamed over `path`. If `write`
+/// fails, or the process dies part way, `path` is left as it was: missing,
+/// or still holding its previous contents.
+pub fn write_atomically<T, F>(path: &Path, write: F) -> Result<T>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<T>,
+{
+    write_atomically_with(path, OnConflict::Overwrite, write).map(|(_, value)| value)
+}
+
+/// `write_atomically`, following `on_conflict` when something already
+/// exists at `path`. Returns the path written, which differs from `path`
+/// after a rename, along with what `write` returned.
+///
+/// Whether the name is free is decided in the same step that moves the
+/// output into place, so runs racing for one name can't replace each
+/// other's output.
+pub fn write_atomically_with<T, F>(path: &Path, on_conflict: OnConflict, write: F) -> Result<(PathBuf, T)>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<T>,
+{
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+    if let Some(parent) = parent {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create output directory: {}", parent.display()))?;
+    }
+    
+    let tmp_path = temp_path_for(path)?;
+    let tmp_file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)
+        .with_context(|| format!("Failed to create output file: {}", tmp_path.display()))?;
+    
+    let result = (|| -> Result<(PathBuf, T)> {
+        let mut writer = BufWriter::new(tmp_file);
+        let value = write(&mut writer)?;
+        let tmp_file = writer.into_inner().map_err(|e| e.into_error())
+            .with_context(|| format!("Failed to write output file: {}", path.display()))?;
+        tmp_file.sync_all()
+            .with_context(|| format!("Failed to sync output file to disk: {}", path.display()))?;
+        drop(tmp_file);
+        
+        let path = commit(&tmp_path, path, on_conflict)?;
+        Ok((path, value))
+    })();
+    
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return result;
+    }
+    
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Some(parent) = parent {
+        let _ = File::open(parent).and_then(|dir| dir.sync_all());
+    }
+    
+    result
+}
+
+/// Move the finished `tmp_path` to `path`, or where `on_conflict` says
+/// when that name is taken
+fn commit(tmp_path: &Path, path: &Path, on_conflict: OnConflict) -> Result<PathBuf> {
+    if on_conflict == OnConflict::Overwrite {
+        fs::rename(tmp_path, path)
+            .with_context(|| format!("Failed to move output file into place: {}", path.display()))?;
+        return Ok(path.to_path_buf());
+    }
+    
+    let renames = (on_conflict == OnConflict::Rename).then_some(1..=u32::MAX).into_iter().flatten();
+    for candidate in iter::once(path.to_path_buf()).chain(renames.map(|n| numbered_path(path, n))) {
+        match move_unless_taken(tmp_path, &candidate) {
+            Ok(()) => return Ok(candidate),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e)
+                .with_context(|| format!("Failed to move output file into place: {}", candidate.display())),
+        }
+    }
+    Err(Error::OutputExists(path.to_path_buf()))
+}
+
+/// Rename `tmp_path` to `path`, failing with `AlreadyExists` instead of
+/// replacing anything there. A hard link can't replace an existing file,
+/// so the check and the move are one step.
+fn move_unless_taken(tmp_path: &Path, path: &Path) -> io::Result<()> {
+    match fs::hard_link(tmp_path, path) {
+        Ok(()) => {
+            if let Err(e) = fs::remove_file(tmp_path) {
+                log::warn!("Failed to remove temporary file {}: {}", tmp_path.display(), e);
+            }
+            Ok(())
+        },
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(e),
+        // Filesystems without hard links (FAT, some network shares) get the
+        // check and the rename as two steps
+        Err(_) if path.symlink_metadata().is_ok() => Err(io::ErrorKind::AlreadyExists.into()),
+        Err(_) => fs::rename(tmp_path, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::Barrier;
+    use std::thread;
+
+    /// Write to `path` from `threads` threads at once, returning where each
+    /// write ended up
+    fn race(path: &Path, on_conflict: OnConflict, threads: usize) -> Vec<Result<PathBuf>> {
+        let barrier = Barrier::new(threads);
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads).map(|i| {
+                let barrier = &barrier;
+                scope.spawn(move || {
+                    write_atomically_with(path, on_conflict, |writer| {
+                        barrier.wait();
+                        Ok(writeln!(writer, "writer {}", i)?)
+                    }).map(|(path, ())| path)
+                })
+            }).collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        })
+    }
+
+    fn contents(dir: &Path) -> Vec<String> {
+        let mut contents: Vec<_> = find_files_recursive(dir).iter()
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect();
+        contents.sort();
+        contents
+    }
+
+    #[test]
+    fn racing_renames_keep_every_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.enc");
+        let written = race(&path, OnConflict::Rename, 8);
+
+        let mut names: Vec<_> = written.into_iter().map(Result::unwrap).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 8);
+        assert!(names.contains(&path));
+        assert!(names.contains(&dir.path().join("out-7.enc")));
+        assert_eq!(contents(dir.path()).len(), 8);
+    }
+
+    #[test]
+    fn racing_writers_never_replace_each_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.enc");
+        let written = race(&path, OnConflict::Fail, 8);
+
+        assert_eq!(written.iter().filter(|result| result.is_ok()).count(), 1);
+        assert!(written.iter().flat_map(|result| result.as_ref().err())
+            .all(|e| matches!(e, Error::OutputExists(existing) if *existing == path)));
+        // The losers' temporary files are gone too
+        assert_eq!(contents(dir.path()).len(), 1);
+    }
+
+    #[test]
+    fn skip_keeps_the_existing_file_and_overwrite_replaces_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.enc");
+        fs::write(&path, "old").unwrap();
+
+        assert!(matches!(OnConflict::Skip.check(&path), Err(Error::OutputExists(_))));
+        assert!(OnConflict::Rename.check(&path).is_ok());
+        let result = write_atomically_with(&path, OnConflict::Skip, |writer| Ok(writer.write_all(b"new")?));
+        assert!(matches!(result, Err(Error::OutputExists(_))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        write_atomically(&path, |writer| Ok(writer.write_all(b"new")?)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(contents(dir.path()).len(), 1);
+    }
+
+    #[test]
+    fn fail_and_rename_leave_the_existing_file_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.enc");
+        fs::write(&path, "old").unwrap();
+
+        assert!(matches!(OnConflict::Fail.check(&path), Err(Error::OutputExists(_))));
+        assert!(OnConflict::Overwrite.check(&path).is_ok());
+        let result = write_atomically_with(&path, OnConflict::Fail, |writer| Ok(writer.write_all(b"new")?));
+        assert!(matches!(result, Err(Error::OutputExists(existing)) if existing == path));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        for n in 1..=2 {
+            let (written, ()) = write_atomically_with(&path, OnConflict::Rename, |writer| Ok(writer.write_all(b"new")?)).unwrap();
+            assert_eq!(written, dir.path().join(format!("out-{}.enc", n)));
+            assert_eq!(fs::read_to_string(&written).unwrap(), "new");
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        assert_eq!("Rename".parse::<OnConflict>().unwrap(), OnConflict::Rename);
+        assert!(matches!("replace".parse::<OnConflict>(), Err(Error::InvalidInput(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dangling_symlinks_count_as_existing_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.enc");
+        std::os::unix::fs::symlink(dir.path().join("missing"), &path).unwrap();
+
+        assert!(matches!(OnConflict::Fail.check(&path), Err(Error::OutputExists(_))));
+        let result = write_atomically_with(&path, OnConflict::Fail, |writer| Ok(writer.write_all(b"new")?));
+        assert!(matches!(result, Err(Error::OutputExists(_))));
+        assert!(fs::symlink_metadata(&path).unwrap().file_type().is_symlink());
+        assert!(!dir.path().join("missing").exists());
+    }
+}
@@ -0,0 +1,364 @@
+// src/keys.rs - Recipients and identities that wrap and unwrap file keys
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use crate::error::{Context, Error, Result};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use crate::container::{Stanza, WRAPPED_KEY_LEN};
+use crate::crypto::{aead_open, aead_seal, hkdf_sha256, CryptoMode, Kdf, SALT_LEN};
+
+const PUBLIC_KEY_PREFIX: &str = "fuipub-";
+const SECRET_KEY_PREFIX: &str = "FUISECRET-";
+
+// Domain separation for the key-wrapping key
+const WRAP_INFO: &[u8] = b"file-utils-i x25519 file key wrap";
+
+// Wrapping keys come from a fresh ephemeral key or salt and wrap a single
+// file key
+const WRAP_NONCE: [u8; 24] = [0u8; 24];
+
+/// Who a file is encrypted for
+pub enum Recipient {
+    /// Anyone who knows the passphrase
+    Passphrase { passphrase: Zeroizing<Vec<u8>>, kdf: Kdf },
+    /// The holder of the matching X25519 identity
+    X25519(X25519Recipient),
+}
+
+impl Recipient {
+    pub fn passphrase(passphrase: &[u8], kdf: Kdf) -> Self {
+        Recipient::Passphrase { passphrase: Zeroizing::new(passphrase.to_vec()), kdf }
+    }
+    
+    /// Wrap a file key into a header stanza for this recipient
+    pub fn wrap(&self, file_key: &[u8; 32]) -> Result<Stanza> {
+        match self {
+            Recipient::Passphrase { passphrase, kdf } => {
+                let mut salt = [0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                
+                let wrap_key = kdf.derive_key(passphrase, &salt)?;
+                Ok(Stanza::Passphrase {
+                    kdf: *kdf,
+                    salt,
+                    wrapped: wrap_file_key(&wrap_key, file_key)?,
+                })
+            },
+            Recipient::X25519(public) => public.wrap(file_key),
+        }
+    }
+}
+
+/// Something that can open a file
+pub enum Identity {
+    Passphrase(Zeroizing<Vec<u8>>),
+    X25519(X25519Identity),
+}
+
+impl Identity {
+    pub fn passphrase(passphrase: &[u8]) -> Self {
+        Identity::Passphrase(Zeroizing::new(passphrase.to_vec()))
+    }
+    
+    /// Recover the file key from a stanza, or `None` if it isn't for us.
+    /// Fails if the stanza's KDF can't be run at all.
+    pub fn unwrap(&self, stanza: &Stanza) -> Result<Option<Zeroizing<[u8; 32]>>> {
+        match (self, stanza) {
+            (Identity::Passphrase(passphrase), Stanza::Passphrase { kdf, salt, wrapped }) => {
+                let wrap_key = kdf.derive_key(passphrase, salt)?;
+                Ok(unwrap_file_key(&wrap_key, wrapped))
+            },
+            (Identity::X25519(identity), Stanza::X25519 { .. }) => Ok(identity.unwrap(stanza)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// A public key that files can be encrypted to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct X25519Recipient {
+    public: PublicKey,
+}
+
+impl X25519Recipient {
+    /// Parse a `fuipub-<hex>` public key string
+    pub fn parse(s: &str) -> Result<Self> {
+        let hex_part = s.trim().strip_prefix(PUBLIC_KEY_PREFIX)
+            .ok_or_else(|| Error::InvalidInput(format!("Public keys start with {}", PUBLIC_KEY_PREFIX)))?;
+        let bytes: [u8; 32] = hex::decode(hex_part)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| Error::InvalidInput(format!("Malformed public key: {}", s.trim())))?;
+
+        Ok(Self { public: PublicKey::from(bytes) })
+    }
+
+    /// Accept either a public key string or a file holding one key per line
+    pub fn from_arg(arg: &str) -> Result<Vec<Self>> {
+        if arg.trim().starts_with(PUBLIC_KEY_PREFIX) {
+            return Ok(vec![Self::parse(arg)?]);
+        }
+
+        let contents = fs::read_to_string(arg)
+            .with_context(|| format!("Failed to read recipients file: {}", arg))?;
+        let recipients = contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(Self::parse)
+            .collect::<Result<Vec<_>>>()?;
+
+        if recipients.is_empty() {
+            return Err(Error::InvalidInput(format!("No public keys found in: {}", arg)));
+        }
+        Ok(recipients)
+    }
+
+    /// Wrap a file key so only the matching identity can recover it
+    pub fn wrap(&self, file_key: &[u8; 32]) -> Result<Stanza> {
+        let ephemeral = StaticSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+
+        let shared = ephemeral.diffie_hellman(&self.public);
+        if !shared.was_contributory() {
+            return Err(Error::InvalidInput("Refusing to encrypt to a low-order public key".into()));
+        }
+
+        let wrap_key = wrap_key(shared.as_bytes(), &ephemeral_public, &self.public)?;
+
+        Ok(Stanza::X25519 {
+            ephemeral: ephemeral_public.to_bytes(),
+            wrapped: wrap_file_key(&wrap_key, file_key)?,
+        })
+    }
+}
+
+impl std::fmt::Display for X25519Recipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", PUBLIC_KEY_PREFIX, hex::encode(self.public.as_bytes()))
+    }
+}
+
+/// A private key that can open files encrypted to its public key
+pub struct X25519Identity {
+    secret: StaticSecret,
+}
+
+impl X25519Identity {
+    /// Generate a new random identity
+    pub fn generate() -> Self {
+        Self { secret: StaticSecret::random_from_rng(OsRng) }
+    }
+
+    /// Public key to hand out to people encrypting for this identity
+    pub fn to_public(&self) -> X25519Recipient {
+        X25519Recipient { public: PublicKey::from(&self.secret) }
+    }
+
+    /// Load every identity from an identity file (comments are ignored)
+    pub fn load(path: &Path) -> Result<Vec<Self>> {
+        let contents = Zeroizing::new(fs::read_to_string(path)
+            .with_context(|| format!("Failed to read identity file: {}", path.display()))?);
+
+        let identities = contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let hex_part = line.strip_prefix(SECRET_KEY_PREFIX)
+                    .ok_or_else(|| Error::InvalidInput(format!("Unrecognised line in identity file: {}", path.display())))?;
+                let bytes = Zeroizing::new(hex::decode(hex_part)
+                    .map_err(|_| Error::InvalidInput(format!("Malformed secret key in: {}", path.display())))?);
+                let bytes: [u8; 32] = bytes.as_slice().try_into()
+                    .map_err(|_| Error::InvalidInput(format!("Malformed secret key in: {}", path.display())))?;
+                Ok(Self { secret: StaticSecret::from(bytes) })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if identities.is_empty() {
+            return Err(Error::InvalidInput(format!("No secret keys found in: {}", path.display())));
+        }
+        Ok(identities)
+    }
+
+    /// Write the identity file (owner-only on Unix) and a `.pub` file next
+    /// to it. Neither may exist yet.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut pub_path = path.as_os_str().to_os_string();
+        pub_path.push(".pub");
+        let pub_path = Path::new(&pub_path);
+        if path.exists() {
+            return Err(Error::InvalidInput(format!("Refusing to overwrite existing identity: {}", path.display())));
+        }
+        if pub_path.exists() {
+            return Err(Error::InvalidInput(format!("Refusing to overwrite existing public key: {}", pub_path.display())));
+        }
+
+        let public = self.to_public();
+        let contents = Zeroizing::new(format!(
+            "# file-utils-i identity - keep this file secret\n# public key: {}\n{}{}\n",
+            public,
+            SECRET_KEY_PREFIX,
+            hex::encode_upper(self.secret.as_bytes())
+        ));
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(path)
+            .with_context(|| format!("Failed to create identity file: {}", path.display()))?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+
+        // Don't leave an identity behind without its public key
+        let written = fs::OpenOptions::new().write(true).create_new(true).open(pub_path)
+            .and_then(|mut file| file.write_all(format!("{}\n", public).as_bytes()));
+        if let Err(e) = written {
+            let _ = fs::remove_file(path);
+            return Err(e).with_context(|| format!("Failed to write public key: {}", pub_path.display()));
+        }
+
+        Ok(())
+    }
+
+    /// Recover the file key from an X25519 stanza, or `None` if it isn't for us
+    pub fn unwrap(&self, stanza: &Stanza) -> Option<Zeroizing<[u8; 32]>> {
+        let Stanza::X25519 { ephemeral, wrapped } = stanza else {
+            return None;
+        };
+
+        let ephemeral_public = PublicKey::from(*ephemeral);
+        let shared = self.secret.diffie_hellman(&ephemeral_public);
+        if !shared.was_contributory() {
+            return None;
+        }
+
+        let our_public = PublicKey::from(&self.secret);
+        let wrap_key = wrap_key(shared.as_bytes(), &ephemeral_public, &our_public).ok()?;
+        unwrap_file_key(&wrap_key, wrapped)
+    }
+}
+
+fn wrap_file_key(wrap_key: &[u8; 32], file_key: &[u8; 32]) -> Result<[u8; WRAPPED_KEY_LEN]> {
+    aead_seal(CryptoMode::XChaCha20Poly1305, wrap_key, &WRAP_NONCE, file_key, &[])?
+        .try_into()
+        .map_err(|_| Error::InvalidInput("Unexpected wrapped key length".into()))
+}
+
+fn unwrap_file_key(wrap_key: &[u8; 32], wrapped: &[u8]) -> Option<Zeroizing<[u8; 32]>> {
+    let file_key = Zeroizing::new(
+        aead_open(CryptoMode::XChaCha20Poly1305, wrap_key, &WRAP_NONCE, wrapped, &[], false).ok()?
+    );
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    key.copy_from_slice(file_key.get(..32)?);
+    Some(key)
+}
+
+/// HKDF-SHA256 over the shared secret, salted with both public keys
+fn wrap_key(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> Result<Zeroizing<[u8; 32]>> {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral.as_bytes());
+    salt[32..].copy_from_slice(recipient.as_bytes());
+
+    hkdf_sha256(Some(&salt), shared, WRAP_INFO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saving_never_overwrites_either_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.txt");
+        let pub_path = dir.path().join("identity.txt.pub");
+
+        fs::write(&pub_path, "someone else's key\n").unwrap();
+        assert!(matches!(X25519Identity::generate().save(&path), Err(Error::InvalidInput(_))));
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(&pub_path).unwrap(), "someone else's key\n");
+
+        fs::remove_file(&pub_path).unwrap();
+        X25519Identity::generate().save(&path).unwrap();
+        let saved = fs::read(&path).unwrap();
+        assert!(matches!(X25519Identity::generate().save(&path), Err(Error::InvalidInput(_))));
+        assert_eq!(fs::read(&path).unwrap(), saved);
+    }
+
+    #[test]
+    fn saved_identities_load_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.txt");
+        let identity = X25519Identity::generate();
+        identity.save(&path).unwrap();
+
+        let loaded = X25519Identity::load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].to_public(), identity.to_public());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        // The .pub file works as a recipients file
+        let recipients = X25519Recipient::from_arg(dir.path().join("identity.txt.pub").to_str().unwrap()).unwrap();
+        assert_eq!(recipients, vec![identity.to_public()]);
+
+        let file_key = [9u8; 32];
+        let stanza = recipients[0].wrap(&file_key).unwrap();
+        assert_eq!(*loaded[0].unwrap(&stanza).unwrap(), file_key);
+        assert!(X25519Identity::generate().unwrap(&stanza).is_none());
+
+        fs::write(&path, "# only comments\n").unwrap();
+        assert!(matches!(X25519Identity::load(&path), Err(Error::InvalidInput(_))));
+        fs::write(&path, format!("{}ABCD\n", SECRET_KEY_PREFIX)).unwrap();
+        assert!(matches!(X25519Identity::load(&path), Err(Error::InvalidInput(_))));
+        fs::write(&path, "fuipub-00\n").unwrap();
+        assert!(matches!(X25519Identity::load(&path), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn malformed_public_keys_are_rejected() {
+        let public = X25519Identity::generate().to_public().to_string();
+        assert_eq!(X25519Recipient::parse(&format!("  {}\n", public)).unwrap().to_string(), public);
+
+        let hex_part = &public[PUBLIC_KEY_PREFIX.len()..];
+        for bad in [
+            hex_part.to_string(),
+            format!("FUIPUB-{}", hex_part),
+            format!("{}{}", SECRET_KEY_PREFIX, hex_part),
+            format!("{}{}", PUBLIC_KEY_PREFIX, &hex_part[2..]),
+            format!("{}{}00", PUBLIC_KEY_PREFIX, hex_part),
+            format!("{}{}zz", PUBLIC_KEY_PREFIX, &hex_part[2..]),
+        ] {
+            assert!(matches!(X25519Recipient::parse(&bad), Err(Error::InvalidInput(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn low_order_points_are_refused() {
+        // u = 0 and u = 1 have small order, so any clamped secret turns
+        // them into an all-zero shared secret
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        let file_key = [9u8; 32];
+        let identity = X25519Identity::generate();
+
+        for point in [[0u8; 32], one] {
+            let recipient = X25519Recipient::parse(&format!("{}{}", PUBLIC_KEY_PREFIX, hex::encode(point))).unwrap();
+            assert!(matches!(recipient.wrap(&file_key), Err(Error::InvalidInput(_))));
+
+            let stanza = Stanza::X25519 { ephemeral: point, wrapped: [0u8; WRAPPED_KEY_LEN] };
+            assert!(identity.unwrap(&stanza).is_none());
+        }
+    }
+}
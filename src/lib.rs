@@ -0,0 +1,28 @@
+// src/lib.rs - Library behind the file-utils-i command line tool
+//! Encryption, decryption and secure deletion of files.
+//!
+//! `crypto` reads and writes the `.enc` container format, from whole files
+//! down to the `EncryptingWriter` / `DecryptingReader` streaming adapters,
+//! and keeps each file's permissions, owner and timestamps (`metadata`).
+//! `secure_delete` and `secure_wipe` overwrite and remove files. Every
+//! fallible function returns [`error::Error`], and nothing here prints to
+//! stdout: progress goes to the `log` crate or is handed back to the caller.
+
+pub mod archive;
+pub mod config;
+pub mod container;
+pub mod crypto;
+pub mod error;
+pub mod file_operations;
+pub mod file_utils;
+pub mod keys;
+pub mod metadata;
+pub mod names;
+pub mod parallel;
+pub mod process_hunter;
+pub mod secure_delete;
+pub mod secure_wipe;
+
+mod compression;
+
+pub use error::{Error, Result};
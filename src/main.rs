@@ -1,21 +1,41 @@
-mod config;
-mod crypto;
-mod secure_delete;
-mod file_utils;
+mod procwolf;
 mod ui;
-mod file_operations;
-mod process_hunter;
-mod secure_wipe;
 
-use anyhow::Result;
-use clap::{Arg, ArgAction, Command};
-use std::path::Path;
+use anyhow::{Context, Result};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use dialoguer::Password;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 
-use crate::crypto::{encrypt_file, decrypt_file, CryptoMode};
-use crate::secure_delete::secure_delete;
+use file_utils_i::config::{config_dir, Config, NameTemplate, OnConflict, Operation};
+use file_utils_i::container::{inspect_file, ContainerInfo, Payload};
+use file_utils_i::crypto::{
+    add_recipients, decrypt_archive_with, decrypt_stream_any, describe_encryption, encrypt_archive_for, encrypt_file_for,
+    encrypt_stream_for, decrypt_file_with, migrate_file, rekey_file, remove_recipients, verify_file_with, CryptoMode, DecryptOptions,
+    EncryptOptions, Kdf,
+};
+use file_utils_i::file_utils::{find_entries_recursive, find_files_recursive, is_encrypted_file, remove_empty_dirs, write_atomically_with};
+use file_utils_i::keys::{Identity, Recipient, X25519Identity, X25519Recipient};
+use file_utils_i::metadata::RestoreMetadata;
+use file_utils_i::names::{NameHiding, NAMING_SALT_FILE};
+use file_utils_i::parallel;
+use file_utils_i::secure_delete::{secure_delete_batch, secure_delete_logged, DeleteLog};
+use file_utils_i::secure_wipe::Wipe;
+use file_utils_i::Error;
+use log::Level;
 
 
 fn main() -> Result<()> {
+    // The library logs what it does instead of printing it; show its
+    // messages (lock owner hunting, mostly) as plain lines on stderr
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("file_utils_i=info"))
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .init();
+    
     let matches = Command::new("file-utils-i")
         .about("Encrypt, decrypt, or securely delete files")
         .arg(Arg::new("secure")
@@ -28,27 +48,85 @@ fn main() -> Result<()> {
             Command::new("encrypt")
                 .about("Encrypt a file")
                 .arg(Arg::new("source")
-                    .help("Path to the source file to encrypt")
+                    .help("Path to the source file (or directory with -r or --archive) to encrypt, or - for stdin")
                     .required(true))
                 .arg(Arg::new("output")
                     .short('o')
                     .long("output")
-                    .help("Output file path (optional)")
+                    .help("Output file path, output directory with -r, or - for stdout (optional)")
                     .num_args(1)
                     .value_name("PATH"))
                 .arg(Arg::new("key")
                     .short('k')
                     .long("key")
-                    .help("Encryption key (optional, will prompt if not provided)")
+                    .help("Encryption key (optional, will prompt if not provided; repeatable)")
                     .num_args(1)
-                    .value_name("KEY"))
+                    .value_name("KEY")
+                    .action(ArgAction::Append))
+                .args(key_source_args(&KEY_ARGS))
                 .arg(Arg::new("mode")
                     .short('m')
                     .long("mode")
-                    .help("Encryption mode: aes or quantum")
+                    .help("Encryption mode: aes-gcm, xchacha, aes or quantum (needs --legacy-quantum); default: aes-gcm, or as configured")
                     .num_args(1)
-                    .value_name("MODE")
-                    .default_value("aes"))
+                    .value_name("MODE"))
+                .arg(Arg::new("legacy-quantum")
+                    .long("legacy-quantum")
+                    .help("Allow the deprecated, unauthenticated quantum mode")
+                    .action(ArgAction::SetTrue))
+                .arg(Arg::new("kdf")
+                    .long("kdf")
+                    .help("Key derivation for aes-gcm/xchacha: argon2id or pbkdf2 (default: argon2id, or as configured)")
+                    .num_args(1)
+                    .value_name("KDF"))
+                .arg(Arg::new("recipient")
+                    .short('R')
+                    .long("recipient")
+                    .help("Encrypt to a public key (fuipub-...) or a file of public keys (repeatable)")
+                    .num_args(1)
+                    .value_name("KEY|FILE")
+                    .action(ArgAction::Append))
+                .arg(Arg::new("passphrase")
+                    .short('p')
+                    .long("passphrase")
+                    .help("Prompt for a passphrase as well as the -R recipients")
+                    .action(ArgAction::SetTrue))
+                .arg(Arg::new("recursive")
+                    .short('r')
+                    .long("recursive")
+                    .help("Encrypt every file under a directory, mirroring the tree under -o")
+                    .action(ArgAction::SetTrue))
+                .arg(Arg::new("archive")
+                    .short('a')
+                    .long("archive")
+                    .help("Pack a directory into a single encrypted archive, hiding file names and sizes \
+                           (empty directories are kept; symlinks are skipped with a warning)")
+                    .action(ArgAction::SetTrue)
+                    .conflicts_with("recursive"))
+                .arg(Arg::new("hide-names")
+                    .long("hide-names")
+                    .help("Write opaque output names and keep the real name in the encrypted header: \
+                           random, or keyed (the same name every time, derived from the passphrase)")
+                    .num_args(0..=1)
+                    .require_equals(true)
+                    .default_missing_value("random")
+                    .value_parser(["random", "keyed"])
+                    .value_name("HOW")
+                    .conflicts_with("archive"))
+                .arg(Arg::new("name-template")
+                    .long("name-template")
+                    .help("Name outputs from a template such as {stem}.{ext}.{mode}.enc; \
+                           decrypting restores the real name (default: {name}.enc, or as configured)")
+                    .value_name("TEMPLATE")
+                    .conflicts_with_all(["archive", "hide-names"]))
+                .arg(Arg::new("compress")
+                    .short('z')
+                    .long("compress")
+                    .help("Deflate the data before encrypting (skipped for data that doesn't compress)")
+                    .action(ArgAction::SetTrue))
+                .arg(jobs_arg())
+                .arg(on_conflict_arg())
+                .arg(force_arg())
                 .arg(Arg::new("secure")
                     .short('s')
                     .long("secure")
@@ -59,12 +137,12 @@ fn main() -> Result<()> {
             Command::new("decrypt")
                 .about("Decrypt a file")
                 .arg(Arg::new("source")
-                    .help("Path to the source file to decrypt")
+                    .help("Path to the source file (or directory with -r) to decrypt, or - for stdin")
                     .required(true))
                 .arg(Arg::new("output")
                     .short('o')
                     .long("output")
-                    .help("Output file path (optional)")
+                    .help("Output file path, output directory with -r or for archives, or - for stdout (optional)")
                     .num_args(1)
                     .value_name("PATH"))
                 .arg(Arg::new("key")
@@ -73,24 +151,255 @@ fn main() -> Result<()> {
                     .help("Decryption key (optional, will prompt if not provided)")
                     .num_args(1)
                     .value_name("KEY"))
+                .args(key_source_args(&KEY_ARGS))
                 .arg(Arg::new("mode")
                     .short('m')
                     .long("mode")
-                    .help("Decryption mode: aes or quantum")
+                    .help("Mode for legacy files without a header: aes, aes-gcm, xchacha or quantum (default: aes)")
                     .num_args(1)
-                    .value_name("MODE")
-                    .default_value("aes"))
+                    .value_name("MODE"))
+                .arg(Arg::new("identity")
+                    .short('i')
+                    .long("identity")
+                    .help("Identity file for files encrypted to a public key (repeatable)")
+                    .num_args(1)
+                    .value_name("FILE")
+                    .action(ArgAction::Append))
+                .arg(Arg::new("recursive")
+                    .short('r')
+                    .long("recursive")
+                    .help("Decrypt every .enc file under a directory, mirroring the tree under -o")
+                    .action(ArgAction::SetTrue))
+                .arg(jobs_arg())
+                .arg(on_conflict_arg())
+                .arg(force_arg())
                 .arg(Arg::new("secure")
                     .short('s')
                     .long("secure")
                     .help("Securely delete original after decryption")
                     .action(ArgAction::SetTrue))
+                .arg(Arg::new("no-restore-permissions")
+                    .long("no-restore-permissions")
+                    .help("Don't restore the stored permission bits")
+                    .action(ArgAction::SetTrue))
+                .arg(Arg::new("no-restore-owner")
+                    .long("no-restore-owner")
+                    .help("Don't restore the stored owner and group")
+                    .action(ArgAction::SetTrue))
+                .arg(Arg::new("no-restore-times")
+                    .long("no-restore-times")
+                    .help("Don't restore the stored modification and access times")
+                    .action(ArgAction::SetTrue))
+                .arg(Arg::new("no-restore-xattrs")
+                    .long("no-restore-xattrs")
+                    .help("Don't restore the stored extended attributes")
+                    .action(ArgAction::SetTrue))
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Check that files decrypt and authenticate, without writing any plaintext")
+                .arg(Arg::new("source")
+                    .help("Encrypted file, or a directory of .enc files with -r")
+                    .required(true))
+                .arg(Arg::new("key")
+                    .short('k')
+                    .long("key")
+                    .help("Decryption key (optional, will prompt if not provided)")
+                    .num_args(1)
+                    .value_name("KEY"))
+                .args(key_source_args(&KEY_ARGS))
+                .arg(Arg::new("mode")
+                    .short('m')
+                    .long("mode")
+                    .help("Mode for legacy files without a header: aes-gcm or xchacha (others can't be verified)")
+                    .num_args(1)
+                    .value_name("MODE"))
+                .arg(Arg::new("identity")
+                    .short('i')
+                    .long("identity")
+                    .help("Identity file for files encrypted to a public key (repeatable)")
+                    .num_args(1)
+                    .value_name("FILE")
+                    .action(ArgAction::Append))
+                .arg(Arg::new("recursive")
+                    .short('r')
+                    .long("recursive")
+                    .help("Verify every .enc file under a directory")
+                    .action(ArgAction::SetTrue))
+                .arg(jobs_arg())
+        )
+        .subcommand(
+            Command::new("inspect")
+                .about("Show how a file was encrypted, without needing the key")
+                .arg(Arg::new("source")
+                    .help("Path to the encrypted file")
+                    .required(true))
+                .arg(Arg::new("json")
+                    .long("json")
+                    .help("Print the report as JSON")
+                    .action(ArgAction::SetTrue))
+        )
+        .subcommand(
+            Command::new("recipients")
+                .about("Add or remove who can open a file, without re-encrypting it")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Wrap the file key for more passphrases or public keys")
+                        .arg(Arg::new("source")
+                            .help("Path to the encrypted file")
+                            .required(true))
+                        .arg(Arg::new("key")
+                            .short('k')
+                            .long("key")
+                            .help("Existing key that opens the file (optional, will prompt if no identity is given)")
+                            .num_args(1)
+                            .value_name("KEY"))
+                        .args(key_source_args(&KEY_ARGS))
+                        .arg(Arg::new("identity")
+                            .short('i')
+                            .long("identity")
+                            .help("Identity file that opens the file (repeatable)")
+                            .num_args(1)
+                            .value_name("FILE")
+                            .action(ArgAction::Append))
+                        .arg(Arg::new("recipient")
+                            .short('R')
+                            .long("recipient")
+                            .help("Public key (fuipub-...) or file of public keys to add (repeatable)")
+                            .num_args(1)
+                            .value_name("KEY|FILE")
+                            .action(ArgAction::Append))
+                        .arg(Arg::new("new-key")
+                            .short('n')
+                            .long("new-key")
+                            .help("Passphrase to add (repeatable)")
+                            .num_args(1)
+                            .value_name("KEY")
+                            .action(ArgAction::Append))
+                        .args(key_source_args(&NEW_KEY_ARGS))
+                        .arg(Arg::new("passphrase")
+                            .short('p')
+                            .long("passphrase")
+                            .help("Prompt for a passphrase to add")
+                            .action(ArgAction::SetTrue))
+                        .arg(Arg::new("kdf")
+                            .long("kdf")
+                            .help("Key derivation for added passphrases: argon2id or pbkdf2 (default: argon2id, or as configured)")
+                            .num_args(1)
+                            .value_name("KDF"))
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Drop recipients by their number in `inspect` output")
+                        .arg(Arg::new("source")
+                            .help("Path to the encrypted file")
+                            .required(true))
+                        .arg(Arg::new("index")
+                            .long("index")
+                            .help("Recipient number to remove (repeatable)")
+                            .num_args(1)
+                            .value_name("N")
+                            .value_parser(clap::value_parser!(usize))
+                            .action(ArgAction::Append)
+                            .required(true))
+                )
+        )
+        .subcommand(
+            Command::new("rekey")
+                .about("Change a file's passphrase without re-encrypting it")
+                .arg(Arg::new("source")
+                    .help("Encrypted file, or a directory of .enc files with -r")
+                    .required(true))
+                .arg(Arg::new("key")
+                    .short('k')
+                    .long("key")
+                    .help("Current key (optional, will prompt if not provided)")
+                    .num_args(1)
+                    .value_name("KEY"))
+                .args(key_source_args(&KEY_ARGS))
+                .arg(Arg::new("new-key")
+                    .short('n')
+                    .long("new-key")
+                    .help("New key (optional, will prompt if not provided)")
+                    .num_args(1)
+                    .value_name("KEY"))
+                .args(key_source_args(&NEW_KEY_ARGS))
+                .arg(Arg::new("kdf")
+                    .long("kdf")
+                    .help("Key derivation for the new key: argon2id or pbkdf2 (default: argon2id, or as configured)")
+                    .num_args(1)
+                    .value_name("KDF"))
+                .arg(Arg::new("recursive")
+                    .short('r')
+                    .long("recursive")
+                    .help("Rekey every .enc file under a directory")
+                    .action(ArgAction::SetTrue))
+        )
+        .subcommand(
+            Command::new("migrate")
+                .about("Recover legacy quantum-mode files and re-encrypt them with an authenticated cipher")
+                .arg(Arg::new("source")
+                    .help("Legacy file, or a directory of .enc files with -r")
+                    .required(true))
+                .arg(Arg::new("key")
+                    .short('k')
+                    .long("key")
+                    .help("Key the files were encrypted with; also used for the new files (optional, will prompt if not provided)")
+                    .num_args(1)
+                    .value_name("KEY"))
+                .args(key_source_args(&KEY_ARGS))
+                .arg(Arg::new("mode")
+                    .short('m')
+                    .long("mode")
+                    .help("Mode for the new files: aes-gcm or xchacha (default: aes-gcm, or as configured)")
+                    .num_args(1)
+                    .value_name("MODE")
+                    .value_parser(["aes-gcm", "xchacha"]))
+                .arg(Arg::new("kdf")
+                    .long("kdf")
+                    .help("Key derivation for the new files: argon2id or pbkdf2 (default: argon2id, or as configured)")
+                    .num_args(1)
+                    .value_name("KDF"))
+                .arg(Arg::new("recursive")
+                    .short('r')
+                    .long("recursive")
+                    .help("Migrate every legacy quantum .enc file under a directory")
+                    .action(ArgAction::SetTrue))
         )
+        .subcommand(
+            Command::new("keygen")
+                .about("Generate an X25519 identity for public-key encryption")
+                .arg(Arg::new("output")
+                    .short('o')
+                    .long("output")
+                    .help("Identity file to create; the public key is written to <PATH>.pub")
+                    .num_args(1)
+                    .value_name("PATH")
+                    .default_value("file-utils-i.key"))
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Settings kept in the config file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("show")
+                        .about("Print the effective settings: the config file merged over the defaults")
+                        .arg(Arg::new("json")
+                            .long("json")
+                            .help("Print the settings as JSON instead of TOML")
+                            .action(ArgAction::SetTrue))
+                )
+        )
+        .subcommand(procwolf::command())
         .get_matches();
+    
+    // Settings from the config file; command line flags override them
+    let settings = Config::load()?;
 
     // CASE: Standalone secure delete mode
     if let Some(path) = matches.get_one::<String>("secure") {
-        secure_delete(Path::new(path))?;
+        shred(Path::new(path), &settings.wipe(), false)?;
         println!("Securely deleted: {}", path);
         return Ok(());
     }
@@ -100,72 +409,513 @@ fn main() -> Result<()> {
         Some(("encrypt", encrypt_matches)) => {
             let source_file = encrypt_matches.get_one::<String>("source").unwrap();
             let source_path = Path::new(source_file);
+            let is_archive = encrypt_matches.get_flag("archive");
+            if is_archive && !source_path.is_dir() {
+                return Err(anyhow::anyhow!("--archive needs a directory: {}", source_path.display()));
+            }
+            if !is_archive {
+                check_directory_flag(source_path, encrypt_matches)?;
+            }
+            let piped = check_piping(source_file, encrypt_matches)?;
+            if piped && (is_archive || encrypt_matches.contains_id("hide-names")) {
+                return Err(anyhow::anyhow!("--archive and --hide-names need a source and output path, not -"));
+            }
+            
+            // Get encryption mode
+            let mode = mode_from_args(encrypt_matches, &settings)?;
+            if let CryptoMode::Quantum = mode {
+                if !encrypt_matches.get_flag("legacy-quantum") {
+                    return Err(anyhow::anyhow!(
+                        "quantum mode is deprecated: it is unauthenticated and most files can't be decrypted again. \
+                         Use aes-gcm or xchacha, or pass --legacy-quantum if you really need it"
+                    ));
+                }
+                ui::display_warning("quantum mode is unauthenticated and usually can't be decrypted; prefer aes-gcm");
+            }
+            
+            let name_template = encrypt_matches.get_one::<String>("name-template")
+                .map(|template| template.parse::<NameTemplate>())
+                .transpose()?
+                .or_else(|| settings.name_template.clone());
             
             // Determine output path
             let output_path = match encrypt_matches.get_one::<String>("output") {
                 Some(output) => output.clone(),
+                None if source_file == "-" => "-".to_string(),
+                // Archives go next to the directory, not inside it
+                None if source_path.is_dir() => format!("{}.enc", source_file.trim_end_matches(['/', '\\'])),
                 None => {
-                    // Auto-generate output filename by adding .enc extension
-                    format!("{}.enc", source_file)
+                    // Auto-generate output filename from the name template
+                    let config = Config { name_template: name_template.clone(), ..Config::default() };
+                    config.get_output_path(source_path, Operation::Encrypt(mode))
+                        .to_string_lossy()
+                        .into_owned()
                 }
             };
             
-            // Get encryption key
-            let key = get_encryption_key(encrypt_matches.get_one::<String>("key"))?;
+            // Get key derivation function
+            let kdf = kdf_from_args(encrypt_matches, &settings)?;
             
-            // Get encryption mode
-            let mode_str = encrypt_matches.get_one::<String>("mode").unwrap();
-            let mode = CryptoMode::from_str(mode_str);
+            // Every passphrase and public key gets its own copy of the file
+            // key; prompt for a passphrase unless only public keys were given
+            let mut recipients = load_recipients(encrypt_matches)?;
+            for key in keys_from_args(encrypt_matches, &KEY_ARGS)? {
+                recipients.push(Recipient::passphrase(&key, kdf));
+            }
+            if recipients.is_empty() || encrypt_matches.get_flag("passphrase") {
+                let key = prompt_for_key("Enter encryption key", true)?;
+                recipients.push(Recipient::passphrase(&key, kdf));
+            }
             
-            // Perform encryption
-            encrypt_file(source_path, Path::new(&output_path), &key, mode)?;
+            // Opaque names are random per file, or a MAC of the real name
+            // keyed by the first passphrase and the salt in the root of the
+            // output tree
+            let hiding = match encrypt_matches.get_one::<String>("hide-names").map(String::as_str) {
+                Some("keyed") => {
+                    let passphrase = recipients.iter()
+                        .find_map(|recipient| match recipient {
+                            Recipient::Passphrase { passphrase, .. } => Some(passphrase),
+                            Recipient::X25519(_) => None,
+                        })
+                        .ok_or_else(|| anyhow::anyhow!("--hide-names=keyed needs a passphrase"))?;
+                    let root = if source_path.is_dir() {
+                        encrypt_matches.get_one::<String>("output").map_or(source_path, Path::new)
+                    } else {
+                        Path::new(&output_path).parent()
+                            .filter(|parent| !parent.as_os_str().is_empty())
+                            .unwrap_or(Path::new("."))
+                    };
+                    Some(NameHiding::keyed(passphrase, kdf, root)?)
+                },
+                Some(_) => Some(NameHiding::Random),
+                None => None,
+            };
             
-            // Securely delete original if requested
-            if encrypt_matches.get_flag("secure") {
-                secure_delete(source_path)?;
-                println!("Original file securely deleted");
+            // Perform encryption: one file per worker for directories,
+            // chunks spread over the workers for a single file
+            let jobs = jobs_from_args(encrypt_matches);
+            let on_conflict = on_conflict_from_args(encrypt_matches, &settings)?;
+            // Opaque and templated names keep the real name in the header;
+            // headerless modes can't, so templated names are lost there
+            let seal_name = hiding.is_some()
+                || (name_template.is_some() && !matches!(mode, CryptoMode::Aes256Ctr | CryptoMode::Quantum));
+            let encrypt = |input: &Path, output: &Path, jobs| -> Result<String> {
+                let options = EncryptOptions {
+                    stored_name: seal_name.then(|| input.file_name()).flatten().map(Into::into),
+                    compress: encrypt_matches.get_flag("compress"),
+                    verify: encrypt_matches.get_flag("secure"),
+                    on_conflict,
+                };
+                let output = encrypt_file_for(input, output, &recipients, mode, jobs, &options)?;
+                Ok(format!("Encrypted {} -> {} using {}",
+                           input.display(),
+                           output.display(),
+                           describe_encryption(mode, &recipients)))
+            };
+            if piped {
+                let options = EncryptOptions {
+                    compress: encrypt_matches.get_flag("compress"),
+                    ..EncryptOptions::default()
+                };
+                let result = pipe(source_path, &output_path, on_conflict, |input, output| {
+                    encrypt_stream_for(input, output, &recipients, mode, jobs, &options)
+                });
+                let Some((output_path, _)) = unless_skipped(result, on_conflict)? else {
+                    return Ok(());
+                };
+                print_status(output_path == "-", &format!("Encrypted {} -> {} using {}",
+                                                    stream_name(source_file, "stdin"),
+                                                    stream_name(&output_path, "stdout"),
+                                                    describe_encryption(mode, &recipients)));
+            } else if is_archive {
+                let output = Path::new(&output_path);
+                let entries = archive_entry_list(source_path, output);
+                let options = EncryptOptions {
+                    compress: encrypt_matches.get_flag("compress"),
+                    verify: encrypt_matches.get_flag("secure"),
+                    on_conflict,
+                    ..EncryptOptions::default()
+                };
+                let result = encrypt_archive_for(source_path, &entries, output, &recipients, mode, jobs, &options);
+                let Some((output, stats)) = unless_skipped(result.map_err(Into::into), on_conflict)? else {
+                    return Ok(());
+                };
+                println!("Archived {} -> {} ({} files, {} directories, {} bytes) using {}",
+                         source_path.display(),
+                         output.display(),
+                         stats.files,
+                         stats.dirs,
+                         stats.bytes,
+                         describe_encryption(mode, &recipients));
+                
+                if encrypt_matches.get_flag("secure") {
+                    // Only regular files went into the archive
+                    let files: Vec<_> = entries.into_iter()
+                        .filter(|entry| fs::symlink_metadata(entry).is_ok_and(|m| m.is_file()))
+                        .collect();
+                    let mut done = 0;
+                    secure_delete_batch(&files, &settings.wipe(), jobs, |path, mut log, result| {
+                        done += 1;
+                        println!("Processing file {}/{}: {}", done, files.len(), path.display());
+                        print_delete_log(&mut log, false);
+                        if let Err(e) = result {
+                            eprintln!("Failed to delete {}: {}", path.display(), e);
+                        }
+                    })?;
+                    println!("Original files securely deleted");
+                    remove_emptied_folders(source_path, &settings);
+                }
+            } else if source_path.is_dir() {
+                let config = Config { name_template, ..directory_config(encrypt_matches, &settings, on_conflict) };
+                process_directory(source_path, &config, Operation::Encrypt(mode), jobs, |input, output| {
+                    match &hiding {
+                        Some(hiding) => encrypt(input, &opaque_output_path(hiding, input, output)?, 1),
+                        None => encrypt(input, output, 1),
+                    }
+                })?;
+            } else {
+                // An explicit -o wins over an opaque name
+                let output = match (&hiding, encrypt_matches.contains_id("output")) {
+                    (Some(hiding), false) => opaque_output_path(hiding, source_path, Path::new(&output_path))?,
+                    _ => PathBuf::from(&output_path),
+                };
+                let Some(status) = unless_skipped(encrypt(source_path, &output, jobs), on_conflict)? else {
+                    return Ok(());
+                };
+                println!("{}", status);
+                
+                // Securely delete original if requested
+                if encrypt_matches.get_flag("secure") {
+                    shred(source_path, &settings.wipe(), false)?;
+                    println!("Original file securely deleted");
+                }
             }
         }
         Some(("decrypt", decrypt_matches)) => {
             let source_file = decrypt_matches.get_one::<String>("source").unwrap();
             let source_path = Path::new(source_file);
+            check_directory_flag(source_path, decrypt_matches)?;
+            let piped = check_piping(source_file, decrypt_matches)?;
             
-            // Determine output path
+            // Determine output path (the directory to extract into, for
+            // archives)
             let output_path = match decrypt_matches.get_one::<String>("output") {
                 Some(output) => output.clone(),
+                None if source_file == "-" => "-".to_string(),
                 None => {
                     // Auto-generate output filename by removing .enc extension
-                    if source_file.ends_with(".enc") {
-                        source_file[..source_file.len() - 4].to_string()
-                    } else {
-                        format!("{}.decrypted", source_file)
-                    }
+                    Config::default().get_output_path(source_path, Operation::Decrypt)
+                        .to_string_lossy()
+                        .into_owned()
                 }
             };
             
-            // Get decryption key
-            let key = get_encryption_key(decrypt_matches.get_one::<String>("key"))?;
+            let identities = load_identities(decrypt_matches)?;
             
-            // Get decryption mode
-            let mode_str = decrypt_matches.get_one::<String>("mode").unwrap();
-            let mode = CryptoMode::from_str(mode_str);
+            // Decryption mode only matters for legacy headerless files
+            let mode = decrypt_matches.get_one::<String>("mode")
+                .map(|m| CryptoMode::from_name(m))
+                .transpose()?
+                .unwrap_or(CryptoMode::Aes256Ctr);
+            
+            // Permissions, owner, times and xattrs stored in the header are
+            // put back unless opted out of
+            let restore = RestoreMetadata {
+                permissions: !decrypt_matches.get_flag("no-restore-permissions"),
+                ownership: !decrypt_matches.get_flag("no-restore-owner"),
+                timestamps: !decrypt_matches.get_flag("no-restore-times"),
+                xattrs: !decrypt_matches.get_flag("no-restore-xattrs"),
+            };
             
             // Perform decryption
-            decrypt_file(source_path, Path::new(&output_path), &key, mode)?;
+            let jobs = jobs_from_args(decrypt_matches);
+            let on_conflict = on_conflict_from_args(decrypt_matches, &settings)?;
+            // Files stored under opaque names get their real name back,
+            // unless an explicit output file was given
+            let decrypt = |input: &Path, output: &Path, jobs, restore_name| -> Result<String> {
+                let options = DecryptOptions { restore_name, metadata: restore, on_conflict };
+                let (used, output) = decrypt_file_with(input, output, &identities, mode, jobs, &options)?;
+                Ok(format!("Decrypted {} -> {} using {} mode",
+                           input.display(),
+                           output.display(),
+                           used.as_str()))
+            };
+            let is_archive = source_path.is_file()
+                && inspect_file(source_path).is_ok_and(|info| info.payload == Some(Payload::Archive));
+            if piped {
+                let result = pipe(source_path, &output_path, on_conflict, |input, output| {
+                    decrypt_stream_any(input, output, &identities, mode, jobs)
+                });
+                let Some((output_path, used)) = unless_skipped(result, on_conflict)? else {
+                    return Ok(());
+                };
+                print_status(output_path == "-", &format!("Decrypted {} -> {} using {} mode",
+                                                    stream_name(source_file, "stdin"),
+                                                    stream_name(&output_path, "stdout"),
+                                                    used.as_str()));
+            } else if is_archive {
+                let (used, stats) = decrypt_archive_with(source_path, Path::new(&output_path), &identities, jobs, on_conflict)?;
+                println!("Extracted {} -> {} ({} files, {} directories, {} bytes) using {} mode",
+                         source_path.display(),
+                         output_path,
+                         stats.files,
+                         stats.dirs,
+                         stats.bytes,
+                         used.as_str());
+                if stats.skipped > 0 {
+                    println!("Skipped {} existing file(s)", stats.skipped);
+                }
+                
+                // Skipped entries only exist in the archive, so it is kept
+                if decrypt_matches.get_flag("secure") && stats.skipped > 0 {
+                    println!("Original file kept, since some of its files weren't extracted");
+                } else if decrypt_matches.get_flag("secure") {
+                    shred(source_path, &settings.wipe(), false)?;
+                    println!("Original file securely deleted");
+                }
+            } else if source_path.is_dir() {
+                let config = directory_config(decrypt_matches, &settings, on_conflict);
+                process_directory(source_path, &config, Operation::Decrypt, jobs, |input, output| {
+                    if config.secure_delete {
+                        check_secure_decrypt(input, mode)?;
+                    }
+                    decrypt(input, output, 1, true)
+                })?;
+            } else {
+                if decrypt_matches.get_flag("secure") {
+                    check_secure_decrypt(source_path, mode)?;
+                }
+                let restore_name = !decrypt_matches.contains_id("output");
+                let result = decrypt(source_path, Path::new(&output_path), jobs, restore_name);
+                let Some(status) = unless_skipped(result, on_conflict)? else {
+                    return Ok(());
+                };
+                println!("{}", status);
+                
+                // Securely delete original if requested
+                if decrypt_matches.get_flag("secure") {
+                    shred(source_path, &settings.wipe(), false)?;
+                    println!("Original file securely deleted");
+                }
+            }
+        }
+        Some(("verify", verify_matches)) => {
+            let source_file = verify_matches.get_one::<String>("source").unwrap();
+            let source_path = Path::new(source_file);
+            check_directory_flag(source_path, verify_matches)?;
+            
+            let identities = load_identities(verify_matches)?;
+            let mode = verify_matches.get_one::<String>("mode")
+                .map(|m| CryptoMode::from_name(m))
+                .transpose()?
+                .unwrap_or(CryptoMode::Aes256Ctr);
+            let jobs = jobs_from_args(verify_matches);
+            
+            let verify = |file: &Path, jobs| -> Result<String> {
+                let (used, bytes) = verify_file_with(file, &identities, mode, jobs)?;
+                Ok(format!("Verified {} ({} mode, {} bytes)", file.display(), used.as_str(), bytes))
+            };
+            
+            if !source_path.is_dir() {
+                println!("{}", verify(source_path, jobs)?);
+                return Ok(());
+            }
+            
+            // One file per worker, reported in directory order
+            let files: Vec<_> = find_files_recursive(source_path)
+                .into_iter()
+                .filter(|path| is_encrypted_file(path))
+                .collect();
+            let mut failed = 0;
+            parallel::for_each_ordered(&files, jobs, |file| verify(file, 1), |file, result| {
+                match result {
+                    Ok(status) => println!("{}", status),
+                    Err(e) => {
+                        ui::display_error(&format!("{}: {:#}", file.display(), e));
+                        failed += 1;
+                    }
+                }
+            });
             
-            // Securely delete original if requested
-            if decrypt_matches.get_flag("secure") {
-                secure_delete(source_path)?;
-                println!("Original file securely deleted");
+            ui::display_summary("Verification", files.len(), files.len() - failed, 0, failed);
+            if failed > 0 {
+                return Err(anyhow::anyhow!("{} of {} file(s) failed verification", failed, files.len()));
             }
         }
+        Some(("inspect", inspect_matches)) => {
+            let source_file = inspect_matches.get_one::<String>("source").unwrap();
+            let info = inspect_file(Path::new(source_file))?;
+            
+            if inspect_matches.get_flag("json") {
+                println!("{}", serde_json::to_string_pretty(&info)?);
+            } else {
+                print_container_info(source_file, &info);
+            }
+        }
+        Some(("recipients", recipients_matches)) => match recipients_matches.subcommand() {
+            Some(("add", add_matches)) => {
+                let source_file = add_matches.get_one::<String>("source").unwrap();
+                let kdf = kdf_from_args(add_matches, &settings)?;
+                
+                let mut recipients = load_recipients(add_matches)?;
+                for key in keys_from_args(add_matches, &NEW_KEY_ARGS)? {
+                    recipients.push(Recipient::passphrase(&key, kdf));
+                }
+                if add_matches.get_flag("passphrase") {
+                    let key = prompt_for_key("Enter new encryption key", true)?;
+                    recipients.push(Recipient::passphrase(&key, kdf));
+                }
+                if recipients.is_empty() {
+                    return Err(anyhow::anyhow!("Nothing to add; use -R, -n or -p"));
+                }
+                
+                let identities = load_identities(add_matches)?;
+                let count = add_recipients(Path::new(source_file), &identities, &recipients)?;
+                println!("Added {} recipient(s) to {} ({} total)", recipients.len(), source_file, count);
+            }
+            Some(("remove", remove_matches)) => {
+                let source_file = remove_matches.get_one::<String>("source").unwrap();
+                let indices: Vec<usize> = remove_matches.get_many::<usize>("index").unwrap().copied().collect();
+                
+                let count = remove_recipients(Path::new(source_file), &indices)?;
+                println!("Removed recipient(s) from {} ({} left)", source_file, count);
+            }
+            _ => unreachable!("clap requires a recipients subcommand"),
+        },
+        Some(("rekey", rekey_matches)) => {
+            let source_file = rekey_matches.get_one::<String>("source").unwrap();
+            let source_path = Path::new(source_file);
+            let kdf = kdf_from_args(rekey_matches, &settings)?;
+            
+            check_directory_flag(source_path, rekey_matches)?;
+            
+            let old_key = get_encryption_key(rekey_matches, &KEY_ARGS, "Enter current encryption key", false)?;
+            let new_key = get_encryption_key(rekey_matches, &NEW_KEY_ARGS, "Enter new encryption key", true)?;
+            
+            if !source_path.is_dir() {
+                rekey_file(source_path, &old_key, &new_key, kdf)?;
+                println!("Rekeyed {}", source_file);
+                return Ok(());
+            }
+            
+            let files: Vec<_> = find_files_recursive(source_path)
+                .into_iter()
+                .filter(|path| is_encrypted_file(path))
+                .collect();
+            
+            let mut failed = 0;
+            for file in &files {
+                match rekey_file(file, &old_key, &new_key, kdf) {
+                    Ok(_) => println!("Rekeyed {}", file.display()),
+                    Err(e) => {
+                        ui::display_error(&format!("{}: {:#}", file.display(), e));
+                        failed += 1;
+                    }
+                }
+            }
+            
+            ui::display_summary("Rekey", files.len(), files.len() - failed, 0, failed);
+            if failed > 0 {
+                return Err(anyhow::anyhow!("Failed to rekey {} file(s)", failed));
+            }
+        }
+        Some(("migrate", migrate_matches)) => {
+            let source_file = migrate_matches.get_one::<String>("source").unwrap();
+            let source_path = Path::new(source_file);
+            let mode = mode_from_args(migrate_matches, &settings)?;
+            let kdf = kdf_from_args(migrate_matches, &settings)?;
+            // -m only takes authenticated modes, but the configured one may not be
+            if matches!(mode, CryptoMode::Aes256Ctr | CryptoMode::Quantum) {
+                return Err(anyhow::anyhow!(
+                    "The configured mode {} can't be migrated to; pass -m aes-gcm or -m xchacha", mode.as_str()
+                ));
+            }
+            
+            check_directory_flag(source_path, migrate_matches)?;
+            
+            let key = get_encryption_key(migrate_matches, &KEY_ARGS, "Enter encryption key", false)?;
+            let recipients = [Recipient::passphrase(&key, kdf)];
+            
+            if !source_path.is_dir() {
+                let backup = migrate_file(source_path, &key, &recipients, mode)?;
+                println!("Migrated {} to {} mode (original kept as {})", source_file, mode.as_str(), backup.display());
+                return Ok(());
+            }
+            
+            let files: Vec<_> = find_files_recursive(source_path)
+                .into_iter()
+                .filter(|path| is_encrypted_file(path))
+                .collect();
+            
+            let (mut skipped, mut failed) = (0, 0);
+            for file in &files {
+                if !matches!(inspect_file(file), Ok(info) if info.format == "legacy-quantum") {
+                    skipped += 1;
+                    continue;
+                }
+                match migrate_file(file, &key, &recipients, mode) {
+                    Ok(backup) => println!("Migrated {} (original kept as {})", file.display(), backup.display()),
+                    Err(e) => {
+                        ui::display_error(&format!("{}: {:#}", file.display(), e));
+                        failed += 1;
+                    }
+                }
+            }
+            
+            ui::display_summary("Migrate", files.len(), files.len() - skipped - failed, skipped, failed);
+            if failed > 0 {
+                return Err(anyhow::anyhow!("Failed to migrate {} file(s)", failed));
+            }
+        }
+        Some(("keygen", keygen_matches)) => {
+            let output = keygen_matches.get_one::<String>("output").unwrap();
+            let identity = X25519Identity::generate();
+            identity.save(Path::new(output))?;
+            
+            println!("Identity written to {} (keep it secret)", output);
+            println!("Public key: {}", identity.to_public());
+        }
+        Some(("config", config_matches)) => match config_matches.subcommand() {
+            Some(("show", show_matches)) => {
+                let source = match Config::find_file() {
+                    Some(path) => format!("Loaded from {}", path.display()),
+                    None => match config_dir() {
+                        Some(dir) => format!("No config.toml or config.json in {}; using defaults", dir.display()),
+                        None => "No config directory on this system; using defaults".to_string(),
+                    },
+                };
+                // Spell out the default name template instead of leaving it out
+                let effective = Config {
+                    name_template: Some(settings.name_template.clone().unwrap_or_default()),
+                    ..settings
+                };
+                if show_matches.get_flag("json") {
+                    eprintln!("{}", source);
+                    println!("{}", serde_json::to_string_pretty(&effective)?);
+                } else {
+                    println!("# {}", source);
+                    print!("{}", toml::to_string(&effective)?);
+                }
+            }
+            _ => unreachable!("clap requires a config subcommand"),
+        },
+        Some(("procwolf", procwolf_matches)) => procwolf::run(procwolf_matches)?,
         _ => {
             println!("Usage:");
-            println!("  Encrypt: file-utils-i.exe encrypt <path> [-o output] [-k key] [-m mode] [-s]");
-            println!("  Decrypt: file-utils-i.exe decrypt <path> [-o output] [-k key] [-m mode] [-s]");
+            println!("  Encrypt: file-utils-i.exe encrypt <path|-> [-o output|-] [-k key]... [-R pubkey]... [-p] [-m mode] [--kdf kdf] [-r | -a] [--hide-names[=keyed] | --name-template T] [-z] [-j N] [-s]");
+            println!("  Decrypt: file-utils-i.exe decrypt <path|-> [-o output|-] [-k key] [-i identity] [-m mode] [-r] [-j N] [-s]");
+            println!("  Recipients: file-utils-i.exe recipients add <path> [-k key | -i identity] [-R pubkey]... [-n newkey]...");
+            println!("              file-utils-i.exe recipients remove <path> --index N...");
+            println!("  Rekey:   file-utils-i.exe rekey <path> [-k key] [-n newkey] [--kdf kdf] [-r]");
+            println!("  Migrate: file-utils-i.exe migrate <path> [-k key] [-m mode] [-r]");
+            println!("  Keygen:  file-utils-i.exe keygen [-o identity]");
+            println!("  Inspect: file-utils-i.exe inspect <path> [--json]");
+            println!("  Config:  file-utils-i.exe config show [--json]");
             println!("  Shred:   file-utils-i.exe -s <path>");
-            println!("");
-            println!("Modes: aes (default), quantum");
+            println!("  Procwolf: file-utils-i.exe procwolf status|list [filter]|owners <path>|deploy <path>|hunt <pattern>|kill <pid>|resume <pid>");
+            println!();
+            println!("Modes: aes-gcm (default), xchacha, aes, quantum (deprecated, needs --legacy-quantum)");
+            println!("Key sources: -k key, --key-file path, --key-env VAR or --key-fd N");
             println!("If no key is provided, you'll be prompted to enter one.");
         }
     }
@@ -173,31 +923,596 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-/// Get encryption key from command line argument or prompt user
-fn get_encryption_key(key_arg: Option<&String>) -> Result<Vec<u8>> {
-    match key_arg {
-        Some(key_str) => {
-            // Use provided key, convert to bytes
-            Ok(key_str.as_bytes().to_vec())
+/// Whether an encrypt/decrypt reads stdin or writes stdout (`-`), after
+/// ruling out combinations that can't work with a stream
+fn check_piping(source: &str, matches: &ArgMatches) -> Result<bool> {
+    let output = matches.get_one::<String>("output").map(String::as_str);
+    let piped = source == "-" || output == Some("-");
+    if piped && matches.get_flag("recursive") {
+        return Err(anyhow::anyhow!("-r can't read from stdin or write to stdout"));
+    }
+    if source == "-" && matches.get_flag("secure") {
+        return Err(anyhow::anyhow!("-s needs a source file to delete, not stdin"));
+    }
+    if output == Some("-") && matches.get_flag("secure") {
+        return Err(anyhow::anyhow!("-s only deletes the source once the output is verified, which can't be done on stdout"));
+    }
+    Ok(piped)
+}
+
+/// Refuse `-s` for headerless aes and quantum files: they have no tag, so a
+/// wrong key decrypts to garbage without an error and deleting the source
+/// would leave nothing to recover from
+fn check_secure_decrypt(source: &Path, mode: CryptoMode) -> Result<()> {
+    if matches!(mode, CryptoMode::Aes256Ctr | CryptoMode::Quantum) && inspect_file(source)?.version.is_none() {
+        return Err(anyhow::anyhow!(
+            "-s can't be used with headerless {} files: they aren't authenticated, so a wrong key goes unnoticed",
+            mode.as_str()
+        ));
+    }
+    Ok(())
+}
+
+/// Run `process` with `source` open for reading and `output` for writing,
+/// where `-` means stdin or stdout. An output file only appears once
+/// `process` has succeeded, following `on_conflict` if one exists already;
+/// whatever already went to stdout can't be taken back. Returns where the
+/// output went, `-` for stdout.
+fn pipe<T, F>(source: &Path, output: &str, on_conflict: OnConflict, process: F) -> Result<(String, T)>
+where
+    F: FnOnce(&mut dyn Read, &mut dyn Write) -> file_utils_i::Result<T>,
+{
+    if output != "-" {
+        on_conflict.check(Path::new(output))?;
+    }
+    
+    let mut input: Box<dyn Read> = if source == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(source)
+            .with_context(|| format!("Failed to open input file: {}", source.display()))?))
+    };
+    
+    if output == "-" {
+        let mut stdout = BufWriter::new(io::stdout().lock());
+        let result = process(&mut input, &mut stdout)?;
+        stdout.flush()?;
+        return Ok((output.to_string(), result));
+    }
+    
+    let (output, result) = write_atomically_with(Path::new(output), on_conflict, |writer| process(&mut input, writer))?;
+    Ok((output.to_string_lossy().into_owned(), result))
+}
+
+/// Print a status line, on stderr when stdout is carrying the data
+fn print_status(stdout_is_data: bool, message: &str) {
+    if stdout_is_data {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// How to show a path that may be `-` in status lines
+fn stream_name<'a>(path: &'a str, stream: &'a str) -> &'a str {
+    if path == "-" { stream } else { path }
+}
+
+/// Securely delete `path`, printing its progress messages (on stderr when
+/// stdout is carrying the data)
+fn shred(path: &Path, wipe: &Wipe, stdout_is_data: bool) -> Result<()> {
+    let mut log = DeleteLog::default();
+    let result = secure_delete_logged(path, wipe, &mut log);
+    print_delete_log(&mut log, stdout_is_data);
+    Ok(result?)
+}
+
+/// Remove the folders under `source` that secure deletion left empty, if
+/// the config asks for it
+fn remove_emptied_folders(source: &Path, config: &Config) {
+    if config.clean_empty_folders {
+        let removed = remove_empty_dirs(source);
+        if removed > 0 {
+            println!("Removed {} empty folder(s)", removed);
         }
-        None => {
-            // Prompt user for key
-            use std::io::{self, Write};
-            
-            print!("Enter encryption key: ");
-            io::stdout().flush()?;
-            
-            let mut key = String::new();
-            io::stdin().read_line(&mut key)?;
-            
-            // Remove trailing newline
-            let key = key.trim();
-            
-            if key.is_empty() {
-                return Err(anyhow::anyhow!("Empty key not allowed"));
+    }
+}
+
+/// Print the messages a deletion collected, warnings to stderr
+fn print_delete_log(log: &mut DeleteLog, stdout_is_data: bool) {
+    for (level, line) in log.drain() {
+        if stdout_is_data || level <= Level::Warn {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// `output` with its file name replaced by an opaque one for `input`
+fn opaque_output_path(hiding: &NameHiding, input: &Path, output: &Path) -> Result<PathBuf> {
+    let name = input.file_name()
+        .ok_or_else(|| anyhow::anyhow!("Not a file: {}", input.display()))?;
+    Ok(output.with_file_name(hiding.opaque_name(name)?))
+}
+
+/// Entries to pack for `encrypt --archive`, leaving out the archive itself
+/// when it's being written inside the source directory
+fn archive_entry_list(source: &Path, output: &Path) -> Vec<PathBuf> {
+    let mut entries = find_entries_recursive(source);
+    if let Ok(output) = fs::canonicalize(output) {
+        entries.retain(|entry| fs::canonicalize(entry).map_or(true, |entry| entry != output));
+    }
+    entries
+}
+
+/// `-j/--jobs`, shared by encrypt and decrypt
+fn jobs_arg() -> Arg {
+    Arg::new("jobs")
+        .short('j')
+        .long("jobs")
+        .help("Worker threads: files at once with -r, chunks at once for a single file (default: CPU count)")
+        .num_args(1)
+        .value_name("N")
+        .value_parser(clap::value_parser!(u64).range(1..))
+}
+
+fn on_conflict_arg() -> Arg {
+    Arg::new("on-conflict")
+        .long("on-conflict")
+        .help("When an output file already exists: fail, skip, overwrite or rename (adds -1, -2, ...)")
+        .num_args(1)
+        .value_name("POLICY")
+        .value_parser(["fail", "skip", "overwrite", "rename"])
+}
+
+fn force_arg() -> Arg {
+    Arg::new("force")
+        .short('f')
+        .long("force")
+        .help("Overwrite existing output files (same as --on-conflict overwrite)")
+        .conflicts_with("on-conflict")
+        .action(ArgAction::SetTrue)
+}
+
+/// `--on-conflict` or `--force`, falling back to the configured policy
+fn on_conflict_from_args(matches: &ArgMatches, settings: &Config) -> Result<OnConflict> {
+    let settings = Config { force: matches.get_flag("force"), ..settings.clone() };
+    match matches.get_one::<String>("on-conflict") {
+        Some(policy) => Ok(policy.parse::<OnConflict>()?),
+        None => Ok(settings.conflict_policy()),
+    }
+}
+
+/// `-m/--mode`, falling back to the configured mode
+fn mode_from_args(matches: &ArgMatches, settings: &Config) -> Result<CryptoMode> {
+    Ok(matches.get_one::<String>("mode")
+        .map(|m| CryptoMode::from_name(m))
+        .transpose()?
+        .unwrap_or(settings.mode))
+}
+
+/// `--kdf`, falling back to the configured KDF. Naming the configured
+/// algorithm keeps its configured cost.
+fn kdf_from_args(matches: &ArgMatches, settings: &Config) -> Result<Kdf> {
+    match matches.get_one::<String>("kdf") {
+        Some(kdf) => {
+            let kdf = kdf.parse::<Kdf>()?;
+            Ok(if kdf.as_str() == settings.kdf.as_str() { settings.kdf } else { kdf })
+        }
+        None => Ok(settings.kdf),
+    }
+}
+
+/// The existing output that made `e`, when `--on-conflict skip` should step
+/// around it instead of failing
+fn skipped_output(e: &anyhow::Error, on_conflict: OnConflict) -> Option<&Path> {
+    match e.downcast_ref::<Error>() {
+        Some(Error::OutputExists(path)) if on_conflict == OnConflict::Skip => Some(path),
+        _ => None,
+    }
+}
+
+/// The result of a single-file operation, or `None` after reporting that
+/// its output already exists and is being skipped
+fn unless_skipped<T>(result: Result<T>, on_conflict: OnConflict) -> Result<Option<T>> {
+    match result {
+        Err(e) => match skipped_output(&e, on_conflict) {
+            Some(path) => {
+                println!("Skipping existing output: {}", path.display());
+                Ok(None)
             }
-            
-            Ok(key.as_bytes().to_vec())
+            None => Err(e),
+        },
+        Ok(value) => Ok(Some(value)),
+    }
+}
+
+fn jobs_from_args(matches: &ArgMatches) -> usize {
+    matches.get_one::<u64>("jobs")
+        .map(|&jobs| jobs as usize)
+        .unwrap_or_else(parallel::default_jobs)
+}
+
+/// Directories are only processed when -r was given
+fn check_directory_flag(source: &Path, matches: &ArgMatches) -> Result<()> {
+    if source.is_dir() && !matches.get_flag("recursive") {
+        return Err(anyhow::anyhow!("{} is a directory; use -r to process the files in it", source.display()));
+    }
+    Ok(())
+}
+
+/// Settings for a recursive encrypt/decrypt run, on top of the configured
+/// ones
+fn directory_config(matches: &ArgMatches, settings: &Config, on_conflict: OnConflict) -> Config {
+    Config {
+        output_path: matches.get_one::<String>("output").map(PathBuf::from),
+        recursive: true,
+        force: matches.get_flag("force"),
+        on_conflict,
+        secure_delete: matches.get_flag("secure"),
+        ..settings.clone()
+    }
+}
+
+/// Run `process` on every file under `source` using up to `jobs` workers,
+/// writing each result to the mirrored path under `config.output_path` (or
+/// next to the input without one), then print a summary.
+///
+/// `process` returns the status line for a file. Status lines and errors are
+/// printed in the order the files were found, however the work was scheduled.
+fn process_directory<F>(source: &Path, config: &Config, operation: Operation, jobs: usize, process: F) -> Result<()>
+where
+    F: Fn(&Path, &Path) -> Result<String> + Sync,
+{
+    let is_encrypting = matches!(operation, Operation::Encrypt(_));
+    let base = source.to_path_buf();
+    let wipe = config.wipe();
+    let (mut total, mut processed, mut skipped, mut failed) = (0, 0, 0, 0);
+    let mut work = Vec::new();
+    
+    for file in find_files_recursive(source) {
+        // Don't pick up our own output when it lives inside the source tree
+        if config.output_path.as_ref().is_some_and(|output| file.starts_with(output)) {
+            continue;
+        }
+        if !is_encrypting && !is_encrypted_file(&file) {
+            continue;
+        }
+        // The salt for keyed opaque names stays as it is
+        if file.file_name() == Some(OsStr::new(NAMING_SALT_FILE)) {
+            continue;
+        }
+        
+        total += 1;
+        if is_encrypting && is_encrypted_file(&file) {
+            println!("Skipping already encrypted file: {}", file.display());
+            skipped += 1;
+            continue;
         }
+        
+        let output = config.get_relative_output_path(&file, &base, operation);
+        work.push((file, output));
     }
-}
\ No newline at end of file
+    
+    // Originals are shredded by the same worker, right after their output
+    // has been written
+    parallel::for_each_ordered(&work, jobs, |(file, output)| {
+        let mut log = DeleteLog::default();
+        let result = process(file, output).map(|status| {
+            let deleted = config.secure_delete.then(|| secure_delete_logged(file, &wipe, &mut log));
+            (status, deleted)
+        });
+        (result, log)
+    }, |(file, _), (result, mut log)| {
+        match result {
+            Ok((status, deleted)) => {
+                println!("{}", status);
+                print_delete_log(&mut log, false);
+                if let Some(Err(e)) = deleted {
+                    ui::display_error(&format!("Failed to securely delete {}: {:#}", file.display(), e));
+                    failed += 1;
+                } else {
+                    processed += 1;
+                }
+            }
+            Err(e) => match skipped_output(&e, config.conflict_policy()) {
+                Some(existing) => {
+                    println!("Skipping existing output: {}", existing.display());
+                    skipped += 1;
+                }
+                None => {
+                    ui::display_error(&format!("{}: {:#}", file.display(), e));
+                    failed += 1;
+                }
+            },
+        }
+    });
+    
+    if config.secure_delete {
+        remove_emptied_folders(source, config);
+    }
+    
+    let summary = if is_encrypting { "Encryption" } else { "Decryption" };
+    ui::display_summary(summary, total, processed, skipped, failed);
+    
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} failed for {} file(s)", summary, failed));
+    }
+    Ok(())
+}
+
+/// Print an `inspect` report in human-readable form
+fn print_container_info(path: &str, info: &ContainerInfo) {
+    println!("File:           {}", path);
+    match info.version {
+        Some(version) => println!("Format:         {} (version {})", info.format, version),
+        None => println!("Format:         {}", info.format),
+    }
+    if let Some(cipher) = info.cipher {
+        println!("Cipher:         {}", cipher);
+    }
+    if let Some(kdf) = &info.kdf {
+        println!("KDF:            {}", kdf.describe());
+    }
+    if !info.recipients.is_empty() {
+        println!("Recipients:     {}", info.recipients.len());
+        for (index, recipient) in info.recipients.iter().enumerate() {
+            match &recipient.kdf {
+                Some(kdf) => println!("  #{:<3} {} ({})", index, recipient.kind, kdf.describe()),
+                None => println!("  #{:<3} {}", index, recipient.kind),
+            }
+        }
+    }
+    if let Some(chunk_size) = info.chunk_size {
+        println!("Chunk size:     {} bytes", chunk_size);
+    }
+    if let Some(payload) = info.payload {
+        println!("Payload:        {}", payload.as_str());
+    }
+    if let Some(compression) = info.compression {
+        println!("Compression:    {}", compression.as_str());
+    }
+    if info.name_hidden {
+        println!("File name:      stored encrypted in the header");
+    }
+    if info.metadata_stored {
+        println!("Metadata:       stored encrypted in the header");
+    }
+    if let Some(size) = info.original_size {
+        println!("Original size:  {} bytes", size);
+    }
+    println!("File size:      {} bytes", info.file_size);
+    if let Some(note) = &info.note {
+        println!("Note:           {}", note);
+    }
+}
+
+/// Public keys given with -R, expanding recipient files
+fn load_recipients(matches: &ArgMatches) -> Result<Vec<Recipient>> {
+    let mut recipients = Vec::new();
+    if let Some(args) = matches.get_many::<String>("recipient") {
+        for arg in args {
+            recipients.extend(X25519Recipient::from_arg(arg)?.into_iter().map(Recipient::X25519));
+        }
+    }
+    Ok(recipients)
+}
+
+/// Identity files given with -i, plus a passphrase if one was given or no
+/// identity was
+fn load_identities(matches: &ArgMatches) -> Result<Vec<Identity>> {
+    let mut identities = Vec::new();
+    if let Some(paths) = matches.get_many::<String>("identity") {
+        for path in paths {
+            identities.extend(X25519Identity::load(Path::new(path))?.into_iter().map(Identity::X25519));
+        }
+    }
+    
+    let keys = keys_from_args(matches, &KEY_ARGS)?;
+    if keys.is_empty() && identities.is_empty() {
+        let key = prompt_for_key("Enter encryption key", false)?;
+        identities.push(Identity::passphrase(&key));
+    }
+    identities.extend(keys.iter().map(|key| Identity::passphrase(key)));
+    Ok(identities)
+}
+
+/// Argument ids for a key and the sources it can be read from
+struct KeyArgs {
+    key: &'static str,
+    file: &'static str,
+    env: &'static str,
+    fd: &'static str,
+}
+
+const KEY_ARGS: KeyArgs = KeyArgs {
+    key: "key",
+    file: "key-file",
+    env: "key-env",
+    fd: "key-fd",
+};
+
+const NEW_KEY_ARGS: KeyArgs = KeyArgs {
+    key: "new-key",
+    file: "new-key-file",
+    env: "new-key-env",
+    fd: "new-key-fd",
+};
+
+/// Alternatives to passing a key as an argument, where it would show up in
+/// shell history and `ps` output
+fn key_source_args(ids: &KeyArgs) -> [Arg; 3] {
+    [
+        Arg::new(ids.file)
+            .long(ids.file)
+            .help("Read the key from a file (a trailing newline is ignored)")
+            .num_args(1)
+            .value_name("PATH")
+            .conflicts_with_all([ids.key, ids.env, ids.fd]),
+        Arg::new(ids.env)
+            .long(ids.env)
+            .help("Read the key from an environment variable")
+            .num_args(1)
+            .value_name("VAR")
+            .conflicts_with_all([ids.key, ids.fd]),
+        Arg::new(ids.fd)
+            .long(ids.fd)
+            .help("Read the key from an open file descriptor (Unix only)")
+            .num_args(1)
+            .value_name("N")
+            .value_parser(clap::value_parser!(i32).range(0..))
+            .conflicts_with(ids.key),
+    ]
+}
+
+/// Every key given on the command line, from the key argument itself or
+/// one of its sources
+fn keys_from_args(matches: &ArgMatches, ids: &KeyArgs) -> Result<Vec<Zeroizing<Vec<u8>>>> {
+    let mut keys: Vec<Zeroizing<Vec<u8>>> = matches.get_many::<String>(ids.key)
+        .into_iter()
+        .flatten()
+        .map(|key| Zeroizing::new(key.as_bytes().to_vec()))
+        .collect();
+    
+    if let Some(path) = matches.get_one::<String>(ids.file) {
+        let contents = Zeroizing::new(fs::read(path)
+            .with_context(|| format!("Failed to read key file: {}", path))?);
+        keys.push(trim_key(&contents));
+    }
+    if let Some(var) = matches.get_one::<String>(ids.env) {
+        let value = Zeroizing::new(env::var(var)
+            .map_err(|_| anyhow::anyhow!("Environment variable {} is not set or not valid UTF-8", var))?);
+        keys.push(Zeroizing::new(value.as_bytes().to_vec()));
+    }
+    if let Some(&fd) = matches.get_one::<i32>(ids.fd) {
+        keys.push(read_key_fd(fd)?);
+    }
+    
+    if keys.iter().any(|key| key.is_empty()) {
+        return Err(anyhow::anyhow!("Empty key not allowed"));
+    }
+    Ok(keys)
+}
+
+/// Get encryption key from the command line or prompt user
+fn get_encryption_key(
+    matches: &ArgMatches,
+    ids: &KeyArgs,
+    prompt: &str,
+    confirm: bool,
+) -> Result<Zeroizing<Vec<u8>>> {
+    match keys_from_args(matches, ids)?.pop() {
+        Some(key) => Ok(key),
+        None => prompt_for_key(prompt, confirm),
+    }
+}
+
+/// Ask for a key without echoing it, twice if `confirm` is set
+fn prompt_for_key(prompt: &str, confirm: bool) -> Result<Zeroizing<Vec<u8>>> {
+    let mut input = Password::new().with_prompt(prompt);
+    if confirm {
+        input = input.with_confirmation("Confirm key", "Keys do not match, try again");
+    }
+    
+    let key = Zeroizing::new(input.interact()
+        .with_context(|| "Can't prompt for a key here; use --key-file, --key-env or --key-fd")?);
+    Ok(Zeroizing::new(key.as_bytes().to_vec()))
+}
+
+/// Drop a single trailing newline, as left by `echo` or an editor
+fn trim_key(contents: &[u8]) -> Zeroizing<Vec<u8>> {
+    let key = contents.strip_suffix(b"\n").unwrap_or(contents);
+    let key = key.strip_suffix(b"\r").unwrap_or(key);
+    Zeroizing::new(key.to_vec())
+}
+
+#[cfg(unix)]
+fn read_key_fd(fd: i32) -> Result<Zeroizing<Vec<u8>>> {
+    use std::io::Read;
+    use std::os::fd::BorrowedFd;
+    
+    // 0-2 carry the data and the messages; never read a key from them
+    if fd < 3 {
+        return Err(anyhow::anyhow!("--key-fd {} is stdin, stdout or stderr; pass a descriptor of 3 or above", fd));
+    }
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("--key-fd {} is not an open file descriptor", fd));
+    }
+    
+    // Read through a duplicate so the caller's descriptor stays open
+    let owned = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()
+        .with_context(|| format!("Failed to duplicate file descriptor {}", fd))?;
+    let mut file = fs::File::from(owned);
+    let mut contents = Zeroizing::new(Vec::with_capacity(1024));
+    file.read_to_end(&mut contents)
+        .with_context(|| format!("Failed to read key from file descriptor {}", fd))?;
+    Ok(trim_key(&contents))
+}
+
+#[cfg(not(unix))]
+fn read_key_fd(_fd: i32) -> Result<Zeroizing<Vec<u8>>> {
+    Err(anyhow::anyhow!("--key-fd is only supported on Unix"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A command with `-k` and its sources, as encrypt has them
+    fn key_command() -> Command {
+        Command::new("test")
+            .arg(Arg::new("key").short('k').num_args(1).action(ArgAction::Append))
+            .args(key_source_args(&KEY_ARGS))
+    }
+
+    fn keys(args: &[&str]) -> Result<Vec<Vec<u8>>> {
+        let matches = key_command().try_get_matches_from(std::iter::once("test").chain(args.iter().copied()))?;
+        Ok(keys_from_args(&matches, &KEY_ARGS)?.iter().map(|key| key.to_vec()).collect())
+    }
+
+    #[test]
+    fn only_one_trailing_newline_is_trimmed() {
+        assert_eq!(*trim_key(b"secret\n"), b"secret");
+        assert_eq!(*trim_key(b"secret\r\n"), b"secret");
+        assert_eq!(*trim_key(b"secret\n\n"), b"secret\n");
+        assert_eq!(*trim_key(b" secret "), b" secret ");
+        assert_eq!(*trim_key(b"\n"), b"");
+        assert_eq!(*trim_key(b""), b"");
+    }
+
+    #[test]
+    fn keys_are_read_from_their_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("key");
+        let path = file.to_str().unwrap();
+
+        assert_eq!(keys(&[]).unwrap(), Vec::<Vec<u8>>::new());
+        assert_eq!(keys(&["-k", "a", "-k", "b"]).unwrap(), [b"a", b"b"]);
+
+        fs::write(&file, "from file\n").unwrap();
+        assert_eq!(keys(&["--key-file", path]).unwrap(), [b"from file"]);
+
+        fs::write(&file, "\n").unwrap();
+        assert!(keys(&["--key-file", path]).is_err());
+        assert!(keys(&["--key-file", dir.path().join("missing").to_str().unwrap()]).is_err());
+        assert!(keys(&["--key-env", "FILE_UTILS_I_TEST_UNSET_KEY"]).is_err());
+        assert!(keys(&["--key-fd", "0"]).is_err());
+    }
+
+    #[test]
+    fn key_sources_conflict_with_each_other() {
+        for args in [
+            ["-k", "a", "--key-file", "f"],
+            ["-k", "a", "--key-env", "V"],
+            ["-k", "a", "--key-fd", "3"],
+            ["--key-file", "f", "--key-env", "V"],
+            ["--key-file", "f", "--key-fd", "3"],
+            ["--key-env", "V", "--key-fd", "3"],
+        ] {
+            let error = key_command().try_get_matches_from(std::iter::once("test").chain(args)).unwrap_err();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict, "{:?}", args);
+        }
+    }
+}
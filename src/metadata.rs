@@ -0,0 +1,394 @@
+// src/metadata.rs - File metadata sealed into headers and restored on decrypt
+use std::fs::{self, File, FileTimes};
+use std::path::Path;
+use std::time::SystemTime;
+use crate::error::{Context, Error, Result};
+
+use crate::archive::{decode_time, encode_time};
+use crate::container::Header;
+use crate::crypto::{open_with_subkey, seal_with_subkey};
+
+// Domain separation for the key that seals the metadata in the header
+const METADATA_SEAL_INFO: &[u8] = b"file-utils-i metadata seal";
+
+// Layout of the sealed record: `METADATA_VERSION (u8) || flags (u8) ||
+// mode (u32 LE) || uid (u32 LE) || gid (u32 LE) || mtime || atime ||
+// xattr_count (u16 LE)`, then `name_len (u16 LE) || name || value_len
+// (u32 LE) || value` per extended attribute. Times are `secs (i64 LE) ||
+// nanos (u32 LE)`.
+const METADATA_VERSION: u8 = 1;
+
+// Bits of the flags byte
+const HAS_OWNER: u8 = 1;
+const HAS_ACCESSED: u8 = 2;
+
+// Headers are capped at 64 KiB, so large extended attributes are left out
+const MAX_XATTR_BYTES: usize = 32 * 1024;
+
+// Extended attribute namespaces that grant privileges or belong to the
+// kernel (file capabilities, SELinux labels, ...); these are never put back
+const PRIVILEGED_XATTR_PREFIXES: [&[u8]; 2] = [b"security.", b"trusted."];
+
+/// Permissions, ownership, timestamps and extended attributes of a file
+#[derive(Debug, Clone)]
+pub struct FileMetadata {
+    /// Unix permission bits; on Windows only the read-only flag is kept
+    pub mode: u32,
+    /// User and group id (Unix only)
+    pub owner: Option<(u32, u32)>,
+    pub modified: SystemTime,
+    pub accessed: Option<SystemTime>,
+    /// Extended attribute names and values (Unix only)
+    pub xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Which kinds of stored metadata to put back on a decrypted file
+#[derive(Debug, Clone, Copy)]
+pub struct RestoreMetadata {
+    pub permissions: bool,
+    /// Only works where the user may change owners, usually as root;
+    /// otherwise the owner is left alone
+    pub ownership: bool,
+    pub timestamps: bool,
+    pub xattrs: bool,
+}
+
+impl Default for RestoreMetadata {
+    fn default() -> Self {
+        Self {
+            permissions: true,
+            ownership: true,
+            timestamps: true,
+            xattrs: true,
+        }
+    }
+}
+
+impl FileMetadata {
+    /// Collect the metadata of `path`, following symlinks
+    pub fn read_from(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)
+            .with_context(|| format!("Failed to read metadata for: {}", path.display()))?;
+
+        #[cfg(unix)]
+        let (mode, owner) = {
+            use std::os::unix::fs::MetadataExt;
+            (metadata.mode() & 0o7777, Some((metadata.uid(), metadata.gid())))
+        };
+        #[cfg(not(unix))]
+        let (mode, owner) = (if metadata.permissions().readonly() { 0o444 } else { 0o644 }, None);
+
+        Ok(Self {
+            mode,
+            owner,
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            accessed: metadata.accessed().ok(),
+            xattrs: read_xattrs(path),
+        })
+    }
+
+    /// Put the selected kinds of metadata back on `file`, an open handle to
+    /// the fully written output at `path`.
+    ///
+    /// Setuid and setgid bits are not restored. An owner that can't be set
+    /// or extended attributes the filesystem refuses are logged and skipped.
+    pub fn apply_to(&self, file: &File, path: &Path, restore: &RestoreMetadata) -> Result<()> {
+        // Changing the owner clears the setuid bits, so it goes first
+        #[cfg(unix)]
+        if let (true, Some((uid, gid))) = (restore.ownership, self.owner) {
+            if let Err(e) = std::os::unix::fs::fchown(file, Some(uid), Some(gid)) {
+                if e.kind() != std::io::ErrorKind::PermissionDenied {
+                    log::warn!("Failed to restore the owner of {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        #[cfg(unix)]
+        if restore.xattrs && !self.xattrs.is_empty() {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+            use xattr::FileExt;
+
+            let restorable: Vec<_> = self.xattrs.iter()
+                .filter(|(name, _)| is_restorable_xattr(name))
+                .collect();
+            let failed = restorable.iter()
+                .filter(|(name, value)| file.set_xattr(OsStr::from_bytes(name), value).is_err())
+                .count();
+            if failed > 0 {
+                log::warn!("Failed to restore {} of {} extended attributes on {}",
+                           failed, restorable.len(), path.display());
+            }
+        }
+
+        if restore.permissions {
+            #[cfg(unix)]
+            let permissions = {
+                use std::os::unix::fs::PermissionsExt;
+                fs::Permissions::from_mode(self.mode & 0o1777)
+            };
+            #[cfg(not(unix))]
+            let permissions = {
+                let mut permissions = file.metadata()?.permissions();
+                permissions.set_readonly(self.mode & 0o200 == 0);
+                permissions
+            };
+            file.set_permissions(permissions)
+                .with_context(|| format!("Failed to set permissions on: {}", path.display()))?;
+        }
+
+        // Last, since writing to the file would move the times again
+        if restore.timestamps {
+            let mut times = FileTimes::new().set_modified(self.modified);
+            if let Some(accessed) = self.accessed {
+                times = times.set_accessed(accessed);
+            }
+            file.set_times(times)
+                .with_context(|| format!("Failed to set timestamps on: {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.owner.is_some() {
+            flags |= HAS_OWNER;
+        }
+        if self.accessed.is_some() {
+            flags |= HAS_ACCESSED;
+        }
+        let (uid, gid) = self.owner.unwrap_or_default();
+
+        let mut out = vec![METADATA_VERSION, flags];
+        out.extend_from_slice(&self.mode.to_le_bytes());
+        out.extend_from_slice(&uid.to_le_bytes());
+        out.extend_from_slice(&gid.to_le_bytes());
+        for time in [self.modified, self.accessed.unwrap_or(SystemTime::UNIX_EPOCH)] {
+            let (secs, nanos) = encode_time(time);
+            out.extend_from_slice(&secs.to_le_bytes());
+            out.extend_from_slice(&nanos.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.xattrs.len() as u16).to_le_bytes());
+        for (name, value) in &self.xattrs {
+            out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            out.extend_from_slice(name);
+            out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            out.extend_from_slice(value);
+        }
+        out
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        let mut rest = data;
+        let version = take::<1>(&mut rest)?[0];
+        if version != METADATA_VERSION {
+            return Err(Error::UnsupportedFormat(format!("Unsupported metadata version {}", version)));
+        }
+        let flags = take::<1>(&mut rest)?[0];
+        let mode = u32::from_le_bytes(take(&mut rest)?);
+        let uid = u32::from_le_bytes(take(&mut rest)?);
+        let gid = u32::from_le_bytes(take(&mut rest)?);
+
+        let mut times = [SystemTime::UNIX_EPOCH; 2];
+        for time in &mut times {
+            let secs = i64::from_le_bytes(take(&mut rest)?);
+            let nanos = u32::from_le_bytes(take(&mut rest)?);
+            *time = decode_time(secs, nanos)
+                .ok_or_else(|| Error::UnsupportedFormat("Invalid timestamp in stored metadata".into()))?;
+        }
+
+        let count = u16::from_le_bytes(take(&mut rest)?);
+        let mut xattrs = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let name_len = u16::from_le_bytes(take(&mut rest)?) as usize;
+            let name = take_slice(&mut rest, name_len)?.to_vec();
+            let value_len = u32::from_le_bytes(take(&mut rest)?) as usize;
+            let value = take_slice(&mut rest, value_len)?.to_vec();
+            xattrs.push((name, value));
+        }
+        if !rest.is_empty() {
+            return Err(Error::UnsupportedFormat("Trailing bytes in stored metadata".into()));
+        }
+
+        Ok(Self {
+            mode: mode & 0o7777,
+            owner: (flags & HAS_OWNER != 0).then_some((uid, gid)),
+            modified: times[0],
+            accessed: (flags & HAS_ACCESSED != 0).then_some(times[1]),
+            xattrs,
+        })
+    }
+}
+
+/// Encrypt `metadata` for `header` under a key derived from the file key
+pub fn seal_metadata(header: &Header, file_key: &[u8; 32], metadata: &FileMetadata) -> Result<Vec<u8>> {
+    seal_with_subkey(header.mode, file_key, METADATA_SEAL_INFO, &metadata.encode())
+}
+
+/// The file metadata stored in `header`, if it has any
+pub fn open_metadata(header: &Header, file_key: &[u8; 32]) -> Result<Option<FileMetadata>> {
+    let Some(sealed) = &header.sealed_metadata else {
+        return Ok(None);
+    };
+
+    let encoded = open_with_subkey(header.mode, file_key, METADATA_SEAL_INFO, sealed)
+        .map_err(|_| Error::Tampered("Stored file metadata failed authentication".into()))?;
+    FileMetadata::decode(&encoded).map(Some)
+}
+
+/// Whether an extended attribute may be put back on a decrypted file.
+/// Privileged ones are left out, so a header can't hand out capabilities.
+fn is_restorable_xattr(name: &[u8]) -> bool {
+    !PRIVILEGED_XATTR_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// Extended attributes of `path`, as far as they fit in a header
+#[cfg(unix)]
+fn read_xattrs(path: &Path) -> Vec<(Vec<u8>, Vec<u8>)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(names) = xattr::list(path) else {
+        return Vec::new();
+    };
+    let mut xattrs = Vec::new();
+    let mut total = 0;
+    for name in names {
+        let Ok(Some(value)) = xattr::get(path, &name) else {
+            continue;
+        };
+        let name = name.as_bytes().to_vec();
+        // Each entry is stored as name length, name, value length, value
+        total += 2 + name.len() + 4 + value.len();
+        if total > MAX_XATTR_BYTES {
+            log::warn!("Extended attributes of {} are too large to store; some were left out", path.display());
+            break;
+        }
+        xattrs.push((name, value));
+    }
+    xattrs
+}
+
+#[cfg(not(unix))]
+fn read_xattrs(_path: &Path) -> Vec<(Vec<u8>, Vec<u8>)> {
+    Vec::new()
+}
+
+fn take<const N: usize>(rest: &mut &[u8]) -> Result<[u8; N]> {
+    Ok(take_slice(rest, N)?.try_into().unwrap())
+}
+
+fn take_slice<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if rest.len() < len {
+        return Err(Error::UnsupportedFormat("Stored metadata is truncated".into()));
+    }
+    let (head, tail) = rest.split_at(len);
+    *rest = tail;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::container::KeySource;
+    use crate::crypto::CryptoMode;
+
+    fn sample() -> FileMetadata {
+        FileMetadata {
+            mode: 0o640,
+            owner: Some((1000, 100)),
+            modified: SystemTime::UNIX_EPOCH - Duration::new(86_400, 500),
+            accessed: None,
+            xattrs: vec![(b"user.comment".to_vec(), b"kept".to_vec())],
+        }
+    }
+
+    #[test]
+    fn sealed_metadata_round_trips_and_is_authenticated() {
+        let key = [7u8; 32];
+        let mut header = Header::new(CryptoMode::XChaCha20Poly1305, KeySource::Recipients(Vec::new())).unwrap();
+        assert!(open_metadata(&header, &key).unwrap().is_none());
+
+        header.sealed_metadata = Some(seal_metadata(&header, &key, &sample()).unwrap());
+        let opened = open_metadata(&header, &key).unwrap().unwrap();
+        assert_eq!(opened.mode, 0o640);
+        assert_eq!(opened.owner, Some((1000, 100)));
+        assert_eq!(opened.modified, sample().modified);
+        assert_eq!(opened.accessed, None);
+        assert_eq!(opened.xattrs, sample().xattrs);
+
+        assert!(matches!(open_metadata(&header, &[8u8; 32]), Err(Error::Tampered(_))));
+        header.sealed_metadata.as_mut().unwrap()[3] ^= 1;
+        assert!(matches!(open_metadata(&header, &key), Err(Error::Tampered(_))));
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        let encoded = sample().encode();
+        for len in 0..encoded.len() {
+            assert!(matches!(FileMetadata::decode(&encoded[..len]), Err(Error::UnsupportedFormat(_))));
+        }
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(matches!(FileMetadata::decode(&trailing), Err(Error::UnsupportedFormat(_))));
+        let mut version = encoded;
+        version[0] = METADATA_VERSION + 1;
+        assert!(matches!(FileMetadata::decode(&version), Err(Error::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn metadata_is_read_and_put_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        fs::write(&source, b"source").unwrap();
+        fs::write(&target, b"target").unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options().write(true).open(&source).unwrap()
+            .set_times(FileTimes::new().set_modified(modified)).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&source, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let metadata = FileMetadata::read_from(&source).unwrap();
+        assert_eq!(metadata.modified, modified);
+
+        let file = File::options().write(true).open(&target).unwrap();
+        metadata.apply_to(&file, &target, &RestoreMetadata::default()).unwrap();
+        drop(file);
+        assert_eq!(fs::metadata(&target).unwrap().modified().unwrap(), modified);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o7777, 0o640);
+        }
+    }
+
+    #[test]
+    fn privileged_xattrs_are_never_restored() {
+        assert!(is_restorable_xattr(b"user.comment"));
+        assert!(is_restorable_xattr(b"system.posix_acl_access"));
+        assert!(!is_restorable_xattr(b"security.capability"));
+        assert!(!is_restorable_xattr(b"security.selinux"));
+        assert!(!is_restorable_xattr(b"trusted.overlay.opaque"));
+
+        #[cfg(unix)]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let target = dir.path().join("target");
+            fs::write(&target, b"target").unwrap();
+            // A valid (if empty) version 2 capability set
+            let mut capability = vec![0, 0, 0, 2];
+            capability.resize(20, 0);
+            let metadata = FileMetadata {
+                xattrs: vec![(b"security.capability".to_vec(), capability), sample().xattrs.remove(0)],
+                ..sample()
+            };
+            let file = File::options().write(true).open(&target).unwrap();
+            metadata.apply_to(&file, &target, &RestoreMetadata::default()).unwrap();
+            assert_eq!(xattr::get(&target, "security.capability").unwrap(), None);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// src/names.rs - Opaque output names, and the real names sealed into headers
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use crate::error::{Context, Error, Result};
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::archive::{decode_path, encode_path};
+use crate::container::Header;
+use crate::crypto::{open_with_subkey, seal_with_subkey, Kdf, SALT_LEN};
+
+// Domain separation for the key that seals the real name in the header
+const NAME_SEAL_INFO: &[u8] = b"file-utils-i file name seal";
+
+/// File in the root of an output tree holding the random salt for keyed
+/// names, so a passphrase always yields the same names within that tree but
+/// nothing can be precomputed across trees
+pub const NAMING_SALT_FILE: &str = ".fui-names";
+
+// Random bytes (or MAC bytes) in an opaque name, before hex encoding
+const OPAQUE_ID_LEN: usize = 16;
+
+/// How `--hide-names` picks output names
+pub enum NameHiding {
+    /// A fresh random name for every file
+    Random,
+    /// A MAC of the real name, so re-encrypting a file gives it the same
+    /// opaque name
+    Keyed(Zeroizing<[u8; 32]>),
+}
+
+impl NameHiding {
+    /// Keyed names derived from a passphrase and the salt kept in `root`,
+    /// which is created on first use
+    pub fn keyed(passphrase: &[u8], kdf: Kdf, root: &Path) -> Result<Self> {
+        Ok(NameHiding::Keyed(kdf.derive_key(passphrase, &naming_salt(root)?)?))
+    }
+
+    /// `<32 hex digits>.enc` to store a file called `name` under
+    pub fn opaque_name(&self, name: &OsStr) -> Result<OsString> {
+        let mut id = [0u8; OPAQUE_ID_LEN];
+        match self {
+            NameHiding::Random => OsRng.fill_bytes(&mut id),
+            NameHiding::Keyed(key) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&key[..])
+                    .map_err(|_| Error::InvalidInput("Invalid naming key".into()))?;
+                mac.update(&encode_path(Path::new(name))?);
+                id.copy_from_slice(&mac.finalize().into_bytes()[..OPAQUE_ID_LEN]);
+            }
+        }
+        Ok(OsString::from(format!("{}.enc", hex::encode(id))))
+    }
+}
+
+/// The salt in `root`'s salt file, writing a fresh random one if there's none
+fn naming_salt(root: &Path) -> Result<[u8; SALT_LEN]> {
+    let path = root.join(NAMING_SALT_FILE);
+    match fs::read_to_string(&path) {
+        Ok(contents) => {
+            let mut salt = [0u8; SALT_LEN];
+            hex::decode_to_slice(contents.trim(), &mut salt)
+                .map_err(|_| Error::UnsupportedFormat(format!("Not a naming salt: {}", path.display())))?;
+            Ok(salt)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            fs::create_dir_all(root)
+                .with_context(|| format!("Failed to create directory: {}", root.display()))?;
+            let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => file,
+                // Another run got there first; use its salt
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return naming_salt(root),
+                Err(e) => return Err(e).with_context(|| format!("Failed to create naming salt: {}", path.display())),
+            };
+            writeln!(file, "{}", hex::encode(salt))
+                .with_context(|| format!("Failed to write naming salt: {}", path.display()))?;
+            Ok(salt)
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to read naming salt: {}", path.display())),
+    }
+}
+
+/// Encrypt a file name for `header` under a key derived from the file key
+pub fn seal_name(header: &Header, file_key: &[u8; 32], name: &OsStr) -> Result<Vec<u8>> {
+    seal_with_subkey(header.mode, file_key, NAME_SEAL_INFO, &encode_path(Path::new(name))?)
+}
+
+/// The real file name stored in `header`, if it has one. Names that aren't
+/// a single plain file name are rejected, so they can't point elsewhere.
+pub fn open_name(header: &Header, file_key: &[u8; 32]) -> Result<Option<OsString>> {
+    let Some(sealed) = &header.sealed_name else {
+        return Ok(None);
+    };
+
+    let name = open_with_subkey(header.mode, file_key, NAME_SEAL_INFO, sealed)
+        .map_err(|_| Error::Tampered("Stored file name failed authentication".into()))?;
+
+    let path = decode_path(&name)?;
+    match path.file_name() {
+        Some(file_name) if file_name == path.as_os_str() => Ok(Some(file_name.to_os_string())),
+        _ => Err(Error::UnsupportedFormat("Stored file name is not a plain file name".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::KeySource;
+    use crate::crypto::CryptoMode;
+
+    const FAST_KDF: Kdf = Kdf::Pbkdf2Sha256 { iterations: 1 };
+
+    #[test]
+    fn sealed_names_round_trip_and_are_authenticated() {
+        let key = [7u8; 32];
+        for mode in [CryptoMode::Aes256Gcm, CryptoMode::XChaCha20Poly1305] {
+            let mut header = Header::new(mode, KeySource::Recipients(Vec::new())).unwrap();
+            assert_eq!(open_name(&header, &key).unwrap(), None);
+
+            header.sealed_name = Some(seal_name(&header, &key, OsStr::new("report.pdf")).unwrap());
+            assert_eq!(open_name(&header, &key).unwrap().unwrap(), "report.pdf");
+            assert!(matches!(open_name(&header, &[8u8; 32]), Err(Error::Tampered(_))));
+
+            header.sealed_name.as_mut().unwrap()[0] ^= 1;
+            assert!(matches!(open_name(&header, &key), Err(Error::Tampered(_))));
+        }
+    }
+
+    #[test]
+    fn stored_names_must_be_plain_file_names() {
+        let key = [7u8; 32];
+        let mut header = Header::new(CryptoMode::XChaCha20Poly1305, KeySource::Recipients(Vec::new())).unwrap();
+        assert!(seal_name(&header, &key, OsStr::new("../escape")).is_err());
+
+        header.sealed_name = Some(seal_name(&header, &key, OsStr::new("dir/file")).unwrap());
+        assert!(matches!(open_name(&header, &key), Err(Error::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn keyed_names_are_stable_and_random_names_are_not() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = OsStr::new("notes.txt");
+        let keyed = NameHiding::keyed(b"pw", FAST_KDF, dir.path()).unwrap();
+        let opaque = keyed.opaque_name(name).unwrap();
+        assert_eq!(opaque.len(), 2 * OPAQUE_ID_LEN + ".enc".len());
+        assert_eq!(keyed.opaque_name(name).unwrap(), opaque);
+        assert_eq!(NameHiding::keyed(b"pw", FAST_KDF, dir.path()).unwrap().opaque_name(name).unwrap(), opaque);
+        assert_ne!(keyed.opaque_name(OsStr::new("other.txt")).unwrap(), opaque);
+        assert_ne!(NameHiding::keyed(b"other", FAST_KDF, dir.path()).unwrap().opaque_name(name).unwrap(), opaque);
+
+        assert_ne!(NameHiding::Random.opaque_name(name).unwrap(), NameHiding::Random.opaque_name(name).unwrap());
+    }
+
+    #[test]
+    fn every_tree_gets_its_own_naming_salt() {
+        let (first, second) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let name = OsStr::new("notes.txt");
+        let opaque = |root: &Path| NameHiding::keyed(b"pw", FAST_KDF, root).unwrap().opaque_name(name).unwrap();
+        assert_ne!(opaque(first.path()), opaque(second.path()));
+
+        let salt = fs::read_to_string(first.path().join(NAMING_SALT_FILE)).unwrap();
+        assert_eq!(salt.trim().len(), 2 * SALT_LEN);
+
+        fs::write(second.path().join(NAMING_SALT_FILE), "not hex").unwrap();
+        assert!(matches!(NameHiding::keyed(b"pw", FAST_KDF, second.path()), Err(Error::UnsupportedFormat(_))));
+    }
+}
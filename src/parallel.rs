@@ -0,0 +1,75 @@
+// src/parallel.rs - Small worker pool that keeps results in input order
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// Number of worker threads to use when none was requested
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Run `task` on every item using up to `jobs` threads, and hand each result
+/// to `done` in input order as soon as it and all earlier ones are ready.
+///
+/// `done` runs on the calling thread, so anything it prints comes out in
+/// the same order however the work was scheduled.
+pub fn for_each_ordered<T, R, F, D>(items: &[T], jobs: usize, task: F, mut done: D)
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+    D: FnMut(&T, R),
+{
+    let jobs = jobs.clamp(1, items.len().max(1));
+    if jobs == 1 {
+        for item in items {
+            let result = task(item);
+            done(item, result);
+        }
+        return;
+    }
+
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let (next, task) = (&next, &task);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                if tx.send((index, task(item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        // Hold back results that finish early until their turn comes
+        let mut pending = BTreeMap::new();
+        let mut emitted = 0;
+        for (index, result) in rx {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&emitted) {
+                done(&items[emitted], result);
+                emitted += 1;
+            }
+        }
+    });
+}
+
+/// Apply `task` to every item on up to `jobs` threads, returning the results
+/// in input order
+pub fn map_ordered<T, R, F>(items: &[T], jobs: usize, task: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let mut results = Vec::with_capacity(items.len());
+    for_each_ordered(items, jobs, task, |_, result| results.push(result));
+    results
+}
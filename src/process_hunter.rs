@@ -1,13 +1,15 @@
 // src/process_hunter.rs - PROCWOLF process hunting and termination
 use std::path::Path;
-use anyhow::Result;
-use std::io::{self, Write};
 
 #[cfg(windows)]
 use std::ptr;
 #[cfg(windows)]
 use std::mem;
 
+#[cfg(windows)]
+use crate::error::Context;
+use crate::error::{Error, Result};
+#[cfg(windows)]
 use crate::file_operations::test_file_access;
 
 // Windows-specific constants and types
@@ -77,7 +79,6 @@ extern "system" {
 const INVALID_HANDLE_VALUE: Handle = (-1isize) as Handle;
 
 /// Process information structure
-#[cfg(windows)]
 #[derive(Debug, Clone)]
 pub struct ProcessInfo {
     pub pid: u32,
@@ -86,6 +87,41 @@ pub struct ProcessInfo {
     pub parent_pid: u32,
 }
 
+impl ProcessInfo {
+    /// Whether the name or path contains `pattern`, ignoring case
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.to_lowercase();
+        self.name.to_lowercase().contains(&pattern)
+            || self.path.as_ref().is_some_and(|path| path.to_lowercase().contains(&pattern))
+    }
+    
+    /// Whether this looks like a process Windows can't run without
+    pub fn is_critical(&self) -> bool {
+        let name = self.name.to_lowercase();
+        ["system", "csrss", "winlogon", "services"].iter().any(|critical| name.contains(critical))
+    }
+}
+
+/// Which step of `emergency_terminate` brought the process down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    Graceful,
+    Forced,
+    /// Terminated after its threads had been suspended
+    Suspended,
+}
+
+/// What `procwolf_status` found out about the system
+#[derive(Debug, Clone)]
+pub struct ProcwolfStatus {
+    pub is_admin: bool,
+    pub pid: u32,
+    pub processes: usize,
+    /// Rough split by name (system, svchost, csrss)
+    pub system_processes: usize,
+    pub user_processes: usize,
+}
+
 /// Convert wide string to regular string
 #[cfg(windows)]
 fn wide_string_to_string(wide: &[u16]) -> String {
@@ -100,7 +136,7 @@ fn enumerate_processes() -> Result<Vec<ProcessInfo>> {
     
     let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) };
     if snapshot == INVALID_HANDLE_VALUE {
-        return Err(anyhow::anyhow!("Failed to create process snapshot"));
+        return Err(std::io::Error::last_os_error()).with_context(|| "Failed to create process snapshot");
     }
     
     let mut process_entry = ProcessEntry32W {
@@ -168,7 +204,8 @@ fn kill_process_by_pid(pid: u32, force: bool) -> Result<bool> {
     
     if process_handle == ptr::null_mut() || process_handle == INVALID_HANDLE_VALUE {
         let error = unsafe { GetLastError() };
-        return Err(anyhow::anyhow!("Failed to open process {}: Error {}", pid, error));
+        return Err(std::io::Error::from_raw_os_error(error as i32))
+            .with_context(|| format!("Failed to open process {}", pid));
     }
     
     let exit_code = if force { 1 } else { 0 };
@@ -180,7 +217,8 @@ fn kill_process_by_pid(pid: u32, force: bool) -> Result<bool> {
         Ok(true)
     } else {
         let error = unsafe { GetLastError() };
-        Err(anyhow::anyhow!("Failed to terminate process {}: Error {}", pid, error))
+        Err(std::io::Error::from_raw_os_error(error as i32))
+            .with_context(|| format!("Failed to terminate process {}", pid))
     }
 }
 
@@ -189,7 +227,7 @@ fn kill_process_by_pid(pid: u32, force: bool) -> Result<bool> {
 fn suspend_process_by_pid(pid: u32) -> Result<()> {
     let thread_snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) };
     if thread_snapshot == INVALID_HANDLE_VALUE {
-        return Err(anyhow::anyhow!("Failed to create thread snapshot"));
+        return Err(std::io::Error::last_os_error()).with_context(|| "Failed to create thread snapshot");
     }
     
     let mut thread_entry = ThreadEntry32 {
@@ -226,7 +264,7 @@ fn suspend_process_by_pid(pid: u32) -> Result<()> {
     if suspended_count > 0 {
         Ok(())
     } else {
-        Err(anyhow::anyhow!("No threads found or suspended for PID: {}", pid))
+        Err(Error::InvalidInput(format!("No threads found or suspended for PID: {}", pid)))
     }
 }
 
@@ -304,16 +342,16 @@ fn find_file_lock_owners(path: &Path) -> Vec<u32> {
 /// The PROCWOLF - Attempt to terminate processes that have a file locked
 #[cfg(windows)]
 pub fn terminate_lock_owners(path: &Path) -> Result<()> {
-    println!("🐺 PROCWOLF activated - hunting file lock owners for: {}", path.display());
+    log::info!("🐺 PROCWOLF activated - hunting file lock owners for: {}", path.display());
     
     let pids = find_file_lock_owners(path);
     
     if pids.is_empty() {
-        println!("No obvious file lock owners detected");
+        log::info!("No obvious file lock owners detected");
         return Ok(());
     }
     
-    println!("Found {} potential file lock owners:", pids.len());
+    log::info!("Found {} potential file lock owners:", pids.len());
     
     // Get process details before termination
     let processes = enumerate_processes()?;
@@ -321,7 +359,7 @@ pub fn terminate_lock_owners(path: &Path) -> Result<()> {
     
     for pid in pids {
         if let Some(process) = processes.iter().find(|p| p.pid == pid) {
-            println!("  - {} (PID: {}) - Path: {:?}", 
+            log::info!("  - {} (PID: {}) - Path: {:?}", 
                      process.name, process.pid, process.path);
             targets.push(process.clone());
         }
@@ -332,11 +370,11 @@ pub fn terminate_lock_owners(path: &Path) -> Result<()> {
     }
     
     // Strategy 1: Try to suspend processes first (less aggressive)
-    println!("\n🐺 Phase 1: Attempting to suspend lock owners...");
+    log::info!("🐺 Phase 1: Attempting to suspend lock owners...");
     for process in &targets {
         match suspend_process_by_pid(process.pid) {
-            Ok(()) => println!("  ✓ Suspended: {} (PID: {})", process.name, process.pid),
-            Err(e) => eprintln!("  ✗ Failed to suspend {} (PID: {}): {}", process.name, process.pid, e),
+            Ok(()) => log::info!("  ✓ Suspended: {} (PID: {})", process.name, process.pid),
+            Err(e) => log::warn!("  ✗ Failed to suspend {} (PID: {}): {}", process.name, process.pid, e),
         }
     }
     
@@ -345,31 +383,31 @@ pub fn terminate_lock_owners(path: &Path) -> Result<()> {
     
     // Test if file is now accessible
     if test_file_access(path) {
-        println!("✓ File is now accessible after suspension - lock owners neutralized!");
+        log::info!("✓ File is now accessible after suspension - lock owners neutralized!");
         return Ok(());
     }
     
     // Strategy 2: Terminate processes (more aggressive)
-    println!("\n🐺 Phase 2: File still locked - initiating termination protocol...");
+    log::info!("🐺 Phase 2: File still locked - initiating termination protocol...");
     
     let mut terminated_count = 0;
     for process in &targets {
         // Skip certain "safer" processes in first pass
         if process.name.to_lowercase().contains("explorer") {
-            println!("  ⚠ Skipping explorer.exe (first pass) - PID: {}", process.pid);
+            log::info!("  ⚠ Skipping explorer.exe (first pass) - PID: {}", process.pid);
             continue;
         }
         
         match kill_process_by_pid(process.pid, false) {
             Ok(true) => {
-                println!("  ✓ Terminated: {} (PID: {})", process.name, process.pid);
+                log::info!("  ✓ Terminated: {} (PID: {})", process.name, process.pid);
                 terminated_count += 1;
             }
             Ok(false) => {
-                eprintln!("  ✗ Termination returned false: {} (PID: {})", process.name, process.pid);
+                log::warn!("  ✗ Termination returned false: {} (PID: {})", process.name, process.pid);
             }
             Err(e) => {
-                eprintln!("  ✗ Failed to terminate {} (PID: {}): {}", process.name, process.pid, e);
+                log::warn!("  ✗ Failed to terminate {} (PID: {}): {}", process.name, process.pid, e);
             }
         }
     }
@@ -379,31 +417,31 @@ pub fn terminate_lock_owners(path: &Path) -> Result<()> {
         std::thread::sleep(std::time::Duration::from_millis(1000));
         
         if test_file_access(path) {
-            println!("✓ File is now accessible after termination - PROCWOLF successful!");
+            log::info!("✓ File is now accessible after termination - PROCWOLF successful!");
             return Ok(());
         }
     }
     
     // Strategy 3: Nuclear option - terminate everything including explorer
-    println!("\n🐺 Phase 3: Nuclear option - terminating all remaining lock owners...");
+    log::info!("🐺 Phase 3: Nuclear option - terminating all remaining lock owners...");
     for process in &targets {
         if process.name.to_lowercase().contains("explorer") {
-            println!("  ⚠ Terminating explorer.exe - PID: {} (Windows shell will restart)", process.pid);
+            log::info!("  ⚠ Terminating explorer.exe - PID: {} (Windows shell will restart)", process.pid);
         }
         
         match kill_process_by_pid(process.pid, true) {
-            Ok(true) => println!("  ✓ Force terminated: {} (PID: {})", process.name, process.pid),
-            Ok(false) => eprintln!("  ✗ Force termination returned false: {} (PID: {})", process.name, process.pid),
-            Err(e) => eprintln!("  ✗ Failed to force terminate {} (PID: {}): {}", process.name, process.pid, e),
+            Ok(true) => log::info!("  ✓ Force terminated: {} (PID: {})", process.name, process.pid),
+            Ok(false) => log::warn!("  ✗ Force termination returned false: {} (PID: {})", process.name, process.pid),
+            Err(e) => log::warn!("  ✗ Failed to force terminate {} (PID: {}): {}", process.name, process.pid, e),
         }
     }
     
     // Final test
     std::thread::sleep(std::time::Duration::from_millis(1500));
     if test_file_access(path) {
-        println!("✓ File is now accessible - PROCWOLF mission accomplished!");
+        log::info!("✓ File is now accessible - PROCWOLF mission accomplished!");
     } else {
-        println!("⚠ File may still be locked - manual intervention may be required");
+        log::info!("⚠ File may still be locked - manual intervention may be required");
     }
     
     Ok(())
@@ -415,109 +453,68 @@ pub fn deploy_procwolf(file_path: &Path) -> Result<()> {
     terminate_lock_owners(file_path)
 }
 
-/// Show running processes that might be locking a file - diagnostic function
+/// Running processes that might be locking a file - diagnostic function
 #[cfg(windows)]
-pub fn show_potential_lock_owners(file_path: &Path) -> Result<()> {
-    println!("Scanning for potential lock owners of: {}", file_path.display());
-    
+pub fn find_potential_lock_owners(file_path: &Path) -> Result<Vec<ProcessInfo>> {
     let pids = find_file_lock_owners(file_path);
-    
     if pids.is_empty() {
-        println!("No obvious lock owners detected");
-        return Ok(());
-    }
-    
-    let processes = enumerate_processes()?;
-    
-    println!("Potential lock owners found:");
-    for pid in pids {
-        if let Some(process) = processes.iter().find(|p| p.pid == pid) {
-            println!("  PID: {} | Name: {} | Path: {:?}", 
-                     process.pid, process.name, process.path);
-        }
+        return Ok(Vec::new());
     }
     
-    Ok(())
+    Ok(enumerate_processes()?
+        .into_iter()
+        .filter(|process| pids.contains(&process.pid))
+        .collect())
 }
 
-/// Advanced process hunting by partial name match
+/// Terminate each of `targets`, returning the PIDs that went down. Failures
+/// are logged and skipped.
 #[cfg(windows)]
-pub fn hunt_and_terminate(name_pattern: &str, force: bool, dry_run: bool) -> Result<Vec<u32>> {
-    println!("🐺 PROCWOLF hunting mode - searching for: '{}'", name_pattern);
-    
-    let processes = enumerate_processes()?;
-    let mut targets = Vec::new();
-    
-    // Find matching processes
-    for process in processes {
-        let name_match = process.name.to_lowercase().contains(&name_pattern.to_lowercase());
-        let path_match = if let Some(ref path) = process.path {
-            path.to_lowercase().contains(&name_pattern.to_lowercase())
-        } else {
-            false
-        };
-        
-        if name_match || path_match {
-            targets.push(process);
-        }
-    }
-    
-    if targets.is_empty() {
-        println!("No processes found matching pattern: '{}'", name_pattern);
-        return Ok(Vec::new());
-    }
-    
-    println!("Found {} matching processes:", targets.len());
-    for target in &targets {
-        println!("  - {} (PID: {}) - Path: {:?}", target.name, target.pid, target.path);
-    }
-    
-    if dry_run {
-        println!("DRY RUN: Would terminate {} processes", targets.len());
-        return Ok(targets.iter().map(|p| p.pid).collect());
-    }
-    
-    // Confirm termination for multiple processes
-    if targets.len() > 1 {
-        print!("Terminate {} processes? [y/N]: ", targets.len());
-        io::stdout().flush().unwrap();
-        
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-        
-        if !input.trim().to_lowercase().starts_with('y') {
-            println!("Operation cancelled");
-            return Ok(Vec::new());
-        }
-    }
-    
+pub fn terminate_processes(targets: &[ProcessInfo], force: bool) -> Vec<u32> {
     let mut killed_pids = Vec::new();
     
-    for target in &targets {
+    for target in targets {
         match kill_process_by_pid(target.pid, force) {
             Ok(true) => {
-                println!("✓ Terminated: {} (PID: {})", target.name, target.pid);
+                log::info!("✓ Terminated: {} (PID: {})", target.name, target.pid);
                 killed_pids.push(target.pid);
             }
             Ok(false) => {
-                eprintln!("✗ Failed to terminate {} (PID: {})", target.name, target.pid);
+                log::warn!("✗ Failed to terminate {} (PID: {})", target.name, target.pid);
             }
             Err(e) => {
-                eprintln!("✗ Failed to terminate {} (PID: {}): {}", target.name, target.pid, e);
+                log::warn!("✗ Failed to terminate {} (PID: {}): {}", target.name, target.pid, e);
             }
         }
     }
     
-    println!("PROCWOLF hunt complete - {} processes terminated", killed_pids.len());
+    killed_pids
+}
+
+/// Advanced process hunting by partial name match. Terminates every match
+/// without asking, so callers should show `list_all_processes` first; with
+/// `dry_run` nothing is terminated and the matching PIDs are returned.
+#[cfg(windows)]
+pub fn hunt_and_terminate(name_pattern: &str, force: bool, dry_run: bool) -> Result<Vec<u32>> {
+    log::info!("🐺 PROCWOLF hunting mode - searching for: '{}'", name_pattern);
+    
+    let targets = list_all_processes(Some(name_pattern))?;
+    if dry_run {
+        return Ok(targets.iter().map(|p| p.pid).collect());
+    }
+    
+    let killed_pids = terminate_processes(&targets, force);
+    log::info!("PROCWOLF hunt complete - {} processes terminated", killed_pids.len());
     Ok(killed_pids)
 }
 
-/// Resume all threads of a previously suspended process
+/// Resume all threads of a previously suspended process, returning how many
+/// were resumed
 #[cfg(windows)]
-pub fn resume_process_by_pid(pid: u32) -> Result<()> {
+pub fn resume_process_by_pid(pid: u32) -> Result<usize> {
     let thread_snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) };
     if thread_snapshot == INVALID_HANDLE_VALUE {
-        return Err(anyhow::anyhow!("Failed to create thread snapshot"));
+        return Err(Error::InvalidInput("Failed to create thread snapshot".to_string()));
     }
     
     let mut thread_entry = ThreadEntry32 {
@@ -552,144 +549,64 @@ pub fn resume_process_by_pid(pid: u32) -> Result<()> {
     unsafe { CloseHandle(thread_snapshot) };
     
     if resumed_count > 0 {
-        println!("Resumed {} threads for process PID: {}", resumed_count, pid);
-        Ok(())
+        Ok(resumed_count)
     } else {
-        Err(anyhow::anyhow!("No threads found or resumed for PID: {}", pid))
+        Err(Error::InvalidInput(format!("No threads found or resumed for PID: {}", pid)))
     }
 }
 
-/// List all running processes with detailed information
+/// All running processes, or those whose name or path contains `filter`
 #[cfg(windows)]
-pub fn list_all_processes(filter: Option<&str>) -> Result<()> {
+pub fn list_all_processes(filter: Option<&str>) -> Result<Vec<ProcessInfo>> {
     let processes = enumerate_processes()?;
     
-    let filtered_processes: Vec<_> = if let Some(filter_str) = filter {
-        processes.into_iter()
-            .filter(|p| {
-                p.name.to_lowercase().contains(&filter_str.to_lowercase()) ||
-                p.path.as_ref().map_or(false, |path| 
-                    path.to_lowercase().contains(&filter_str.to_lowercase()))
-            })
-            .collect()
-    } else {
-        processes
-    };
-    
-    if filtered_processes.is_empty() {
-        println!("No processes found{}", 
-                 filter.map_or(String::new(), |f| format!(" matching '{}'", f)));
-        return Ok(());
-    }
-    
-    println!("\n{:-<120}", "");
-    println!("{:>8} | {:>8} | {:<30} | {}", "PID", "PPID", "Process Name", "Path");
-    println!("{:-<120}", "");
-    
-    for process in &filtered_processes {
-        let path_display = process.path.as_deref().unwrap_or("N/A");
-        println!("{:>8} | {:>8} | {:<30} | {}", 
-                 process.pid, 
-                 process.parent_pid, 
-                 &process.name[..process.name.len().min(30)], 
-                 path_display);
-    }
-    
-    println!("{:-<120}", "");
-    println!("Total: {} processes{}", 
-             filtered_processes.len(),
-             filter.map_or(String::new(), |f| format!(" (filtered by '{}')", f)));
-    
-    Ok(())
+    Ok(match filter {
+        Some(filter) => processes.into_iter().filter(|p| p.matches(filter)).collect(),
+        None => processes,
+    })
 }
 
-/// Emergency process termination - kill by PID with maximum force
+/// Emergency process termination - kill by PID with maximum force.
+///
+/// This doesn't ask before terminating critical processes; callers should
+/// check `ProcessInfo::is_critical` first.
 #[cfg(windows)]
-pub fn emergency_terminate(pid: u32) -> Result<()> {
-    println!("🚨 EMERGENCY TERMINATION for PID: {}", pid);
-    
-    // First try to get process info
-    if let Ok(processes) = enumerate_processes() {
-        if let Some(process) = processes.iter().find(|p| p.pid == pid) {
-            println!("Target: {} - Path: {:?}", process.name, process.path);
-            
-            // Warn about critical processes
-            let name_lower = process.name.to_lowercase();
-            if name_lower.contains("system") || 
-               name_lower.contains("csrss") || 
-               name_lower.contains("winlogon") ||
-               name_lower.contains("services") {
-                println!("⚠️  WARNING: This appears to be a critical system process!");
-                print!("Continue with termination? [y/N]: ");
-                io::stdout().flush().unwrap();
-                
-                let mut input = String::new();
-                io::stdin().read_line(&mut input).unwrap();
-                
-                if !input.trim().to_lowercase().starts_with('y') {
-                    println!("Emergency termination cancelled");
-                    return Ok(());
-                }
-            }
-        }
-    }
+pub fn emergency_terminate(pid: u32) -> Result<Termination> {
+    log::info!("🚨 EMERGENCY TERMINATION for PID: {}", pid);
     
     // Try progressive termination methods
-    println!("Phase 1: Attempting graceful termination...");
+    log::info!("Phase 1: Attempting graceful termination...");
     match kill_process_by_pid(pid, false) {
-        Ok(true) => {
-            println!("✓ Process terminated gracefully");
-            return Ok(());
-        }
-        Ok(false) => {
-            eprintln!("Graceful termination returned false");
-        }
-        Err(e) => {
-            eprintln!("Graceful termination failed: {}", e);
-        }
+        Ok(true) => return Ok(Termination::Graceful),
+        Ok(false) => log::warn!("Graceful termination returned false"),
+        Err(e) => log::warn!("Graceful termination failed: {}", e),
     }
     
-    println!("Phase 2: Attempting forced termination...");
+    log::info!("Phase 2: Attempting forced termination...");
     match kill_process_by_pid(pid, true) {
-        Ok(true) => {
-            println!("✓ Process force terminated");
-            return Ok(());
-        }
-        Ok(false) => {
-            eprintln!("Force termination returned false");
-        }
-        Err(e) => {
-            eprintln!("Force termination failed: {}", e);
-        }
+        Ok(true) => return Ok(Termination::Forced),
+        Ok(false) => log::warn!("Force termination returned false"),
+        Err(e) => log::warn!("Force termination failed: {}", e),
     }
     
-    println!("Phase 3: Attempting thread suspension...");
+    log::info!("Phase 3: Attempting thread suspension...");
     match suspend_process_by_pid(pid) {
         Ok(()) => {
-            println!("✓ Process threads suspended");
+            log::info!("✓ Process threads suspended");
             
             // Wait a moment then try termination again
             std::thread::sleep(std::time::Duration::from_millis(1000));
             
             match kill_process_by_pid(pid, true) {
-                Ok(true) => {
-                    println!("✓ Suspended process terminated");
-                    return Ok(());
-                }
-                Ok(false) => {
-                    eprintln!("Suspended process termination returned false");
-                }
-                Err(e) => {
-                    eprintln!("Failed to terminate suspended process: {}", e);
-                }
+                Ok(true) => return Ok(Termination::Suspended),
+                Ok(false) => log::warn!("Suspended process termination returned false"),
+                Err(e) => log::warn!("Failed to terminate suspended process: {}", e),
             }
         }
-        Err(e) => {
-            eprintln!("Thread suspension failed: {}", e);
-        }
+        Err(e) => log::warn!("Thread suspension failed: {}", e),
     }
     
-    Err(anyhow::anyhow!("All emergency termination methods failed for PID: {}", pid))
+    Err(Error::InvalidInput(format!("All emergency termination methods failed for PID: {}", pid)))
 }
 
 /// Get detailed information about a specific process
@@ -699,14 +616,12 @@ pub fn get_process_details(pid: u32) -> Result<ProcessInfo> {
     
     processes.into_iter()
         .find(|p| p.pid == pid)
-        .ok_or_else(|| anyhow::anyhow!("Process with PID {} not found", pid))
+        .ok_or_else(|| Error::InvalidInput(format!("Process with PID {} not found", pid)))
 }
 
 /// Check if current process has admin privileges
 #[cfg(windows)]
 pub fn is_admin() -> bool {
-    use std::ptr;
-    
     #[repr(C)]
     struct SidIdentifierAuthority {
         value: [u8; 6],
@@ -772,102 +687,72 @@ pub fn is_admin() -> bool {
 
 /// PROCWOLF status and system information
 #[cfg(windows)]
-pub fn procwolf_status() -> Result<()> {
-    println!("🐺 PROCWOLF System Status");
-    println!("{:-<50}", "");
-    
-    // Check admin privileges
-    let is_admin_user = is_admin();
-    println!("Administrator privileges: {}", if is_admin_user { "✓ YES" } else { "✗ NO" });
-    
-    if !is_admin_user {
-        println!("⚠️  Some PROCWOLF functions require administrator privileges");
-    }
-    
-    // Get current process info
-    let current_pid = unsafe { GetCurrentProcessId() };
-    println!("PROCWOLF PID: {}", current_pid);
-    
-    // Count total processes
-    match enumerate_processes() {
-        Ok(processes) => {
-            println!("Total processes visible: {}", processes.len());
-            
-            // Count by type
-            let mut system_processes = 0;
-            let mut user_processes = 0;
-            
-            for process in &processes {
-                if process.name.to_lowercase().contains("system") ||
-                   process.name.to_lowercase().contains("svchost") ||
-                   process.name.to_lowercase().contains("csrss") {
-                    system_processes += 1;
-                } else {
-                    user_processes += 1;
-                }
-            }
-            
-            println!("  - System processes: ~{}", system_processes);
-            println!("  - User processes: ~{}", user_processes);
-        }
-        Err(e) => {
-            eprintln!("Failed to enumerate processes: {}", e);
-        }
-    }
-    
-    println!("{:-<50}", "");
-    println!("PROCWOLF ready for deployment 🐺");
+pub fn procwolf_status() -> Result<ProcwolfStatus> {
+    let processes = enumerate_processes()?;
     
-    Ok(())
+    // Count by type
+    let system_processes = processes.iter()
+        .filter(|process| {
+            let name = process.name.to_lowercase();
+            name.contains("system") || name.contains("svchost") || name.contains("csrss")
+        })
+        .count();
+    
+    Ok(ProcwolfStatus {
+        is_admin: is_admin(),
+        pid: unsafe { GetCurrentProcessId() },
+        processes: processes.len(),
+        system_processes,
+        user_processes: processes.len() - system_processes,
+    })
 }
 
 // Non-Windows stubs to make the code compile on other platforms
 #[cfg(not(windows))]
 pub fn terminate_lock_owners(_path: &Path) -> Result<()> {
-    eprintln!("Process termination is only implemented for Windows");
+    log::warn!("Process termination is only implemented for Windows");
     Ok(())
 }
 
 #[cfg(not(windows))]
 pub fn deploy_procwolf(_file_path: &Path) -> Result<()> {
-    eprintln!("PROCWOLF is only available on Windows");
-    Err(anyhow::anyhow!("PROCWOLF not supported on this platform"))
+    Err(Error::InvalidInput("PROCWOLF not supported on this platform".to_string()))
 }
 
 #[cfg(not(windows))]
-pub fn show_potential_lock_owners(_file_path: &Path) -> Result<()> {
-    eprintln!("Lock owner detection is only available on Windows");
-    Ok(())
+pub fn find_potential_lock_owners(_file_path: &Path) -> Result<Vec<ProcessInfo>> {
+    Err(Error::InvalidInput("Lock owner detection is only available on Windows".to_string()))
+}
+
+#[cfg(not(windows))]
+pub fn terminate_processes(_targets: &[ProcessInfo], _force: bool) -> Vec<u32> {
+    log::warn!("Process termination is only implemented for Windows");
+    Vec::new()
 }
 
 #[cfg(not(windows))]
 pub fn hunt_and_terminate(_name_pattern: &str, _force: bool, _dry_run: bool) -> Result<Vec<u32>> {
-    eprintln!("Process hunting is only available on Windows");
-    Ok(Vec::new())
+    Err(Error::InvalidInput("Process hunting is only available on Windows".to_string()))
 }
 
 #[cfg(not(windows))]
-pub fn list_all_processes(_filter: Option<&str>) -> Result<()> {
-    eprintln!("Detailed process listing is only available on Windows");
-    Ok(())
+pub fn list_all_processes(_filter: Option<&str>) -> Result<Vec<ProcessInfo>> {
+    Err(Error::InvalidInput("Detailed process listing is only available on Windows".to_string()))
 }
 
 #[cfg(not(windows))]
-pub fn emergency_terminate(_pid: u32) -> Result<()> {
-    eprintln!("Emergency termination is only available on Windows");
-    Err(anyhow::anyhow!("Emergency termination not supported on this platform"))
+pub fn emergency_terminate(_pid: u32) -> Result<Termination> {
+    Err(Error::InvalidInput("Emergency termination not supported on this platform".to_string()))
 }
 
 #[cfg(not(windows))]
-pub fn resume_process_by_pid(_pid: u32) -> Result<()> {
-    eprintln!("Process resume is only available on Windows");
-    Err(anyhow::anyhow!("Process resume not supported on this platform"))
+pub fn resume_process_by_pid(_pid: u32) -> Result<usize> {
+    Err(Error::InvalidInput("Process resume not supported on this platform".to_string()))
 }
 
 #[cfg(not(windows))]
 pub fn get_process_details(_pid: u32) -> Result<ProcessInfo> {
-    eprintln!("Process details are only available on Windows");
-    Err(anyhow::anyhow!("Process details not supported on this platform"))
+    Err(Error::InvalidInput("Process details not supported on this platform".to_string()))
 }
 
 #[cfg(not(windows))]
@@ -876,17 +761,6 @@ pub fn is_admin() -> bool {
 }
 
 #[cfg(not(windows))]
-pub fn procwolf_status() -> Result<()> {
-    println!("PROCWOLF is only available on Windows");
-    Ok(())
+pub fn procwolf_status() -> Result<ProcwolfStatus> {
+    Err(Error::InvalidInput("PROCWOLF is only available on Windows".to_string()))
 }
-
-// Need to define ProcessInfo for non-Windows too
-#[cfg(not(windows))]
-#[derive(Debug, Clone)]
-pub struct ProcessInfo {
-    pub pid: u32,
-    pub name: String,
-    pub path: Option<String>,
-    pub parent_pid: u32,
-}
\ No newline at end of file
@@ -0,0 +1,204 @@
+// src/procwolf.rs - The `procwolf` subcommand: PROCWOLF process tools on the terminal
+use anyhow::Result;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use dialoguer::Confirm;
+use std::path::Path;
+
+use file_utils_i::secure_delete::{
+    deploy_procwolf, emergency_terminate, find_potential_lock_owners, get_process_details, list_all_processes,
+    procwolf_status, resume_process_by_pid, terminate_processes, ProcessInfo, Termination,
+};
+
+use crate::ui;
+
+pub fn command() -> Command {
+    let pid_arg = || Arg::new("pid")
+        .help("Process ID")
+        .required(true)
+        .value_parser(value_parser!(u32));
+    let yes_arg = || Arg::new("yes")
+        .short('y')
+        .long("yes")
+        .help("Don't ask for confirmation")
+        .action(ArgAction::SetTrue);
+
+    Command::new("procwolf")
+        .about("Find and stop processes that keep files locked (Windows only)")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("status")
+                .about("Show privileges and how many processes are visible")
+        )
+        .subcommand(
+            Command::new("list")
+                .about("List running processes")
+                .arg(Arg::new("filter")
+                    .help("Only show processes whose name or path contains this"))
+        )
+        .subcommand(
+            Command::new("owners")
+                .about("Show processes that might be locking a file")
+                .arg(Arg::new("path").help("Locked file").required(true))
+        )
+        .subcommand(
+            Command::new("deploy")
+                .about("Suspend, then terminate, whatever keeps a file locked")
+                .arg(Arg::new("path").help("Locked file").required(true))
+        )
+        .subcommand(
+            Command::new("hunt")
+                .about("Terminate every process whose name or path contains a pattern")
+                .arg(Arg::new("pattern").help("Part of a process name or path").required(true))
+                .arg(Arg::new("force")
+                    .short('f')
+                    .long("force")
+                    .help("Terminate forcefully")
+                    .action(ArgAction::SetTrue))
+                .arg(Arg::new("dry-run")
+                    .long("dry-run")
+                    .help("Only show what would be terminated")
+                    .action(ArgAction::SetTrue))
+                .arg(yes_arg())
+        )
+        .subcommand(
+            Command::new("kill")
+                .about("Terminate a process by any means: gracefully, forcefully, then after suspending it")
+                .arg(pid_arg())
+                .arg(yes_arg())
+        )
+        .subcommand(
+            Command::new("resume")
+                .about("Resume a process suspended by PROCWOLF")
+                .arg(pid_arg())
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        Some(("status", _)) => {
+            let status = procwolf_status()?;
+
+            println!("🐺 PROCWOLF System Status");
+            println!("{:-<50}", "");
+            println!("Administrator privileges: {}", if status.is_admin { "✓ YES" } else { "✗ NO" });
+            if !status.is_admin {
+                ui::display_warning("Some PROCWOLF functions require administrator privileges");
+            }
+            println!("PROCWOLF PID: {}", status.pid);
+            println!("Total processes visible: {}", status.processes);
+            println!("  - System processes: ~{}", status.system_processes);
+            println!("  - User processes: ~{}", status.user_processes);
+            println!("{:-<50}", "");
+            println!("PROCWOLF ready for deployment 🐺");
+        }
+        Some(("list", list_matches)) => {
+            let filter = list_matches.get_one::<String>("filter").map(String::as_str);
+            let processes = list_all_processes(filter)?;
+
+            if processes.is_empty() {
+                println!("No processes found{}",
+                         filter.map_or(String::new(), |f| format!(" matching '{}'", f)));
+                return Ok(());
+            }
+            print_process_table(&processes);
+            println!("Total: {} processes{}",
+                     processes.len(),
+                     filter.map_or(String::new(), |f| format!(" (filtered by '{}')", f)));
+        }
+        Some(("owners", owners_matches)) => {
+            let path = Path::new(owners_matches.get_one::<String>("path").unwrap());
+            println!("Scanning for potential lock owners of: {}", path.display());
+
+            let owners = find_potential_lock_owners(path)?;
+            if owners.is_empty() {
+                println!("No obvious lock owners detected");
+                return Ok(());
+            }
+            println!("Potential lock owners found:");
+            for process in &owners {
+                println!("  PID: {} | Name: {} | Path: {:?}", process.pid, process.name, process.path);
+            }
+        }
+        Some(("deploy", deploy_matches)) => {
+            let path = Path::new(deploy_matches.get_one::<String>("path").unwrap());
+            deploy_procwolf(path)?;
+        }
+        Some(("hunt", hunt_matches)) => {
+            let pattern = hunt_matches.get_one::<String>("pattern").unwrap();
+            println!("🐺 PROCWOLF hunting mode - searching for: '{}'", pattern);
+
+            let targets = list_all_processes(Some(pattern))?;
+            if targets.is_empty() {
+                println!("No processes found matching pattern: '{}'", pattern);
+                return Ok(());
+            }
+            println!("Found {} matching processes:", targets.len());
+            for target in &targets {
+                println!("  - {} (PID: {}) - Path: {:?}", target.name, target.pid, target.path);
+            }
+
+            if hunt_matches.get_flag("dry-run") {
+                println!("DRY RUN: Would terminate {} processes", targets.len());
+                return Ok(());
+            }
+            // Confirm termination for multiple processes
+            if targets.len() > 1 && !hunt_matches.get_flag("yes")
+                && !confirm(&format!("Terminate {} processes?", targets.len()))? {
+                println!("Operation cancelled");
+                return Ok(());
+            }
+
+            let killed = terminate_processes(&targets, hunt_matches.get_flag("force"));
+            println!("PROCWOLF hunt complete - {} processes terminated", killed.len());
+        }
+        Some(("kill", kill_matches)) => {
+            let pid = *kill_matches.get_one::<u32>("pid").unwrap();
+
+            if let Ok(process) = get_process_details(pid) {
+                println!("Target: {} - Path: {:?}", process.name, process.path);
+                if process.is_critical() {
+                    ui::display_warning("This appears to be a critical system process!");
+                    if !kill_matches.get_flag("yes") && !confirm("Continue with termination?")? {
+                        println!("Emergency termination cancelled");
+                        return Ok(());
+                    }
+                }
+            }
+
+            match emergency_terminate(pid)? {
+                Termination::Graceful => println!("✓ Process terminated gracefully"),
+                Termination::Forced => println!("✓ Process force terminated"),
+                Termination::Suspended => println!("✓ Suspended process terminated"),
+            }
+        }
+        Some(("resume", resume_matches)) => {
+            let pid = *resume_matches.get_one::<u32>("pid").unwrap();
+            let resumed = resume_process_by_pid(pid)?;
+            println!("Resumed {} threads for process PID: {}", resumed, pid);
+        }
+        _ => unreachable!("clap requires a procwolf subcommand"),
+    }
+    Ok(())
+}
+
+fn print_process_table(processes: &[ProcessInfo]) {
+    println!("\n{:-<120}", "");
+    println!("{:>8} | {:>8} | {:<30} | Path", "PID", "PPID", "Process Name");
+    println!("{:-<120}", "");
+
+    for process in processes {
+        let name: String = process.name.chars().take(30).collect();
+        println!("{:>8} | {:>8} | {:<30} | {}",
+                 process.pid,
+                 process.parent_pid,
+                 name,
+                 process.path.as_deref().unwrap_or("N/A"));
+    }
+
+    println!("{:-<120}", "");
+}
+
+/// Ask a yes/no question on the terminal, defaulting to no
+fn confirm(prompt: &str) -> Result<bool> {
+    Ok(Confirm::new().with_prompt(prompt).default(false).interact()?)
+}
@@ -1,27 +1,75 @@
 // src/secure_delete.rs - Main module that orchestrates everything
 use std::path::Path;
-use anyhow::{Context, Result};
+use log::Level;
 
+use crate::error::{Context, Error, Result};
 // Import our modular components from the same src directory
-use crate::file_operations::{remove_file_attributes, schedule_deletion_on_reboot, test_file_access};
+#[cfg(windows)]
+use crate::file_operations::{remove_file_attributes, schedule_deletion_on_reboot};
+use crate::parallel;
+#[cfg(windows)]
 use crate::process_hunter::terminate_lock_owners;
-use crate::secure_wipe::secure_overwrite;
+use crate::secure_wipe::Wipe;
 
 // Re-export public functions from other modules
 pub use crate::process_hunter::{
     deploy_procwolf, 
-    show_potential_lock_owners, 
+    find_potential_lock_owners, 
     hunt_and_terminate,
+    terminate_processes,
     emergency_terminate,
     list_all_processes,
     resume_process_by_pid,
     get_process_details,
     is_admin,
-    procwolf_status
+    procwolf_status,
+    ProcessInfo,
+    ProcwolfStatus,
+    Termination,
 };
 
-/// Cross-platform secure delete with Windows-specific stubborn file handling
+/// Progress messages from one deletion, held back so that deletions running
+/// side by side can still be reported one file at a time
+#[derive(Default)]
+pub struct DeleteLog {
+    lines: Vec<(Level, String)>,
+}
+
+impl DeleteLog {
+    pub fn info(&mut self, message: String) {
+        self.lines.push((Level::Info, message));
+    }
+    
+    pub fn warn(&mut self, message: String) {
+        self.lines.push((Level::Warn, message));
+    }
+    
+    /// Take everything collected so far, oldest first
+    pub fn drain(&mut self) -> impl Iterator<Item = (Level, String)> + '_ {
+        self.lines.drain(..)
+    }
+    
+    /// Pass everything collected so far on to the `log` crate
+    fn emit(&mut self) {
+        for (level, line) in self.drain() {
+            log::log!(level, "{}", line);
+        }
+    }
+}
+
+/// Cross-platform secure delete with Windows-specific stubborn file handling,
+/// overwriting with the default `Wipe`. Progress messages go to the `log`
+/// crate.
 pub fn secure_delete(file_path: &Path) -> Result<()> {
+    let mut messages = DeleteLog::default();
+    let result = secure_delete_logged(file_path, &Wipe::default(), &mut messages);
+    messages.emit();
+    result
+}
+
+/// `secure_delete` overwriting as `wipe` says, collecting progress messages
+/// in `log` instead
+pub fn secure_delete_logged(file_path: &Path, wipe: &Wipe, log: &mut DeleteLog) -> Result<()> {
     let path = if file_path.is_relative() {
         std::env::current_dir()?.join(file_path)
     } else {
@@ -30,7 +78,7 @@ pub fn secure_delete(file_path: &Path) -> Result<()> {
     
     // Check if file exists
     if !path.exists() {
-        return Err(anyhow::anyhow!("File does not exist: {}", path.display()));
+        return Err(Error::InvalidInput(format!("File does not exist: {}", path.display())));
     }
     
     // Get file metadata
@@ -39,34 +87,34 @@ pub fn secure_delete(file_path: &Path) -> Result<()> {
     
     let file_size = metadata.len() as usize;
     
-    println!("Attempting secure deletion of: {} ({} bytes)", path.display(), file_size);
+    log.info(format!("Attempting secure deletion of: {} ({} bytes)", path.display(), file_size));
     
     #[cfg(windows)]
     {
         // Step 1: Remove restrictive attributes
         if let Err(e) = remove_file_attributes(&path) {
-            eprintln!("Warning: Could not remove file attributes: {}", e);
+            log.warn(format!("Warning: Could not remove file attributes: {}", e));
         }
     }
     
     // Step 2: Attempt secure overwrite
     if file_size > 0 {
-        match secure_overwrite(&path, file_size) {
-            Ok(_) => println!("Successfully overwrote file data"),
+        match wipe.overwrite(&path, file_size, log) {
+            Ok(_) => log.info("Successfully overwrote file data".to_string()),
             Err(e) => {
-                eprintln!("Warning: Could not overwrite file data: {}", e);
+                log.warn(format!("Warning: Could not overwrite file data: {}", e));
                 
                 #[cfg(windows)]
                 {
                     // Step 3: Deploy the PROCWOLF to handle lock owners
                     if let Err(e) = terminate_lock_owners(&path) {
-                        eprintln!("Warning: PROCWOLF could not eliminate all lock owners: {}", e);
+                        log.warn(format!("Warning: PROCWOLF could not eliminate all lock owners: {}", e));
                     }
                     
                     // Retry overwrite after PROCWOLF
-                    match secure_overwrite(&path, file_size) {
-                        Ok(_) => println!("Successfully overwrote file data after PROCWOLF intervention"),
-                        Err(e2) => eprintln!("Still could not overwrite after PROCWOLF: {}", e2),
+                    match wipe.overwrite(&path, file_size, log) {
+                        Ok(_) => log.info("Successfully overwrote file data after PROCWOLF intervention".to_string()),
+                        Err(e2) => log.warn(format!("Still could not overwrite after PROCWOLF: {}", e2)),
                     }
                 }
             }
@@ -76,35 +124,37 @@ pub fn secure_delete(file_path: &Path) -> Result<()> {
     // Step 4: Attempt standard deletion
     match std::fs::remove_file(&path) {
         Ok(_) => {
-            println!("Successfully deleted: {}", path.display());
-            return Ok(());
+            log.info(format!("Successfully deleted: {}", path.display()));
+            Ok(())
         }
         Err(e) => {
-            eprintln!("Standard deletion failed: {}", e);
+            log.warn(format!("Standard deletion failed: {}", e));
             
             #[cfg(windows)]
             {
                 // Step 5: Final PROCWOLF attempt if deletion still fails
-                println!("Deploying PROCWOLF for final deletion attempt...");
+                log.info("Deploying PROCWOLF for final deletion attempt...".to_string());
                 if let Err(e) = terminate_lock_owners(&path) {
-                    eprintln!("Warning: Final PROCWOLF deployment failed: {}", e);
+                    log.warn(format!("Warning: Final PROCWOLF deployment failed: {}", e));
                 }
                 
                 // Try deletion one more time
                 match std::fs::remove_file(&path) {
                     Ok(_) => {
-                        println!("Successfully deleted after final PROCWOLF intervention: {}", path.display());
+                        log.info(format!("Successfully deleted after final PROCWOLF intervention: {}", path.display()));
                         return Ok(());
                     }
                     Err(_) => {
                         // Step 6: Schedule for deletion on reboot (Windows only)
-                        println!("All active methods failed - scheduling deletion on next reboot...");
+                        log.info("All active methods failed - scheduling deletion on next reboot...".to_string());
                         match schedule_deletion_on_reboot(&path) {
-                            Ok(_) => return Ok(()),
+                            Ok(_) => {
+                                log.info(format!("Scheduled for deletion on next reboot: {}", path.display()));
+                                return Ok(());
+                            }
                             Err(e) => {
-                                return Err(anyhow::anyhow!(
-                                    "All deletion methods failed including reboot scheduling. Last error: {}", e
-                                ));
+                                log.warn(format!("All deletion methods failed including reboot scheduling. Last error: {}", e));
+                                return Err(Error::Locked(path));
                             }
                         }
                     }
@@ -113,9 +163,7 @@ pub fn secure_delete(file_path: &Path) -> Result<()> {
             
             #[cfg(not(windows))]
             {
-                return Err(anyhow::anyhow!(
-                    "Could not delete file: {}. Manual intervention required.", e
-                ));
+                Err(e).with_context(|| format!("Could not delete file: {}", path.display()))
             }
         }
     }
@@ -123,69 +171,56 @@ pub fn secure_delete(file_path: &Path) -> Result<()> {
 
 /// Secure delete with retry logic and multiple strategies
 pub fn secure_delete_with_retry(file_path: &Path, max_retries: u32) -> Result<()> {
+    let mut messages = DeleteLog::default();
+    let result = secure_delete_with_retry_logged(file_path, &Wipe::default(), max_retries, &mut messages);
+    messages.emit();
+    result
+}
+
+fn secure_delete_with_retry_logged(file_path: &Path, wipe: &Wipe, max_retries: u32, log: &mut DeleteLog) -> Result<()> {
     let mut last_error = None;
     
     for attempt in 1..=max_retries {
-        match secure_delete(file_path) {
+        match secure_delete_logged(file_path, wipe, log) {
             Ok(_) => return Ok(()),
             Err(e) => {
                 last_error = Some(e);
                 if attempt < max_retries {
-                    println!("Deletion attempt {} failed, retrying...", attempt);
+                    log.info(format!("Deletion attempt {} failed, retrying...", attempt));
                     std::thread::sleep(std::time::Duration::from_millis(100 * attempt as u64));
                 }
             }
         }
     }
     
-    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("Unknown error")))
+    Err(last_error.unwrap_or_else(|| Error::InvalidInput("No deletion attempts were allowed".into())))
 }
 
-/// Batch secure delete multiple files
-pub fn secure_delete_batch<P: AsRef<Path>>(file_paths: &[P]) -> Result<()> {
+/// Batch secure delete multiple files on up to `jobs` threads, overwriting
+/// as `wipe` says. `report` is called with each file's messages and result,
+/// in the order the files were given.
+pub fn secure_delete_batch<P, F>(file_paths: &[P], wipe: &Wipe, jobs: usize, mut report: F) -> Result<()>
+where
+    P: AsRef<Path> + Sync,
+    F: FnMut(&Path, DeleteLog, &Result<()>),
+{
     let mut failed_files = Vec::new();
     
-    for (i, path) in file_paths.iter().enumerate() {
+    parallel::for_each_ordered(file_paths, jobs, |path| {
+        let mut log = DeleteLog::default();
+        let result = secure_delete_with_retry_logged(path.as_ref(), wipe, 3, &mut log);
+        (log, result)
+    }, |path, (log, result)| {
         let path = path.as_ref();
-        println!("Processing file {}/{}: {}", i + 1, file_paths.len(), path.display());
-        
-        match secure_delete_with_retry(path, 3) {
-            Ok(_) => {},
-            Err(e) => {
-                eprintln!("Failed to delete {}: {}", path.display(), e);
-                failed_files.push(path.to_path_buf());
-            }
+        report(path, log, &result);
+        if result.is_err() {
+            failed_files.push(path.to_path_buf());
         }
-    }
+    });
     
     if !failed_files.is_empty() {
-        return Err(anyhow::anyhow!(
-            "Failed to delete {} files: {:?}", 
-            failed_files.len(), 
-            failed_files
-        ));
+        return Err(Error::DeleteFailed(failed_files));
     }
     
     Ok(())
 }
-
-/// Example usage and CLI interface
-pub fn main() {
-    println!("🐺 PROCWOLF Enhanced Secure Delete System");
-    println!("==========================================");
-    
-    #[cfg(windows)]
-    {
-        if let Err(e) = procwolf_status() {
-            eprintln!("Failed to get PROCWOLF status: {}", e);
-        }
-    }
-    
-    // Example usage patterns:
-    println!("\nExample usage:");
-    println!("  secure_delete(Path::new(\"locked_file.txt\"))");
-    println!("  deploy_procwolf(Path::new(\"stubborn_file.exe\"))");
-    println!("  hunt_and_terminate(\"malware\", true, false)");
-    println!("  emergency_terminate(1234)");
-    println!("  list_all_processes(Some(\"chrome\"))");
-}
\ No newline at end of file
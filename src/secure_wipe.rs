@@ -2,11 +2,52 @@
 use std::io::{Write, Seek};
 use std::path::Path;
 use std::fs::OpenOptions;
-use anyhow::{Context, Result};
 use rand::RngCore;
+use serde::{Deserialize, Serialize};
 
-/// Perform secure overwrite of file contents with multiple passes
-pub fn secure_overwrite(path: &Path, file_size: usize) -> Result<()> {
+use crate::error::{Context, Result};
+use crate::secure_delete::DeleteLog;
+
+/// How file contents are overwritten before the file is removed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WipeMethod {
+    /// Several passes of random data
+    #[default]
+    Random,
+    /// A single pass of zeros (faster but less secure)
+    Zeros,
+    /// DoD 5220.22-M: zeros, ones, then random data
+    Dod,
+}
+
+/// A wipe method and, for `WipeMethod::Random`, how many passes it makes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Wipe {
+    pub method: WipeMethod,
+    pub passes: u8,
+}
+
+impl Default for Wipe {
+    fn default() -> Self {
+        Self { method: WipeMethod::Random, passes: 3 }
+    }
+}
+
+impl Wipe {
+    /// Overwrite the first `file_size` bytes of `path`
+    pub fn overwrite(&self, path: &Path, file_size: usize, log: &mut DeleteLog) -> Result<()> {
+        match self.method {
+            WipeMethod::Random => secure_overwrite(path, file_size, self.passes, log),
+            WipeMethod::Zeros => quick_overwrite(path, file_size, log),
+            WipeMethod::Dod => dod_overwrite(path, file_size, log),
+        }
+    }
+}
+
+/// Perform secure overwrite of file contents with `passes` passes of
+/// random data
+pub fn secure_overwrite(path: &Path, file_size: usize, passes: u8, log: &mut DeleteLog) -> Result<()> {
     // Open file for writing (don't truncate to preserve size)
     let mut file = OpenOptions::new()
         .write(true)
@@ -14,11 +55,10 @@ pub fn secure_overwrite(path: &Path, file_size: usize) -> Result<()> {
         .open(path)
         .with_context(|| format!("Failed to open file for overwriting: {}", path.display()))?;
     
-    const PASSES: u8 = 3;
     let mut rng = rand::thread_rng();
     
-    for pass in 1..=PASSES {
-        println!("  Overwrite pass {}/{}", pass, PASSES);
+    for pass in 1..=passes {
+        log.info(format!("  Overwrite pass {}/{}", pass, passes));
         
         // Seek to beginning
         file.seek(std::io::SeekFrom::Start(0))
@@ -48,14 +88,14 @@ pub fn secure_overwrite(path: &Path, file_size: usize) -> Result<()> {
 }
 
 /// Perform a single-pass overwrite with zeros (faster but less secure)
-pub fn quick_overwrite(path: &Path, file_size: usize) -> Result<()> {
+pub fn quick_overwrite(path: &Path, file_size: usize, log: &mut DeleteLog) -> Result<()> {
     let mut file = OpenOptions::new()
         .write(true)
         .truncate(false)
         .open(path)
         .with_context(|| format!("Failed to open file for quick overwrite: {}", path.display()))?;
     
-    println!("  Quick overwrite with zeros");
+    log.info("  Quick overwrite with zeros".to_string());
     
     file.seek(std::io::SeekFrom::Start(0))
         .with_context(|| "Failed to seek to start")?;
@@ -78,14 +118,14 @@ pub fn quick_overwrite(path: &Path, file_size: usize) -> Result<()> {
 }
 
 /// Perform a DoD 5220.22-M standard 3-pass overwrite
-pub fn dod_overwrite(path: &Path, file_size: usize) -> Result<()> {
+pub fn dod_overwrite(path: &Path, file_size: usize, log: &mut DeleteLog) -> Result<()> {
     let mut file = OpenOptions::new()
         .write(true)
         .truncate(false)
         .open(path)
         .with_context(|| format!("Failed to open file for DoD overwrite: {}", path.display()))?;
     
-    println!("  DoD 5220.22-M standard overwrite");
+    log.info("  DoD 5220.22-M standard overwrite".to_string());
     
     // DoD standard: Pass 1 = all 0s, Pass 2 = all 1s, Pass 3 = random
     let patterns = [
@@ -95,7 +135,7 @@ pub fn dod_overwrite(path: &Path, file_size: usize) -> Result<()> {
     
     // Passes 1 and 2: Fixed patterns
     for (i, (name, pattern)) in patterns.iter().enumerate() {
-        println!("    DoD pass {}: {}", i + 1, name);
+        log.info(format!("  DoD pass {}: {}", i + 1, name));
         
         file.seek(std::io::SeekFrom::Start(0))
             .with_context(|| format!("Failed to seek to start on DoD pass {}", i + 1))?;
@@ -113,7 +153,7 @@ pub fn dod_overwrite(path: &Path, file_size: usize) -> Result<()> {
     }
     
     // Pass 3: Random data
-    println!("    DoD pass 3: random");
+    log.info("  DoD pass 3: random".to_string());
     let mut rng = rand::thread_rng();
     
     file.seek(std::io::SeekFrom::Start(0))
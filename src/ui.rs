@@ -1,3 +1,6 @@
+// src/ui.rs - Coloured terminal output for the command line tool
+// Not every helper is used by every command
+#![allow(dead_code)]
 use anyhow::{Context, Result};
 use console::style;
 use std::{
@@ -87,4 +90,4 @@ pub fn display_info(message: &str) {
 /// Display success message
 pub fn display_success(message: &str) {
     println!("{} {}", style("[SUCCESS]").green().bold(), message);
-}
\ No newline at end of file
+}
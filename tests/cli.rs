@@ -0,0 +1,209 @@
+// tests/cli.rs - The command line tool end to end: exit codes, key sources and pipes
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Run the tool with a config that makes key derivation cheap
+fn run(config: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_file-utils-i"))
+        .args(args)
+        .env("XDG_CONFIG_HOME", config)
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .unwrap()
+}
+
+fn setup() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config/file-utils-i");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(config.join("config.toml"), "[kdf]\nalgorithm = \"pbkdf2-sha256\"\niterations = 1\n").unwrap();
+    fs::create_dir(dir.path().join("files")).unwrap();
+    for name in ["a.txt", "b.txt"] {
+        fs::write(dir.path().join("files").join(name), name).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn verify_exits_non_zero_when_a_file_fails() {
+    let dir = setup();
+    let config = dir.path().join("config");
+    let files = dir.path().join("files");
+    let files = files.to_str().unwrap();
+    let a = format!("{}/a.txt.enc", files);
+
+    assert!(run(&config, &["encrypt", files, "-r", "-k", "pw"]).status.success());
+    assert!(run(&config, &["verify", &a, "-k", "pw"]).status.success());
+    assert!(run(&config, &["verify", files, "-r", "-k", "pw"]).status.success());
+    assert_eq!(run(&config, &["verify", &a, "-k", "wrong"]).status.code(), Some(1));
+
+    let mut contents = fs::read(&a).unwrap();
+    *contents.last_mut().unwrap() ^= 1;
+    fs::write(&a, contents).unwrap();
+    assert_eq!(run(&config, &["verify", &a, "-k", "pw"]).status.code(), Some(1));
+
+    // The rest of a directory is still checked and reported
+    let output = run(&config, &["verify", files, "-r", "-k", "pw"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("b.txt.enc"));
+}
+
+#[test]
+fn secure_decrypt_refuses_unauthenticated_files() {
+    let dir = setup();
+    let config = dir.path().join("config");
+    let files = dir.path().join("files");
+    let a = files.join("a.txt");
+    let encrypted = files.join("a.txt.enc");
+    let (a, encrypted) = (a.to_str().unwrap(), encrypted.to_str().unwrap());
+
+    assert!(run(&config, &["encrypt", a, "-m", "aes", "-k", "right"]).status.success());
+    fs::remove_file(a).unwrap();
+    let output = run(&config, &["decrypt", encrypted, "-m", "aes", "-k", "wrong", "-s"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(Path::new(encrypted).exists());
+    assert!(!Path::new(a).exists());
+
+    let output = run(&config, &["decrypt", files.to_str().unwrap(), "-r", "-m", "aes", "-k", "right", "-s"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(Path::new(encrypted).exists());
+}
+
+#[test]
+fn force_overwrites_existing_outputs() {
+    let dir = setup();
+    let config = dir.path().join("config");
+    let a = dir.path().join("files/a.txt");
+    let a = a.to_str().unwrap();
+
+    assert!(run(&config, &["encrypt", a, "-k", "pw"]).status.success());
+    assert_eq!(run(&config, &["encrypt", a, "-k", "pw"]).status.code(), Some(1));
+    assert!(run(&config, &["encrypt", a, "-k", "pw", "--force"]).status.success());
+    assert_eq!(run(&config, &["encrypt", a, "-k", "pw", "-f", "--on-conflict", "skip"]).status.code(), Some(2));
+}
+
+#[test]
+fn unknown_modes_are_rejected() {
+    let dir = setup();
+    let config = dir.path().join("config");
+    let a = dir.path().join("files/a.txt");
+    let a = a.to_str().unwrap();
+
+    let output = run(&config, &["encrypt", a, "-m", "chacha20-typo", "-k", "pw"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("chacha20-typo"));
+    assert!(!dir.path().join("files/a.txt.enc").exists());
+
+    assert!(run(&config, &["encrypt", a, "-m", "xchacha20", "-k", "pw"]).status.success());
+    let encrypted = dir.path().join("files/a.txt.enc");
+    let output = run(&config, &["verify", encrypted.to_str().unwrap(), "-m", "gcmm", "-k", "pw"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn keyed_names_use_a_salt_kept_with_the_tree() {
+    let dir = setup();
+    let config = dir.path().join("config");
+    let files = dir.path().join("files");
+    let (first, second) = (dir.path().join("first"), dir.path().join("second"));
+    let encrypted = |out: &Path| -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(out).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    };
+
+    for out in [&first, &second] {
+        let args = ["encrypt", files.to_str().unwrap(), "-r", "--hide-names=keyed", "-k", "pw", "-o", out.to_str().unwrap()];
+        assert!(run(&config, &args).status.success());
+    }
+    assert!(first.join(".fui-names").exists());
+    assert_ne!(encrypted(&first), encrypted(&second));
+
+    // Running again into the same tree reuses its salt, and the salt file
+    // itself is left alone
+    let again = run(&config, &["encrypt", files.to_str().unwrap(), "-r", "--hide-names=keyed", "-k", "pw",
+                               "-o", first.to_str().unwrap(), "--on-conflict", "skip"]);
+    assert!(again.status.success());
+    assert_eq!(encrypted(&first).len(), 3);
+    assert!(run(&config, &["decrypt", first.to_str().unwrap(), "-r", "-k", "pw"]).status.success());
+}
+
+#[test]
+fn keys_can_come_from_a_file_or_the_environment() {
+    let dir = setup();
+    let config = dir.path().join("config");
+    let a = dir.path().join("files/a.txt");
+    let encrypted = dir.path().join("files/a.txt.enc");
+    let key_file = dir.path().join("key");
+    let (a, encrypted, key_file_arg) = (a.to_str().unwrap(), encrypted.to_str().unwrap(), key_file.to_str().unwrap());
+
+    // The newline an editor leaves is not part of the key
+    fs::write(&key_file, "pw\n").unwrap();
+    assert!(run(&config, &["encrypt", a, "--key-file", key_file_arg]).status.success());
+    assert!(run(&config, &["verify", encrypted, "-k", "pw"]).status.success());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_file-utils-i"))
+        .args(["verify", encrypted, "--key-env", "FUI_TEST_KEY"])
+        .env("XDG_CONFIG_HOME", &config)
+        .env("FUI_TEST_KEY", "pw")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let output = run(&config, &["verify", encrypted, "--key-env", "FUI_TEST_UNSET_KEY"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    fs::write(&key_file, "").unwrap();
+    assert_eq!(run(&config, &["verify", encrypted, "--key-file", key_file_arg]).status.code(), Some(1));
+    let missing = dir.path().join("missing");
+    assert_eq!(run(&config, &["verify", encrypted, "--key-file", missing.to_str().unwrap()]).status.code(), Some(1));
+    assert_eq!(run(&config, &["verify", encrypted, "-k", "pw", "--key-file", key_file_arg]).status.code(), Some(2));
+}
+
+#[test]
+fn pipes_round_trip_with_status_on_stderr() {
+    let dir = setup();
+    let config = dir.path().join("config");
+    let piped = |args: &[&str], input: &[u8]| -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_file-utils-i"))
+            .args(args)
+            .env("XDG_CONFIG_HOME", &config)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let input = input.to_vec();
+        // A failing run may stop reading early, so a broken pipe is fine
+        let writer = std::thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+        let output = child.wait_with_output().unwrap();
+        writer.join().unwrap();
+        output
+    };
+    let plaintext: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+
+    let encrypted = piped(&["encrypt", "-", "-o", "-", "-k", "pw"], &plaintext);
+    assert!(encrypted.status.success());
+    assert!(String::from_utf8_lossy(&encrypted.stderr).contains("Encrypted stdin -> stdout"));
+    assert!(!encrypted.stdout.windows(b"Encrypted".len()).any(|w| w == b"Encrypted"));
+
+    // Only ciphertext reached stdout, so it reads back as a file too
+    let file = dir.path().join("piped.enc");
+    fs::write(&file, &encrypted.stdout).unwrap();
+    assert!(run(&config, &["verify", file.to_str().unwrap(), "-k", "pw"]).status.success());
+
+    let decrypted = piped(&["decrypt", "-", "-o", "-", "-k", "pw"], &encrypted.stdout);
+    assert!(decrypted.status.success());
+    assert_eq!(decrypted.stdout, plaintext);
+    assert!(String::from_utf8_lossy(&decrypted.stderr).contains("Decrypted stdin -> stdout"));
+
+    let wrong = piped(&["decrypt", "-", "-o", "-", "-k", "wrong"], &encrypted.stdout);
+    assert_eq!(wrong.status.code(), Some(1));
+    assert!(wrong.stdout.is_empty());
+}